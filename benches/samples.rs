@@ -0,0 +1,54 @@
+//! Times the sample programs on both engines.
+//!
+//! Programs get no input and what they print is discarded, so that
+//! only the report is shown.
+
+use plxml::Options;
+use std::fs;
+use std::io;
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 20;
+
+const HELLO_WORLD: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]\
+                           >>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+fn time(program: &str, args: &[&str], bytecode: bool) -> Duration {
+    let mut options = Options::new();
+    options
+        .bytecode(bytecode)
+        .args(args.iter().map(|a| String::from(*a)).collect());
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        // failing samples are timed as well, up to their error
+        let _ = options
+            .interpreter()
+            .input(io::empty())
+            .output(io::sink())
+            .run(program);
+    }
+    start.elapsed() / ITERATIONS
+}
+
+fn main() {
+    let mut samples: Vec<_> = fs::read_dir("sample")
+        .expect("sample directory")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    samples.sort();
+
+    println!("{:<20} {:>12} {:>12}", "sample", "tree", "bytecode");
+    for path in samples {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let args: &[&str] = match name.as_ref() {
+            "bf.pl.xml" => &[HELLO_WORLD],
+            // copies files around
+            "cp.pl.xml" => continue,
+            _ => &[],
+        };
+        let program = fs::read_to_string(&path).expect("sample program");
+        let tree = time(&program, args, false);
+        let bytecode = time(&program, args, true);
+        println!("{:<20} {:>12.2?} {:>12.2?}", name, tree, bytecode);
+    }
+}
@@ -0,0 +1,762 @@
+use super::error::ErrorKind::{self, InvalidValue};
+use super::instruction::{Instruction, InstructionKind, Target};
+use super::value::Function;
+use super::{Location, Name, Program, Value};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Where a variable may be found. Since variables only exist once
+/// assigned, a name is resolved to every scope that may hold it,
+/// innermost first, and the first one holding a value is used.
+#[derive(Clone, Copy, Debug)]
+pub enum Slot {
+    Local(usize),
+    /// Slot of the scope a given number of functions up.
+    Outer(usize, usize),
+    /// Variable of the interpreter, such as a top-level function.
+    Global,
+}
+
+#[derive(Debug)]
+pub struct Variable {
+    pub name: Name,
+    pub slots: Vec<Slot>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    /// Pushes a constant.
+    Push(usize),
+    /// Raises one of the errors known at compile time.
+    Fail(usize),
+    Load(usize),
+    Store(usize),
+    /// Updates a variable in the nearest enclosing scope defining it.
+    StoreOuter(usize),
+    /// Updates a variable of the top-level scope of the program.
+    StoreGlobal(usize),
+    Pop,
+    Array(usize),
+    /// Checks that the value on top of the stack is a valid map key.
+    Key,
+    Map(usize),
+    IntegerCast,
+    RealCast,
+    StringCast,
+    Add(usize),
+    Subtract(usize),
+    Multiply(usize),
+    Divide(usize),
+    Modulo(usize),
+    IntDivide(usize),
+    Power(usize),
+    Negate,
+    BitAnd(usize),
+    BitOr(usize),
+    BitXor(usize),
+    ShiftLeft(usize),
+    ShiftRight(usize),
+    And(usize),
+    Or(usize),
+    Not,
+    Equal,
+    Greater,
+    Lower,
+    /// Calls the function on top of the stack with a number of
+    /// arguments, reported under a given name in error traces.
+    Call(usize, usize),
+    Return,
+    Jump(usize),
+    JumpUnless(usize),
+    /// Jumps to a target unless the value on top of the stack is an
+    /// integer, dropping a number of values.
+    SkipUnlessInteger(usize, usize),
+    /// Enters the loop whose bounds are on top of the stack.
+    For(usize),
+    ForNext(usize, usize),
+    /// Enters the loop over the collection on top of the stack.
+    Each(usize),
+    EachNext(Option<usize>, usize, usize),
+    /// Enters an iteration of a while loop.
+    While(usize),
+    EndLoop,
+    Break(Option<usize>),
+    Continue(Option<usize>),
+    Try(usize),
+    /// Leaves a try or catch block for the finally block.
+    Finally(usize),
+    EndFinally,
+    Throw(Option<usize>),
+    Closure(usize),
+}
+
+#[derive(Debug)]
+pub struct Loop {
+    pub label: Option<String>,
+    /// Where iterations start over.
+    pub next: usize,
+    /// Where the loop is left.
+    pub end: usize,
+}
+
+#[derive(Debug)]
+pub struct Catch {
+    pub filter: Option<String>,
+    pub variable: Option<usize>,
+    pub target: usize,
+}
+
+#[derive(Debug)]
+pub struct Handler {
+    pub catches: Vec<Catch>,
+    pub finally: usize,
+}
+
+/// A compiled function body, or the main block.
+#[derive(Debug, Default)]
+pub struct Proto {
+    /// Slots receiving the arguments.
+    pub args: Vec<usize>,
+    pub slots: usize,
+    pub code: Vec<Op>,
+    /// Location of the instruction each operation comes from.
+    pub locations: Vec<Location>,
+    pub constants: Vec<Value>,
+    pub errors: Vec<ErrorKind>,
+    pub strings: Vec<String>,
+    pub variables: Vec<Variable>,
+    pub loops: Vec<Loop>,
+    pub handlers: Vec<Handler>,
+    pub protos: Vec<Rc<Proto>>,
+}
+
+/// A program compiled for the virtual machine.
+pub struct Module {
+    pub functions: Vec<(Name, Rc<Proto>)>,
+    pub main: Rc<Proto>,
+}
+
+pub fn compile(program: &Program) -> Module {
+    Module {
+        functions: program
+            .functions
+            .iter()
+            .map(|(name, function)| {
+                (
+                    name.clone(),
+                    Rc::new(Compiler::function(Vec::new(), function)),
+                )
+            })
+            .collect(),
+        main: Rc::new(Compiler::body(Vec::new(), &[], &program.main)),
+    }
+}
+
+struct Compiler {
+    /// Local slots of the enclosing functions, innermost last.
+    scopes: Vec<HashMap<Name, usize>>,
+    proto: Proto,
+}
+
+/// Whether an instruction gives a value when it completes.
+fn gives_value(ins: &Instruction) -> bool {
+    !matches!(
+        ins.kind,
+        InstructionKind::Assign(..)
+            | InstructionKind::If(..)
+            | InstructionKind::IfElse(..)
+            | InstructionKind::For { .. }
+            | InstructionKind::Each { .. }
+            | InstructionKind::While(..)
+            | InstructionKind::Handle { .. }
+            | InstructionKind::Function(..)
+    )
+}
+
+/// Collects the variables assigned by a block, excluding the ones
+/// of the functions it defines.
+fn declare(ins: &[Instruction], locals: &mut HashMap<Name, usize>) {
+    fn add(name: Name, locals: &mut HashMap<Name, usize>) {
+        let slot = locals.len();
+        locals.entry(name).or_insert(slot);
+    }
+
+    for i in ins {
+        match &i.kind {
+            InstructionKind::Assign(name, target, value) => {
+                if *target == Target::Local {
+                    add(name.clone(), locals);
+                }
+                declare(std::slice::from_ref(value), locals);
+            }
+            InstructionKind::Value(_)
+            | InstructionKind::Boolean(_)
+            | InstructionKind::Null
+            | InstructionKind::Integer(_)
+            | InstructionKind::Real(_)
+            | InstructionKind::String(_)
+            | InstructionKind::Break(_)
+            | InstructionKind::Continue(_)
+            | InstructionKind::Lambda(_) => {}
+            InstructionKind::IntegerCast(value)
+            | InstructionKind::RealCast(value)
+            | InstructionKind::StringCast(value)
+            | InstructionKind::Not(value)
+            | InstructionKind::Negate(value)
+            | InstructionKind::Return(value)
+            | InstructionKind::Throw(_, value) => declare(std::slice::from_ref(value), locals),
+            InstructionKind::Array(values)
+            | InstructionKind::Add(values)
+            | InstructionKind::Subtract(values)
+            | InstructionKind::Multiply(values)
+            | InstructionKind::Divide(values)
+            | InstructionKind::Modulo(values)
+            | InstructionKind::IntDivide(values)
+            | InstructionKind::Power(values)
+            | InstructionKind::BitAnd(values)
+            | InstructionKind::BitOr(values)
+            | InstructionKind::BitXor(values)
+            | InstructionKind::ShiftLeft(values)
+            | InstructionKind::ShiftRight(values)
+            | InstructionKind::And(values)
+            | InstructionKind::Or(values)
+            | InstructionKind::CallNamed(_, values) => declare(values, locals),
+            InstructionKind::Map(entries) => {
+                for (key, value) in entries {
+                    declare(std::slice::from_ref(key), locals);
+                    declare(std::slice::from_ref(value), locals);
+                }
+            }
+            InstructionKind::Equal(v1, v2)
+            | InstructionKind::Greater(v1, v2)
+            | InstructionKind::Lower(v1, v2) => {
+                declare(std::slice::from_ref(v1), locals);
+                declare(std::slice::from_ref(v2), locals);
+            }
+            InstructionKind::Call(function, args) => {
+                declare(args, locals);
+                declare(std::slice::from_ref(function), locals);
+            }
+            InstructionKind::If(cond, then) => {
+                declare(std::slice::from_ref(cond), locals);
+                declare(then, locals);
+            }
+            InstructionKind::IfElse(cond, then, els) => {
+                declare(std::slice::from_ref(cond), locals);
+                declare(then, locals);
+                declare(els, locals);
+            }
+            InstructionKind::For {
+                variable,
+                from,
+                to,
+                step,
+                body,
+                ..
+            } => {
+                declare(std::slice::from_ref(from), locals);
+                declare(std::slice::from_ref(to), locals);
+                declare(std::slice::from_ref(step), locals);
+                add(variable.clone(), locals);
+                declare(body, locals);
+            }
+            InstructionKind::Each {
+                variable,
+                key,
+                array,
+                body,
+                ..
+            } => {
+                declare(std::slice::from_ref(array), locals);
+                if let Some(key) = key {
+                    add(key.clone(), locals);
+                }
+                add(variable.clone(), locals);
+                declare(body, locals);
+            }
+            InstructionKind::While(cond, body, _) => {
+                declare(std::slice::from_ref(cond), locals);
+                declare(body, locals);
+            }
+            InstructionKind::Handle {
+                body,
+                catches,
+                finally,
+            } => {
+                declare(body, locals);
+                for catch in catches {
+                    if let Some(variable) = &catch.variable {
+                        add(variable.clone(), locals);
+                    }
+                    declare(&catch.body, locals);
+                }
+                declare(finally, locals);
+            }
+            InstructionKind::Function(name, _) => add(name.clone(), locals),
+        }
+    }
+}
+
+impl Compiler {
+    /// Compiles a function, given the local slots of the functions
+    /// it is defined in.
+    fn function(scopes: Vec<HashMap<Name, usize>>, function: &Function) -> Proto {
+        Compiler::body(scopes, &function.args, &function.ins)
+    }
+
+    fn body(scopes: Vec<HashMap<Name, usize>>, args: &[Name], ins: &[Instruction]) -> Proto {
+        let mut locals = HashMap::new();
+        for arg in args {
+            let slot = locals.len();
+            locals.entry(arg.clone()).or_insert(slot);
+        }
+        declare(ins, &mut locals);
+
+        let mut compiler = Compiler {
+            scopes,
+            proto: Proto::default(),
+        };
+        compiler.proto.args = args.iter().map(|a| locals[a]).collect();
+        compiler.proto.slots = locals.len();
+        compiler.scopes.push(locals);
+        compiler.block(ins);
+        let location = ins.last().map(|i| i.location.clone()).unwrap_or_default();
+        let null = compiler.constant(Value::Null);
+        compiler.emit(Op::Push(null), &location);
+        compiler.emit(Op::Return, &location);
+        compiler.proto
+    }
+
+    fn emit(&mut self, op: Op, location: &Location) -> usize {
+        self.proto.code.push(op);
+        self.proto.locations.push(location.clone());
+        self.proto.code.len() - 1
+    }
+
+    fn here(&self) -> usize {
+        self.proto.code.len()
+    }
+
+    /// Points a previously emitted jump to the current position.
+    fn patch(&mut self, at: usize) {
+        let here = self.here();
+        match &mut self.proto.code[at] {
+            Op::Jump(target)
+            | Op::JumpUnless(target)
+            | Op::SkipUnlessInteger(target, _)
+            | Op::ForNext(_, target)
+            | Op::EachNext(_, _, target) => *target = here,
+            _ => {}
+        }
+    }
+
+    fn constant(&mut self, value: Value) -> usize {
+        self.proto.constants.push(value);
+        self.proto.constants.len() - 1
+    }
+
+    fn error(&mut self, kind: ErrorKind) -> usize {
+        self.proto.errors.push(kind);
+        self.proto.errors.len() - 1
+    }
+
+    fn string(&mut self, s: &str) -> usize {
+        match self.proto.strings.iter().position(|x| x == s) {
+            Some(i) => i,
+            None => {
+                self.proto.strings.push(String::from(s));
+                self.proto.strings.len() - 1
+            }
+        }
+    }
+
+    fn local(&self, name: Name) -> usize {
+        self.scopes
+            .last()
+            .and_then(|s| s.get(&name))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn variable(&mut self, name: Name) -> usize {
+        let mut slots = Vec::new();
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(slot) = scope.get(&name) {
+                slots.push(if depth == 0 {
+                    Slot::Local(*slot)
+                } else {
+                    Slot::Outer(depth, *slot)
+                });
+            }
+        }
+        slots.push(Slot::Global);
+        self.proto.variables.push(Variable { name, slots });
+        self.proto.variables.len() - 1
+    }
+
+    fn block(&mut self, ins: &[Instruction]) {
+        for i in ins {
+            self.instruction(i);
+            if gives_value(i) {
+                self.emit(Op::Pop, &i.location);
+            }
+        }
+    }
+
+    /// Compiles an instruction whose value is needed by another one,
+    /// which fails if it gives none. Tells whether there is a value.
+    fn value(&mut self, ins: &Instruction, tag: &'static str, location: &Location) -> bool {
+        self.instruction(ins);
+        if !gives_value(ins) {
+            let error = self.error(InvalidValue(tag));
+            self.emit(Op::Fail(error), location);
+        }
+        gives_value(ins)
+    }
+
+    fn values(&mut self, ins: &[Instruction], tag: &'static str, location: &Location) -> bool {
+        ins.iter().all(|i| self.value(i, tag, location))
+    }
+
+    fn instruction(&mut self, ins: &Instruction) {
+        let location = &ins.location;
+        match &ins.kind {
+            InstructionKind::Value(name) => {
+                let variable = self.variable(name.clone());
+                self.emit(Op::Load(variable), location);
+            }
+            InstructionKind::Assign(name, target, value) => {
+                if self.value(value, "assign", location) {
+                    let op = match target {
+                        Target::Local => Op::Store(self.local(name.clone())),
+                        Target::Outer => Op::StoreOuter(self.variable(name.clone())),
+                        Target::Global => Op::StoreGlobal(self.variable(name.clone())),
+                    };
+                    self.emit(op, location);
+                }
+            }
+            InstructionKind::Boolean(b) => {
+                let constant = self.constant(Value::Boolean(*b));
+                self.emit(Op::Push(constant), location);
+            }
+            InstructionKind::Null => {
+                let constant = self.constant(Value::Null);
+                self.emit(Op::Push(constant), location);
+            }
+            InstructionKind::Integer(i) => {
+                let constant = self.constant(Value::Integer(*i));
+                self.emit(Op::Push(constant), location);
+            }
+            InstructionKind::Real(f) => {
+                let constant = self.constant(Value::Real(*f));
+                self.emit(Op::Push(constant), location);
+            }
+            InstructionKind::String(val) => {
+                let constant = self.constant(Value::String(val.clone()));
+                self.emit(Op::Push(constant), location);
+            }
+            InstructionKind::IntegerCast(value) => {
+                if self.value(value, "integer", location) {
+                    self.emit(Op::IntegerCast, location);
+                }
+            }
+            InstructionKind::RealCast(value) => {
+                if self.value(value, "real", location) {
+                    self.emit(Op::RealCast, location);
+                }
+            }
+            InstructionKind::StringCast(value) => {
+                if self.value(value, "string", location) {
+                    self.emit(Op::StringCast, location);
+                }
+            }
+            InstructionKind::Array(values) => {
+                if self.values(values, "array", location) {
+                    self.emit(Op::Array(values.len()), location);
+                }
+            }
+            InstructionKind::Map(entries) => {
+                for (key, value) in entries {
+                    if !self.value(key, "entry", location) {
+                        return;
+                    }
+                    self.emit(Op::Key, location);
+                    if !self.value(value, "entry", location) {
+                        return;
+                    }
+                }
+                self.emit(Op::Map(entries.len()), location);
+            }
+            InstructionKind::Add(values) => {
+                if self.values(values, "add", location) {
+                    self.emit(Op::Add(values.len()), location);
+                }
+            }
+            InstructionKind::Subtract(values) => {
+                if self.values(values, "subtract", location) {
+                    self.emit(Op::Subtract(values.len()), location);
+                }
+            }
+            InstructionKind::Multiply(values) => {
+                if self.values(values, "multiply", location) {
+                    self.emit(Op::Multiply(values.len()), location);
+                }
+            }
+            InstructionKind::Divide(values) => {
+                if self.values(values, "divide", location) {
+                    self.emit(Op::Divide(values.len()), location);
+                }
+            }
+            InstructionKind::Modulo(values) => {
+                if self.values(values, "modulo", location) {
+                    self.emit(Op::Modulo(values.len()), location);
+                }
+            }
+            InstructionKind::IntDivide(values) => {
+                if self.values(values, "int-divide", location) {
+                    self.emit(Op::IntDivide(values.len()), location);
+                }
+            }
+            InstructionKind::Power(values) => {
+                if self.values(values, "power", location) {
+                    self.emit(Op::Power(values.len()), location);
+                }
+            }
+            InstructionKind::Negate(value) => {
+                if self.value(value, "negate", location) {
+                    self.emit(Op::Negate, location);
+                }
+            }
+            InstructionKind::BitAnd(values) => {
+                if self.values(values, "bit-and", location) {
+                    self.emit(Op::BitAnd(values.len()), location);
+                }
+            }
+            InstructionKind::BitOr(values) => {
+                if self.values(values, "bit-or", location) {
+                    self.emit(Op::BitOr(values.len()), location);
+                }
+            }
+            InstructionKind::BitXor(values) => {
+                if self.values(values, "bit-xor", location) {
+                    self.emit(Op::BitXor(values.len()), location);
+                }
+            }
+            InstructionKind::ShiftLeft(values) => {
+                if self.values(values, "shift-left", location) {
+                    self.emit(Op::ShiftLeft(values.len()), location);
+                }
+            }
+            InstructionKind::ShiftRight(values) => {
+                if self.values(values, "shift-right", location) {
+                    self.emit(Op::ShiftRight(values.len()), location);
+                }
+            }
+            InstructionKind::And(values) => {
+                if self.values(values, "and", location) {
+                    self.emit(Op::And(values.len()), location);
+                }
+            }
+            InstructionKind::Or(values) => {
+                if self.values(values, "or", location) {
+                    self.emit(Op::Or(values.len()), location);
+                }
+            }
+            InstructionKind::Not(value) => {
+                if self.value(value, "not", location) {
+                    self.emit(Op::Not, location);
+                }
+            }
+            InstructionKind::Equal(v1, v2) => {
+                if self.value(v1, "equal", location) && self.value(v2, "equal", location) {
+                    self.emit(Op::Equal, location);
+                }
+            }
+            InstructionKind::Greater(v1, v2) => {
+                if self.value(v1, "greater", location) && self.value(v2, "greater", location) {
+                    self.emit(Op::Greater, location);
+                }
+            }
+            InstructionKind::Lower(v1, v2) => {
+                if self.value(v1, "lower", location) && self.value(v2, "lower", location) {
+                    self.emit(Op::Lower, location);
+                }
+            }
+            InstructionKind::Call(function, args) => {
+                if self.values(args, "call", location) && self.value(function, "call", location) {
+                    let name = self.string(&function.describe());
+                    self.emit(Op::Call(args.len(), name), location);
+                }
+            }
+            InstructionKind::CallNamed(function, args) => {
+                if self.values(args, "call", location) {
+                    let variable = self.variable(function.clone());
+                    self.emit(Op::Load(variable), location);
+                    let name = self.string(function);
+                    self.emit(Op::Call(args.len(), name), location);
+                }
+            }
+            InstructionKind::Return(value) => {
+                if self.value(value, "return", location) {
+                    self.emit(Op::Return, location);
+                }
+            }
+            InstructionKind::If(cond, then) => {
+                if self.value(cond, "if", location) {
+                    let jump = self.emit(Op::JumpUnless(0), location);
+                    self.block(then);
+                    self.patch(jump);
+                }
+            }
+            InstructionKind::IfElse(cond, then, els) => {
+                if self.value(cond, "if", location) {
+                    let jump = self.emit(Op::JumpUnless(0), location);
+                    self.block(then);
+                    let end = self.emit(Op::Jump(0), location);
+                    self.patch(jump);
+                    self.block(els);
+                    self.patch(end);
+                }
+            }
+            InstructionKind::For {
+                variable,
+                from,
+                to,
+                step,
+                body,
+                label,
+            } => {
+                let mut skips = Vec::new();
+                for (count, bound) in [from, to, step].into_iter().enumerate() {
+                    if !self.value(bound, "for", location) {
+                        return;
+                    }
+                    skips.push(self.emit(Op::SkipUnlessInteger(0, count + 1), location));
+                }
+                let info = self.proto.loops.len();
+                self.proto.loops.push(Loop {
+                    label: label.clone(),
+                    next: 0,
+                    end: 0,
+                });
+                self.emit(Op::For(info), location);
+                let next = self.emit(Op::ForNext(self.local(variable.clone()), 0), location);
+                self.block(body);
+                self.emit(Op::Jump(next), location);
+                self.patch(next);
+                let end = self.emit(Op::EndLoop, location);
+                self.proto.loops[info].next = next;
+                self.proto.loops[info].end = end;
+                for skip in skips {
+                    self.patch(skip);
+                }
+            }
+            InstructionKind::Each {
+                variable,
+                key,
+                array,
+                body,
+                label,
+            } => {
+                if !self.value(array, "each", location) {
+                    return;
+                }
+                let info = self.proto.loops.len();
+                self.proto.loops.push(Loop {
+                    label: label.clone(),
+                    next: 0,
+                    end: 0,
+                });
+                self.emit(Op::Each(info), location);
+                let key = key.as_ref().map(|k| self.local(k.clone()));
+                let next = self.emit(Op::EachNext(key, self.local(variable.clone()), 0), location);
+                self.block(body);
+                self.emit(Op::Jump(next), location);
+                self.patch(next);
+                let end = self.emit(Op::EndLoop, location);
+                self.proto.loops[info].next = next;
+                self.proto.loops[info].end = end;
+            }
+            InstructionKind::While(cond, body, label) => {
+                // the loop is entered for each iteration only, so that
+                // the condition is evaluated outside of it
+                let start = self.here();
+                if !self.value(cond, "while", location) {
+                    return;
+                }
+                let exit = self.emit(Op::JumpUnless(0), location);
+                let info = self.proto.loops.len();
+                self.proto.loops.push(Loop {
+                    label: label.clone(),
+                    next: 0,
+                    end: 0,
+                });
+                self.emit(Op::While(info), location);
+                self.block(body);
+                let next = self.emit(Op::EndLoop, location);
+                self.emit(Op::Jump(start), location);
+                let end = self.emit(Op::EndLoop, location);
+                self.patch(exit);
+                self.proto.loops[info].next = next;
+                self.proto.loops[info].end = end;
+            }
+            InstructionKind::Break(label) => {
+                let label = label.as_ref().map(|l| self.string(l));
+                self.emit(Op::Break(label), location);
+            }
+            InstructionKind::Continue(label) => {
+                let label = label.as_ref().map(|l| self.string(l));
+                self.emit(Op::Continue(label), location);
+            }
+            InstructionKind::Handle {
+                body,
+                catches,
+                finally,
+            } => {
+                let info = self.proto.handlers.len();
+                self.proto.handlers.push(Handler {
+                    catches: Vec::new(),
+                    finally: 0,
+                });
+                self.emit(Op::Try(info), location);
+                self.block(body);
+                self.emit(Op::Finally(info), location);
+                for catch in catches {
+                    let handler = Catch {
+                        filter: catch.filter.clone(),
+                        variable: catch.variable.as_ref().map(|v| self.local(v.clone())),
+                        target: self.here(),
+                    };
+                    self.proto.handlers[info].catches.push(handler);
+                    self.block(&catch.body);
+                    self.emit(Op::Finally(info), location);
+                }
+                self.proto.handlers[info].finally = self.here();
+                self.block(finally);
+                self.emit(Op::EndFinally, location);
+            }
+            InstructionKind::Throw(tag, value) => {
+                if self.value(value, "throw", location) {
+                    let tag = tag.as_ref().map(|t| self.string(t));
+                    self.emit(Op::Throw(tag), location);
+                }
+            }
+            InstructionKind::Function(name, function) => {
+                let proto = self.nested(function);
+                self.emit(Op::Closure(proto), location);
+                let slot = self.local(name.clone());
+                self.emit(Op::Store(slot), location);
+            }
+            InstructionKind::Lambda(function) => {
+                let proto = self.nested(function);
+                self.emit(Op::Closure(proto), location);
+            }
+        }
+    }
+
+    fn nested(&mut self, function: &Function) -> usize {
+        let proto = Compiler::function(self.scopes.clone(), function);
+        self.proto.protos.push(Rc::new(proto));
+        self.proto.protos.len() - 1
+    }
+}
@@ -0,0 +1,423 @@
+use super::instruction::{Instruction, InstructionKind, Target};
+use super::value::Function;
+use super::{stl, Location, Name, Program};
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    UndefinedName(String),
+    ArityMismatch(String, usize, usize),
+    Unreachable,
+    UnusedVariable(String),
+}
+
+impl DiagnosticKind {
+    pub fn code(&self) -> &'static str {
+        match self {
+            DiagnosticKind::UndefinedName(_) => "W001",
+            DiagnosticKind::ArityMismatch(..) => "W002",
+            DiagnosticKind::Unreachable => "W003",
+            DiagnosticKind::UnusedVariable(_) => "W004",
+        }
+    }
+}
+
+impl fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiagnosticKind::UndefinedName(name) => write!(f, "undefined name '{}'", name),
+            DiagnosticKind::ArityMismatch(function, count, expected) => write!(
+                f,
+                "bad argument count ({}, expected {}) in call to '{}'",
+                count, expected, function
+            ),
+            DiagnosticKind::Unreachable => write!(f, "unreachable instruction"),
+            DiagnosticKind::UnusedVariable(name) => write!(f, "unused variable '{}'", name),
+        }
+    }
+}
+
+/// A problem found in a program without running it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub location: Location,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.kind, self.location)
+    }
+}
+
+struct Binding {
+    /// Number of arguments, if the name is only ever bound to a function.
+    arity: Option<usize>,
+    /// First assignment, for names that are reported when unused.
+    assigned: Option<Location>,
+    used: bool,
+}
+
+/// Use of a name, optionally as a function called with some arguments.
+struct Reference {
+    name: String,
+    arguments: Option<usize>,
+    location: Location,
+}
+
+/// Names bound in a function body, or at the top level of the program.
+///
+/// As with the interpreter, a name assigned anywhere in a body belongs
+/// to that body, so references are only resolved once it has been
+/// entirely walked.
+#[derive(Default)]
+struct Scope {
+    bindings: HashMap<String, Binding>,
+    references: Vec<Reference>,
+}
+
+impl Scope {
+    fn declare(&mut self, name: &str, arity: Option<usize>, assigned: Option<&Location>) {
+        match self.bindings.get_mut(name) {
+            Some(binding) => {
+                if binding.arity != arity {
+                    binding.arity = None;
+                }
+                if binding.assigned.is_none() {
+                    binding.assigned = assigned.cloned();
+                }
+            }
+            None => {
+                self.bindings.insert(
+                    String::from(name),
+                    Binding {
+                        arity,
+                        assigned: assigned.cloned(),
+                        used: false,
+                    },
+                );
+            }
+        }
+    }
+}
+
+pub struct Checker {
+    scopes: Vec<Scope>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    /// Checks a program, given the names it imports along with the
+    /// number of arguments of the functions they are bound to.
+    pub fn check(program: &Program, imported: &[(Name, usize)]) -> Vec<Diagnostic> {
+        let mut global = Scope::default();
+        for (name, _, arity) in stl::FUNCTIONS {
+            global.declare(name, Some(*arity), None);
+        }
+        for (name, arity) in imported {
+            global.declare(name, Some(*arity), None);
+        }
+        for (name, function) in &program.functions {
+            global.declare(name, Some(function.args.len()), None);
+        }
+        for variable in &program.globals {
+            let arity = match &variable.value.kind {
+                InstructionKind::Lambda(function) => Some(function.args.len()),
+                _ => None,
+            };
+            global.declare(&variable.name, arity, None);
+        }
+
+        let mut checker = Checker {
+            scopes: vec![global],
+            diagnostics: Vec::new(),
+        };
+        for variable in &program.globals {
+            checker.instruction(&variable.value);
+        }
+        for (_, function) in &program.functions {
+            checker.function(function);
+        }
+        checker.scopes.push(Scope::default());
+        checker.block(&program.main);
+        checker.leave();
+
+        let global = checker.scopes.pop().unwrap_or_default();
+        for reference in global.references {
+            match global.bindings.get(&reference.name) {
+                Some(binding) => checker.call(binding, reference),
+                None => checker.report(
+                    DiagnosticKind::UndefinedName(reference.name),
+                    &reference.location,
+                ),
+            }
+        }
+
+        let mut diagnostics = checker.diagnostics;
+        diagnostics.sort_by_key(|d| d.location.range.start);
+        diagnostics
+    }
+
+    fn report(&mut self, kind: DiagnosticKind, location: &Location) {
+        self.diagnostics.push(Diagnostic {
+            kind,
+            location: location.clone(),
+        });
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().expect("checker scope")
+    }
+
+    fn declare(&mut self, name: &str, arity: Option<usize>, assigned: Option<&Location>) {
+        self.scope().declare(name, arity, assigned);
+    }
+
+    fn refer(&mut self, name: &str, arguments: Option<usize>, location: &Location) {
+        self.scope().references.push(Reference {
+            name: String::from(name),
+            arguments,
+            location: location.clone(),
+        });
+    }
+
+    /// Refers to a name assigned to in an enclosing scope, which is
+    /// resolved from the scope it updates rather than the current one.
+    fn update(&mut self, name: &str, target: Target, location: &Location) {
+        let depth = match target {
+            Target::Global => Some(0),
+            _ => self.scopes.len().checked_sub(2),
+        };
+        match depth {
+            Some(depth) => self.scopes[depth].references.push(Reference {
+                name: String::from(name),
+                arguments: None,
+                location: location.clone(),
+            }),
+            None => self.report(DiagnosticKind::UndefinedName(String::from(name)), location),
+        }
+    }
+
+    fn call(&mut self, binding: &Binding, reference: Reference) {
+        if let (Some(count), Some(expected)) = (reference.arguments, binding.arity) {
+            if count != expected {
+                self.report(
+                    DiagnosticKind::ArityMismatch(reference.name, count, expected),
+                    &reference.location,
+                );
+            }
+        }
+    }
+
+    /// Closes the innermost scope, handing the references it cannot
+    /// resolve over to the enclosing one.
+    fn leave(&mut self) {
+        let mut scope = self.scopes.pop().expect("checker scope");
+        for reference in scope.references.drain(..) {
+            match scope.bindings.get_mut(&reference.name) {
+                Some(binding) => {
+                    binding.used = true;
+                    self.call(binding, reference);
+                }
+                None => self.scope().references.push(reference),
+            }
+        }
+        let mut unused: Vec<(String, Location)> = scope
+            .bindings
+            .into_iter()
+            .filter(|(_, b)| !b.used)
+            .filter_map(|(name, b)| b.assigned.map(|l| (name, l)))
+            .collect();
+        unused.sort_by_key(|(_, l)| l.range.start);
+        for (name, location) in unused {
+            self.report(DiagnosticKind::UnusedVariable(name), &location);
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.scopes.push(Scope::default());
+        for arg in &function.args {
+            self.declare(arg, None, None);
+        }
+        self.block(&function.ins);
+        self.leave();
+    }
+
+    /// Whether control never reaches the instruction following `ins`.
+    fn diverges(ins: &Instruction) -> bool {
+        match &ins.kind {
+            InstructionKind::Return(_)
+            | InstructionKind::Break(_)
+            | InstructionKind::Continue(_)
+            | InstructionKind::Throw(..) => true,
+            InstructionKind::IfElse(_, then, els) => {
+                then.iter().any(Checker::diverges) && els.iter().any(Checker::diverges)
+            }
+            InstructionKind::Handle { finally, .. } => finally.iter().any(Checker::diverges),
+            _ => false,
+        }
+    }
+
+    fn block(&mut self, ins: &[Instruction]) {
+        let mut reported = false;
+        for (i, instruction) in ins.iter().enumerate() {
+            if !reported && i > 0 && Checker::diverges(&ins[i - 1]) {
+                self.report(DiagnosticKind::Unreachable, &instruction.location);
+                reported = true;
+            }
+            self.instruction(instruction);
+        }
+    }
+
+    fn all(&mut self, ins: &[Instruction]) {
+        for i in ins {
+            self.instruction(i);
+        }
+    }
+
+    fn instruction(&mut self, ins: &Instruction) {
+        match &ins.kind {
+            InstructionKind::Value(name) => self.refer(name, None, &ins.location),
+            InstructionKind::Assign(name, target @ (Target::Outer | Target::Global), value) => {
+                self.update(name, *target, &ins.location);
+                self.instruction(value);
+            }
+            InstructionKind::Assign(name, Target::Local, value) => {
+                let arity = match &value.kind {
+                    InstructionKind::Lambda(function) => Some(function.args.len()),
+                    _ => None,
+                };
+                self.declare(name, arity, Some(&ins.location));
+                self.instruction(value);
+            }
+            InstructionKind::Boolean(_)
+            | InstructionKind::Null
+            | InstructionKind::Integer(_)
+            | InstructionKind::Real(_)
+            | InstructionKind::String(_)
+            | InstructionKind::Break(_)
+            | InstructionKind::Continue(_) => {}
+            InstructionKind::IntegerCast(value)
+            | InstructionKind::RealCast(value)
+            | InstructionKind::StringCast(value)
+            | InstructionKind::Not(value)
+            | InstructionKind::Negate(value)
+            | InstructionKind::Return(value)
+            | InstructionKind::Throw(_, value) => self.instruction(value),
+            InstructionKind::Array(values)
+            | InstructionKind::Add(values)
+            | InstructionKind::Subtract(values)
+            | InstructionKind::Multiply(values)
+            | InstructionKind::Divide(values)
+            | InstructionKind::Modulo(values)
+            | InstructionKind::IntDivide(values)
+            | InstructionKind::Power(values)
+            | InstructionKind::BitAnd(values)
+            | InstructionKind::BitOr(values)
+            | InstructionKind::BitXor(values)
+            | InstructionKind::ShiftLeft(values)
+            | InstructionKind::ShiftRight(values)
+            | InstructionKind::And(values)
+            | InstructionKind::Or(values) => self.all(values),
+            InstructionKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.instruction(key);
+                    self.instruction(value);
+                }
+            }
+            InstructionKind::Equal(v1, v2)
+            | InstructionKind::Greater(v1, v2)
+            | InstructionKind::Lower(v1, v2) => {
+                self.instruction(v1);
+                self.instruction(v2);
+            }
+            InstructionKind::Call(function, args) => {
+                self.all(args);
+                match &function.kind {
+                    InstructionKind::Value(name) => {
+                        self.refer(name, Some(args.len()), &function.location)
+                    }
+                    InstructionKind::Lambda(lambda) => {
+                        if lambda.args.len() != args.len() {
+                            self.report(
+                                DiagnosticKind::ArityMismatch(
+                                    String::from("<anonymous>"),
+                                    args.len(),
+                                    lambda.args.len(),
+                                ),
+                                &ins.location,
+                            );
+                        }
+                        self.instruction(function);
+                    }
+                    _ => self.instruction(function),
+                }
+            }
+            InstructionKind::CallNamed(name, args) => {
+                self.all(args);
+                self.refer(name, Some(args.len()), &ins.location);
+            }
+            InstructionKind::If(cond, then) => {
+                self.instruction(cond);
+                self.block(then);
+            }
+            InstructionKind::IfElse(cond, then, els) => {
+                self.instruction(cond);
+                self.block(then);
+                self.block(els);
+            }
+            InstructionKind::For {
+                variable,
+                from,
+                to,
+                step,
+                body,
+                ..
+            } => {
+                self.instruction(from);
+                self.instruction(to);
+                self.instruction(step);
+                self.declare(variable, None, None);
+                self.block(body);
+            }
+            InstructionKind::Each {
+                variable,
+                key,
+                array,
+                body,
+                ..
+            } => {
+                self.instruction(array);
+                if let Some(key) = key {
+                    self.declare(key, None, None);
+                }
+                self.declare(variable, None, None);
+                self.block(body);
+            }
+            InstructionKind::While(cond, body, _) => {
+                self.instruction(cond);
+                self.block(body);
+            }
+            InstructionKind::Handle {
+                body,
+                catches,
+                finally,
+            } => {
+                self.block(body);
+                for catch in catches {
+                    if let Some(variable) = &catch.variable {
+                        self.declare(variable, None, None);
+                    }
+                    self.block(&catch.body);
+                }
+                self.block(finally);
+            }
+            InstructionKind::Function(name, function) => {
+                self.declare(name, Some(function.args.len()), Some(&ins.location));
+                self.function(function);
+            }
+            InstructionKind::Lambda(function) => self.function(function),
+        }
+    }
+}
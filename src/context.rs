@@ -1,31 +1,189 @@
-use super::value::Value;
+use super::error::{
+    Error,
+    ErrorKind::{ConstantAssignment, UnknownVariable},
+};
+use super::name::Name;
+use super::runtime::Runtime;
+use super::value::{Closure, Function, Value};
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
 
+/// A variable scope, shared by reference so that functions
+/// can capture the scope they were defined in.
 #[derive(Clone)]
-pub struct Context<'par> {
-    dict: HashMap<String, Value>,
-    parent: Option<&'par Context<'par>>,
+pub struct Context(Rc<Scope>);
+
+struct Scope {
+    dict: RefCell<HashMap<Name, Binding>>,
+    parent: Option<Context>,
+    runtime: Rc<Runtime>,
+    /// Whether this is the top-level scope of a program.
+    program: bool,
+}
+
+/// Value of a variable, as kept in its scope.
+enum Binding {
+    Value(Value),
+    /// Function defined in the very scope it is kept in, which is only
+    /// attached to it once read, as they would keep each other alive.
+    Function(Rc<Function>),
+    /// Value of a constant, which may only be declared again.
+    Constant(Value),
 }
 
-impl<'par> Context<'par> {
-    pub fn new(parent: Option<&'par Context<'par>>) -> Context<'par> {
-        Context {
-            dict: HashMap::new(),
-            parent,
+impl Context {
+    /// Creates a scope, sharing the runtime of its parent if any.
+    pub fn new(parent: Option<&Context>) -> Context {
+        Context(Rc::new(Scope {
+            dict: RefCell::new(HashMap::new()),
+            parent: parent.cloned(),
+            runtime: parent.map_or_else(Rc::default, |p| Rc::clone(&p.0.runtime)),
+            program: false,
+        }))
+    }
+
+    /// Creates the top-level scope of a program other than the first
+    /// one, such as an imported one.
+    pub fn program(parent: &Context) -> Context {
+        Context(Rc::new(Scope {
+            dict: RefCell::new(HashMap::new()),
+            parent: Some(parent.clone()),
+            runtime: Rc::clone(&parent.0.runtime),
+            program: true,
+        }))
+    }
+
+    pub fn root(runtime: Runtime) -> Context {
+        Context(Rc::new(Scope {
+            dict: RefCell::new(HashMap::new()),
+            parent: None,
+            runtime: Rc::new(runtime),
+            program: true,
+        }))
+    }
+
+    pub fn runtime(&self) -> &Runtime {
+        &self.0.runtime
+    }
+
+    pub fn assign(&mut self, key: &Name, value: Value) -> Result<(), Error> {
+        let binding = self.bind(value);
+        let mut dict = self.0.dict.borrow_mut();
+        match dict.get_mut(key) {
+            Some(variable) => Context::replace(key, variable, binding)?,
+            None => {
+                dict.insert(key.clone(), binding);
+            }
         }
+        Ok(())
     }
 
-    pub fn assign(&mut self, key: String, value: Value) {
-        self.dict.insert(key, value);
+    /// Declares a constant, which may replace any variable of this
+    /// scope, including a constant declared before.
+    pub fn constant(&mut self, key: &Name, value: Value) {
+        self.0
+            .dict
+            .borrow_mut()
+            .insert(key.clone(), Binding::Constant(value));
     }
 
-    pub fn value(&self, key: &String) -> Option<&Value> {
-        self.dict
-            .get(key)
-            .or(self.parent.as_ref().and_then(|p| p.value(key)))
+    /// Updates a variable in the nearest enclosing scope defining it.
+    pub fn assign_outer(&self, key: &Name, value: Value) -> Result<(), Error> {
+        match &self.0.parent {
+            Some(parent) => parent.update(key, value),
+            None => Err(UnknownVariable(key.to_string()))?,
+        }
     }
 
-    pub fn take(&mut self, key: &String) -> Option<Value> {
-        self.dict.remove(key)
+    /// Updates a variable of the top-level scope of the program.
+    pub fn assign_global(&self, key: &Name, value: Value) -> Result<(), Error> {
+        if !self.0.program {
+            return match &self.0.parent {
+                Some(parent) => parent.assign_global(key, value),
+                None => Err(UnknownVariable(key.to_string()))?,
+            };
+        }
+        match self.0.dict.borrow_mut().get_mut(key) {
+            Some(variable) => Context::replace(key, variable, self.bind(value)),
+            None => Err(UnknownVariable(key.to_string()))?,
+        }
+    }
+
+    /// Updates a variable in this scope or the nearest enclosing one
+    /// defining it.
+    pub fn update(&self, key: &Name, value: Value) -> Result<(), Error> {
+        if let Some(variable) = self.0.dict.borrow_mut().get_mut(key) {
+            return Context::replace(key, variable, self.bind(value));
+        }
+        match &self.0.parent {
+            Some(parent) => parent.update(key, value),
+            None => Err(UnknownVariable(key.to_string()))?,
+        }
+    }
+
+    pub fn value(&self, key: &Name) -> Option<Value> {
+        match self.0.dict.borrow().get(key) {
+            Some(binding) => Some(self.unbind(binding)),
+            None => self.0.parent.as_ref().and_then(|p| p.value(key)),
+        }
+    }
+
+    /// Keeps a value in this scope, leaving out the scope itself from
+    /// functions defined in it.
+    fn bind(&self, value: Value) -> Binding {
+        match value {
+            Value::Function(closure) if Rc::ptr_eq(&closure.env.0, &self.0) => {
+                Binding::Function(closure.function)
+            }
+            value => Binding::Value(value),
+        }
+    }
+
+    /// Sets a variable again, unless it is a constant.
+    fn replace(key: &Name, variable: &mut Binding, binding: Binding) -> Result<(), Error> {
+        if let Binding::Constant(_) = variable {
+            Err(ConstantAssignment(key.to_string()))?
+        }
+        *variable = binding;
+        Ok(())
+    }
+
+    fn unbind(&self, binding: &Binding) -> Value {
+        match binding {
+            Binding::Value(value) | Binding::Constant(value) => value.clone(),
+            Binding::Function(function) => Value::Function(Closure {
+                function: Rc::clone(function),
+                env: self.clone(),
+            }),
+        }
+    }
+
+    /// Forgets the variables of this scope. Functions stored in the
+    /// scopes they can see, such as compiled ones in the scope of the
+    /// interpreter, keep it alive until it is cleared.
+    pub fn clear(&self) {
+        drop(self.0.dict.take());
+    }
+
+    /// Variables of this scope only, sorted by name.
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let mut variables: Vec<(String, Value)> = self
+            .0
+            .dict
+            .borrow()
+            .iter()
+            .map(|(k, v)| (k.to_string(), self.unbind(v)))
+            .collect();
+        variables.sort_by(|(k1, _), (k2, _)| k1.cmp(k2));
+        variables
+    }
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // scopes may contain closures referring back to them
+        write!(f, "Context {{ .. }}")
     }
 }
@@ -0,0 +1,258 @@
+use super::format::escape;
+use super::instruction::{Instruction, InstructionKind};
+use super::value::Function;
+use super::Program;
+use std::fmt::Write;
+
+/// Writes a parsed program back as XML, such as after optimizing it.
+///
+/// The output is compact, and meant to be given to the formatter.
+pub fn dump(program: &Program) -> String {
+    let mut s = String::new();
+    s.push_str("<program");
+    attribute(&mut s, "name", program.name.as_deref());
+    s.push('>');
+    for import in &program.imports {
+        s.push_str("<import");
+        attribute(&mut s, "src", Some(&import.src));
+        attribute(&mut s, "as", import.prefix.as_deref());
+        s.push_str(" />");
+    }
+    for global in &program.globals {
+        let tag = if global.constant { "const" } else { "global" };
+        let _ = write!(s, "<{}", tag);
+        attribute(&mut s, "name", Some(&global.name));
+        s.push('>');
+        instruction(&mut s, &global.value);
+        let _ = write!(s, "</{}>", tag);
+    }
+    for (name, function) in &program.functions {
+        s.push_str("<function");
+        attribute(&mut s, "name", Some(name));
+        s.push('>');
+        signature(&mut s, function);
+        s.push_str("</function>");
+    }
+    element(&mut s, "main", &program.main);
+    s.push_str("</program>");
+    s
+}
+
+fn attribute(s: &mut String, name: &str, value: Option<&str>) {
+    if let Some(value) = value {
+        let _ = write!(s, " {}=\"{}\"", name, escape(value));
+    }
+}
+
+/// Writes an element holding a sequence of instructions.
+fn element(s: &mut String, tag: &str, ins: &[Instruction]) {
+    let _ = write!(s, "<{}>", tag);
+    for i in ins {
+        instruction(s, i);
+    }
+    let _ = write!(s, "</{}>", tag);
+}
+
+fn binary(s: &mut String, tag: &str, v1: &Instruction, v2: &Instruction) {
+    let _ = write!(s, "<{}>", tag);
+    instruction(s, v1);
+    instruction(s, v2);
+    let _ = write!(s, "</{}>", tag);
+}
+
+fn signature(s: &mut String, function: &Function) {
+    s.push_str("<arguments>");
+    for arg in &function.args {
+        s.push_str("<argument");
+        attribute(s, "name", Some(arg));
+        s.push_str(" />");
+    }
+    s.push_str("</arguments>");
+    element(s, "body", &function.ins);
+}
+
+fn instruction(s: &mut String, ins: &Instruction) {
+    match &ins.kind {
+        InstructionKind::Value(name) => {
+            s.push_str("<value");
+            attribute(s, "variable", Some(name));
+            s.push_str(" />");
+        }
+        InstructionKind::Assign(name, target, value) => {
+            s.push_str("<assign");
+            attribute(s, "variable", Some(name));
+            attribute(s, "scope", target.name());
+            s.push('>');
+            instruction(s, value);
+            s.push_str("</assign>");
+        }
+        InstructionKind::Boolean(true) => s.push_str("<true />"),
+        InstructionKind::Boolean(false) => s.push_str("<false />"),
+        InstructionKind::Null => s.push_str("<null />"),
+        InstructionKind::Integer(i) => {
+            let _ = write!(s, "<integer value=\"{}\" />", i);
+        }
+        InstructionKind::Real(f) => {
+            let _ = write!(s, "<real value=\"{}\" />", f);
+        }
+        InstructionKind::String(val) => {
+            s.push_str("<string");
+            attribute(s, "value", Some(val));
+            s.push_str(" />");
+        }
+        InstructionKind::IntegerCast(value) => element(s, "integer", std::slice::from_ref(value)),
+        InstructionKind::RealCast(value) => element(s, "real", std::slice::from_ref(value)),
+        InstructionKind::StringCast(value) => element(s, "string", std::slice::from_ref(value)),
+        InstructionKind::Array(values) => element(s, "array", values),
+        InstructionKind::Map(entries) => {
+            s.push_str("<map>");
+            for (key, value) in entries {
+                s.push_str("<entry>");
+                instruction(s, key);
+                instruction(s, value);
+                s.push_str("</entry>");
+            }
+            s.push_str("</map>");
+        }
+        InstructionKind::Add(values) => element(s, "add", values),
+        InstructionKind::Subtract(values) => element(s, "subtract", values),
+        InstructionKind::Multiply(values) => element(s, "multiply", values),
+        InstructionKind::Divide(values) => element(s, "divide", values),
+        InstructionKind::Modulo(values) => element(s, "modulo", values),
+        InstructionKind::IntDivide(values) => element(s, "int-divide", values),
+        InstructionKind::Power(values) => element(s, "power", values),
+        InstructionKind::Negate(value) => element(s, "negate", std::slice::from_ref(value)),
+        InstructionKind::BitAnd(values) => element(s, "bit-and", values),
+        InstructionKind::BitOr(values) => element(s, "bit-or", values),
+        InstructionKind::BitXor(values) => element(s, "bit-xor", values),
+        InstructionKind::ShiftLeft(values) => element(s, "shift-left", values),
+        InstructionKind::ShiftRight(values) => element(s, "shift-right", values),
+        InstructionKind::And(values) => element(s, "and", values),
+        InstructionKind::Or(values) => element(s, "or", values),
+        InstructionKind::Not(value) => element(s, "not", std::slice::from_ref(value)),
+        InstructionKind::Equal(v1, v2) => binary(s, "equal", v1, v2),
+        InstructionKind::Greater(v1, v2) => binary(s, "greater", v1, v2),
+        InstructionKind::Lower(v1, v2) => binary(s, "lower", v1, v2),
+        InstructionKind::Call(function, args) => {
+            s.push_str("<call>");
+            instruction(s, function);
+            element(s, "arguments", args);
+            s.push_str("</call>");
+        }
+        InstructionKind::CallNamed(function, args) => {
+            s.push_str("<call");
+            attribute(s, "function", Some(function));
+            s.push('>');
+            element(s, "arguments", args);
+            s.push_str("</call>");
+        }
+        InstructionKind::Return(value) => element(s, "return", std::slice::from_ref(value)),
+        InstructionKind::If(cond, then) => {
+            s.push_str("<if>");
+            instruction(s, cond);
+            element(s, "then", then);
+            s.push_str("</if>");
+        }
+        InstructionKind::IfElse(cond, then, els) => {
+            s.push_str("<if>");
+            instruction(s, cond);
+            element(s, "then", then);
+            element(s, "else", els);
+            s.push_str("</if>");
+        }
+        InstructionKind::For {
+            variable,
+            from,
+            to,
+            step,
+            body,
+            label,
+        } => {
+            s.push_str("<for");
+            attribute(s, "variable", Some(variable));
+            attribute(s, "label", label.as_deref());
+            s.push('>');
+            element(s, "from", std::slice::from_ref(from));
+            element(s, "to", std::slice::from_ref(to));
+            element(s, "step", std::slice::from_ref(step));
+            element(s, "do", body);
+            s.push_str("</for>");
+        }
+        InstructionKind::Each {
+            variable,
+            key,
+            array,
+            body,
+            label,
+        } => {
+            s.push_str("<each");
+            attribute(s, "variable", Some(variable));
+            attribute(s, "key", key.as_deref());
+            attribute(s, "label", label.as_deref());
+            s.push('>');
+            instruction(s, array);
+            element(s, "do", body);
+            s.push_str("</each>");
+        }
+        InstructionKind::While(cond, body, label) => {
+            s.push_str("<while");
+            attribute(s, "label", label.as_deref());
+            s.push('>');
+            instruction(s, cond);
+            element(s, "do", body);
+            s.push_str("</while>");
+        }
+        InstructionKind::Break(label) => {
+            s.push_str("<break");
+            attribute(s, "label", label.as_deref());
+            s.push_str(" />");
+        }
+        InstructionKind::Continue(label) => {
+            s.push_str("<continue");
+            attribute(s, "label", label.as_deref());
+            s.push_str(" />");
+        }
+        InstructionKind::Handle {
+            body,
+            catches,
+            finally,
+        } => {
+            s.push_str("<handle>");
+            element(s, "try", body);
+            for catch in catches {
+                s.push_str("<catch");
+                attribute(s, "type", catch.filter.as_deref());
+                attribute(s, "variable", catch.variable.as_deref());
+                s.push('>');
+                for i in &catch.body {
+                    instruction(s, i);
+                }
+                s.push_str("</catch>");
+            }
+            // a handle node needs at least a catch or a finally node
+            if !finally.is_empty() || catches.is_empty() {
+                element(s, "finally", finally);
+            }
+            s.push_str("</handle>");
+        }
+        InstructionKind::Throw(tag, value) => {
+            s.push_str("<throw");
+            attribute(s, "type", tag.as_deref());
+            s.push('>');
+            instruction(s, value);
+            s.push_str("</throw>");
+        }
+        InstructionKind::Function(name, function) => {
+            s.push_str("<function");
+            attribute(s, "name", Some(name));
+            s.push('>');
+            signature(s, function);
+            s.push_str("</function>");
+        }
+        InstructionKind::Lambda(function) => {
+            s.push_str("<lambda>");
+            signature(s, function);
+            s.push_str("</lambda>");
+        }
+    }
+}
@@ -1,131 +1,329 @@
-use std::error::Error;
+use super::{Location, Value};
 use std::fmt;
 
-#[derive(Clone, Debug)]
-pub struct MissingChild(pub &'static str, pub &'static str);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Category {
+    Parse,
+    Type,
+    Arity,
+    UnknownVariable,
+    Io,
+    User,
+    Exit,
+    Denied,
+    Limit,
+}
 
-impl fmt::Display for MissingChild {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "missing '{}' child in '{}' node", self.1, self.0)
+impl Category {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Parse => "parse",
+            Category::Type => "type",
+            Category::Arity => "arity",
+            Category::UnknownVariable => "unknown-variable",
+            Category::Io => "io",
+            Category::User => "user",
+            Category::Exit => "exit",
+            Category::Denied => "denied",
+            Category::Limit => "limit",
+        }
     }
 }
 
-impl Error for MissingChild {}
-
-#[derive(Clone, Debug)]
-pub struct MissingAttribute(pub &'static str, pub &'static str);
-
-impl fmt::Display for MissingAttribute {
+impl fmt::Display for Category {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "missing '{}' attribute in '{}' node", self.1, self.0)
+        write!(f, "{}", self.name())
     }
 }
 
-impl Error for MissingAttribute {}
-
 #[derive(Clone, Debug)]
-pub struct BadArgumentCount(pub &'static str, pub usize, pub usize);
-
-impl fmt::Display for BadArgumentCount {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "bad argument count ({}, expected {}) in call to '{}'",
-            self.1, self.2, self.0
-        )
-    }
+pub enum ErrorKind {
+    Xml(String),
+    InvalidProgram,
+    UnknownTag(String),
+    MissingChild(&'static str, &'static str),
+    MissingAttribute(&'static str, &'static str),
+    BadChildCount(&'static str, usize),
+    Unnamed(&'static str),
+    OutsideLoop(&'static str, Option<String>),
+    UnexpectedAttribute(&'static str, String),
+    UnexpectedChild(&'static str, String),
+    UnexpectedText(&'static str),
+    CyclicImport(String),
+    ConstantAssignment(String),
+    InvalidScope(String),
+    InvalidNumber(String),
+    InvalidValue(&'static str),
+    IncompatibleValues,
+    InvalidArgument(&'static str, &'static str),
+    UnexpectedType(&'static str),
+    DivisionByZero(&'static str),
+    Overflow(&'static str),
+    BadArgumentCount(&'static str, usize, usize),
+    UnknownVariable(String),
+    InaccessibleFile(String),
+    Io(String),
+    Raised(Value, Option<String>),
+    Exit(i32),
+    Denied(&'static str, String),
+    LimitExceeded(&'static str, usize),
 }
 
-impl Error for BadArgumentCount {}
-
-#[derive(Clone, Debug)]
-pub struct BadChildCount(pub &'static str, pub usize);
+impl ErrorKind {
+    pub fn category(&self) -> Category {
+        match self {
+            ErrorKind::Xml(_)
+            | ErrorKind::InvalidProgram
+            | ErrorKind::UnknownTag(_)
+            | ErrorKind::MissingChild(..)
+            | ErrorKind::MissingAttribute(..)
+            | ErrorKind::BadChildCount(..)
+            | ErrorKind::Unnamed(_)
+            | ErrorKind::OutsideLoop(..)
+            | ErrorKind::UnexpectedAttribute(..)
+            | ErrorKind::UnexpectedChild(..)
+            | ErrorKind::UnexpectedText(_)
+            | ErrorKind::CyclicImport(_)
+            | ErrorKind::ConstantAssignment(_)
+            | ErrorKind::InvalidScope(_) => Category::Parse,
+            ErrorKind::InvalidNumber(_)
+            | ErrorKind::InvalidValue(_)
+            | ErrorKind::IncompatibleValues
+            | ErrorKind::InvalidArgument(..)
+            | ErrorKind::UnexpectedType(_)
+            | ErrorKind::DivisionByZero(_)
+            | ErrorKind::Overflow(_) => Category::Type,
+            ErrorKind::BadArgumentCount(..) => Category::Arity,
+            ErrorKind::UnknownVariable(_) => Category::UnknownVariable,
+            ErrorKind::InaccessibleFile(_) | ErrorKind::Io(_) => Category::Io,
+            ErrorKind::Raised(..) => Category::User,
+            ErrorKind::Exit(_) => Category::Exit,
+            ErrorKind::Denied(..) => Category::Denied,
+            ErrorKind::LimitExceeded(..) => Category::Limit,
+        }
+    }
 
-impl fmt::Display for BadChildCount {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "bad child count ({}) in '{}' tag", self.1, self.0)
+    /// Stable identifier of the error, grouped by category
+    /// (1xx parse, 2xx type, 3xx arity, 4xx unknown variable, 5xx io,
+    /// 6xx user, 7xx exit, 8xx denied, 9xx limit).
+    pub fn code(&self) -> &'static str {
+        match self {
+            ErrorKind::Xml(_) => "E100",
+            ErrorKind::InvalidProgram => "E101",
+            ErrorKind::UnknownTag(_) => "E102",
+            ErrorKind::MissingChild(..) => "E103",
+            ErrorKind::MissingAttribute(..) => "E104",
+            ErrorKind::BadChildCount(..) => "E105",
+            ErrorKind::Unnamed(_) => "E106",
+            ErrorKind::OutsideLoop(..) => "E107",
+            ErrorKind::UnexpectedAttribute(..) => "E108",
+            ErrorKind::UnexpectedChild(..) => "E109",
+            ErrorKind::UnexpectedText(_) => "E110",
+            ErrorKind::CyclicImport(_) => "E111",
+            ErrorKind::ConstantAssignment(_) => "E112",
+            ErrorKind::InvalidScope(_) => "E113",
+            ErrorKind::InvalidNumber(_) => "E200",
+            ErrorKind::InvalidValue(_) => "E201",
+            ErrorKind::IncompatibleValues => "E202",
+            ErrorKind::InvalidArgument(..) => "E203",
+            ErrorKind::UnexpectedType(_) => "E204",
+            ErrorKind::DivisionByZero(_) => "E205",
+            ErrorKind::Overflow(_) => "E206",
+            ErrorKind::BadArgumentCount(..) => "E300",
+            ErrorKind::UnknownVariable(_) => "E400",
+            ErrorKind::InaccessibleFile(_) => "E500",
+            ErrorKind::Io(_) => "E501",
+            ErrorKind::Raised(..) => "E600",
+            ErrorKind::Exit(_) => "E700",
+            ErrorKind::Denied(..) => "E800",
+            ErrorKind::LimitExceeded(..) => "E900",
+        }
     }
 }
 
-impl Error for BadChildCount {}
-
-#[derive(Clone, Debug)]
-pub struct InvalidArgument(pub &'static str, pub &'static str);
-
-impl fmt::Display for InvalidArgument {
+impl fmt::Display for ErrorKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "invalid value for argument '{}' in call to '{}'",
-            self.1, self.0
-        )
+        match self {
+            ErrorKind::Xml(e) => write!(f, "malformed document: {}", e),
+            ErrorKind::InvalidProgram => write!(f, "invalid program structure"),
+            ErrorKind::UnknownTag(tag) => write!(f, "unknown tag '{}'", tag),
+            ErrorKind::MissingChild(node, child) => {
+                write!(f, "missing '{}' child in '{}' node", child, node)
+            }
+            ErrorKind::MissingAttribute(node, attribute) => {
+                write!(f, "missing '{}' attribute in '{}' node", attribute, node)
+            }
+            ErrorKind::BadChildCount(tag, count) => {
+                write!(f, "bad child count ({}) in '{}' tag", count, tag)
+            }
+            ErrorKind::Unnamed(node) => write!(f, "unnamed '{}'", node),
+            ErrorKind::OutsideLoop(tag, None) => write!(f, "'{}' outside of a loop", tag),
+            ErrorKind::OutsideLoop(tag, Some(label)) => {
+                write!(f, "'{}' outside of a loop labeled '{}'", tag, label)
+            }
+            ErrorKind::UnexpectedAttribute(node, attribute) => {
+                write!(f, "unexpected '{}' attribute in '{}' node", attribute, node)
+            }
+            ErrorKind::UnexpectedChild(node, child) => {
+                write!(f, "unexpected '{}' child in '{}' node", child, node)
+            }
+            ErrorKind::UnexpectedText(node) => write!(f, "unexpected text in '{}' node", node),
+            ErrorKind::CyclicImport(src) => write!(f, "cyclic import of '{}'", src),
+            ErrorKind::ConstantAssignment(name) => {
+                write!(f, "assignment to constant '{}'", name)
+            }
+            ErrorKind::InvalidScope(scope) => write!(f, "invalid scope '{}'", scope),
+            ErrorKind::InvalidNumber(s) => write!(f, "invalid number '{}'", s),
+            ErrorKind::InvalidValue(tag) => write!(f, "invalid value in '{}' tag", tag),
+            ErrorKind::IncompatibleValues => write!(f, "incompatible comparison values"),
+            ErrorKind::InvalidArgument(function, argument) => write!(
+                f,
+                "invalid value for argument '{}' in call to '{}'",
+                argument, function
+            ),
+            ErrorKind::UnexpectedType(expected) => {
+                write!(f, "expected a value of type '{}'", expected)
+            }
+            ErrorKind::DivisionByZero(tag) => write!(f, "division by zero in '{}' tag", tag),
+            ErrorKind::Overflow(tag) => write!(f, "integer overflow in '{}' tag", tag),
+            ErrorKind::BadArgumentCount(function, count, expected) => write!(
+                f,
+                "bad argument count ({}, expected {}) in call to '{}'",
+                count, expected, function
+            ),
+            ErrorKind::UnknownVariable(name) => write!(f, "unknown variable '{}'", name),
+            ErrorKind::InaccessibleFile(path) => write!(f, "unable to access file '{}'", path),
+            ErrorKind::Io(e) => write!(f, "i/o error: {}", e),
+            ErrorKind::Raised(value, None) => write!(f, "uncaught exception: {}", value),
+            ErrorKind::Raised(value, Some(tag)) => {
+                write!(f, "uncaught '{}' exception: {}", tag, value)
+            }
+            ErrorKind::Exit(code) => write!(f, "exit with status {}", code),
+            ErrorKind::Denied(function, what) => {
+                write!(f, "access to {} denied in call to '{}'", what, function)
+            }
+            ErrorKind::LimitExceeded(limit, value) => {
+                write!(f, "{} limit of {} exceeded", limit, value)
+            }
+        }
     }
 }
 
-impl Error for InvalidArgument {}
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub function: String,
+    pub location: Location,
+}
 
 #[derive(Clone, Debug)]
-pub struct InvalidValue(pub &'static str);
+pub struct Error(Box<Repr>);
 
-impl fmt::Display for InvalidValue {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "invalid value in '{}' tag", self.0)
-    }
+#[derive(Clone, Debug)]
+struct Repr {
+    kind: ErrorKind,
+    location: Option<Location>,
+    trace: Vec<Frame>,
 }
 
-impl Error for InvalidValue {}
+impl Error {
+    pub fn new(kind: ErrorKind) -> Error {
+        Error(Box::new(Repr {
+            kind,
+            location: None,
+            trace: Vec::new(),
+        }))
+    }
 
-#[derive(Clone, Debug)]
-pub struct InvalidProgram;
+    pub fn kind(&self) -> &ErrorKind {
+        &self.0.kind
+    }
 
-impl fmt::Display for InvalidProgram {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "invalid program structure")
+    /// Location of the node that raised the error.
+    pub fn location(&self) -> Option<&Location> {
+        self.0.location.as_ref()
     }
-}
 
-impl Error for InvalidProgram {}
+    /// Calls the error went through, innermost first.
+    pub fn trace(&self) -> &[Frame] {
+        &self.0.trace
+    }
 
-#[derive(Clone, Debug)]
-pub struct IncompatibleValues;
+    pub fn code(&self) -> &'static str {
+        self.kind().code()
+    }
 
-impl fmt::Display for IncompatibleValues {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "incompatible comparison values")
+    pub fn category(&self) -> Category {
+        self.kind().category()
     }
-}
 
-impl Error for IncompatibleValues {}
+    /// Status requested by the program if it is exiting, which is
+    /// not an actual error and cannot be caught.
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.kind() {
+            ErrorKind::Exit(code) => Some(*code),
+            _ => None,
+        }
+    }
 
-#[derive(Clone, Debug)]
-pub struct Unnamed(pub &'static str);
+    /// Whether programs may handle the error, which they cannot when
+    /// exiting.
+    pub fn catchable(&self) -> bool {
+        !matches!(self.kind(), ErrorKind::Exit(_))
+    }
 
-impl fmt::Display for Unnamed {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "unnamed '{}'", self.0)
+    /// Whether the error is designated by `filter`, which may be
+    /// a category name, an error code, or the tag of a thrown value.
+    pub fn is(&self, filter: &str) -> bool {
+        self.category().name() == filter
+            || self.code() == filter
+            || matches!(self.kind(), ErrorKind::Raised(_, Some(tag)) if tag == filter)
     }
-}
 
-impl Error for Unnamed {}
+    /// Attaches a location to the error, unless a more precise one
+    /// was already recorded.
+    pub fn at(mut self, location: &Location) -> Error {
+        if self.0.location.is_none() {
+            self.0.location = Some(location.clone());
+        }
+        self
+    }
 
-#[derive(Clone, Debug)]
-pub struct UnknownVariable(pub String);
+    /// Records the call to `function` at `location` the error went through.
+    pub fn called(mut self, function: &str, location: &Location) -> Error {
+        self.0.trace.push(Frame {
+            function: String::from(function),
+            location: location.clone(),
+        });
+        self
+    }
+}
 
-impl fmt::Display for UnknownVariable {
+impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "unknown variable '{}'", self.0)
+        write!(f, "{}", self.kind())?;
+        if let Some(location) = self.location() {
+            write!(f, " at {}", location)?;
+        }
+        Ok(())
     }
 }
 
-impl Error for UnknownVariable {}
+impl std::error::Error for Error {}
 
-#[derive(Clone, Debug)]
-pub struct InaccessibleFile(pub String);
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Error {
+        Error::new(kind)
+    }
+}
 
-impl fmt::Display for InaccessibleFile {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "unable to access file '{}'", self.0)
+impl From<roxmltree::Error> for Error {
+    fn from(e: roxmltree::Error) -> Error {
+        Error::new(ErrorKind::Xml(e.to_string()))
     }
 }
 
-impl Error for InaccessibleFile {}
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Error {
+        Error::new(ErrorKind::Io(e.to_string()))
+    }
+}
@@ -0,0 +1,79 @@
+use roxmltree::{Document, Node, NodeType};
+use std::fmt::Write;
+
+const INDENT: &str = "    ";
+
+/// Elements kept on a single line when they hold a single leaf,
+/// as in `<from><integer value="0" /></from>`.
+const INLINE: &[&str] = &["from", "to", "step"];
+
+pub fn format(doc: &Document) -> String {
+    let mut s = String::new();
+    for node in doc.root().children() {
+        write_node(&mut s, &node, 0);
+    }
+    s
+}
+
+fn write_node(s: &mut String, node: &Node, depth: usize) {
+    let indent = INDENT.repeat(depth);
+    match node.node_type() {
+        NodeType::Element => {
+            write_start(s, node, &indent);
+            let children: Vec<Node> = node.children().filter(|n| !blank(n)).collect();
+            if children.is_empty() {
+                let _ = writeln!(s, " />");
+            } else if INLINE.contains(&node.tag_name().name())
+                && matches!(children.as_slice(), [child] if leaf(child))
+            {
+                write_start(s, &children[0], ">");
+                let _ = writeln!(s, " /></{}>", node.tag_name().name());
+            } else {
+                let _ = writeln!(s, ">");
+                for child in children {
+                    write_node(s, &child, depth + 1);
+                }
+                let _ = writeln!(s, "{}</{}>", indent, node.tag_name().name());
+            }
+        }
+        NodeType::Text => {
+            let _ = writeln!(s, "{}{}", indent, escape(node.text().unwrap_or("").trim()));
+        }
+        NodeType::Comment => {
+            let _ = writeln!(s, "{}<!--{}-->", indent, node.text().unwrap_or(""));
+        }
+        NodeType::PI => {
+            if let Some(pi) = node.pi() {
+                match pi.value {
+                    Some(value) => writeln!(s, "{}<?{} {}?>", indent, pi.target, value),
+                    None => writeln!(s, "{}<?{}?>", indent, pi.target),
+                }
+                .unwrap_or_default();
+            }
+        }
+        NodeType::Root => {}
+    }
+}
+
+fn write_start(s: &mut String, node: &Node, prefix: &str) {
+    let _ = write!(s, "{}<{}", prefix, node.tag_name().name());
+    for attribute in node.attributes() {
+        let _ = write!(s, " {}=\"{}\"", attribute.name(), escape(attribute.value()));
+    }
+}
+
+/// Whether a node is an element without children.
+fn leaf(node: &Node) -> bool {
+    node.is_element() && node.children().all(|n| blank(&n))
+}
+
+/// Whether a node is whitespace between elements.
+fn blank(node: &Node) -> bool {
+    node.is_text() && node.text().unwrap_or("").trim().is_empty()
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,716 @@
+use super::error::{
+    Error,
+    ErrorKind::{
+        InvalidProgram, MissingAttribute, MissingChild, UnexpectedAttribute, UnexpectedChild,
+        UnexpectedText, UnknownTag,
+    },
+};
+use super::Location;
+use roxmltree::{Document, Node};
+use std::fmt::Write;
+
+/// Grammar of the language, as described in `doc/syntax.md`.
+///
+/// It is used to validate programs in strict mode, and to generate
+/// the RelaxNG schema of the language.
+pub struct Element {
+    pub name: &'static str,
+    /// Name of the definition in the schema, which differs from the
+    /// element name when it has different contents depending on its
+    /// parent.
+    pub define: &'static str,
+    /// Alternative sets of attributes and children.
+    pub forms: &'static [Form],
+}
+
+pub struct Form {
+    pub attributes: &'static [Attribute],
+    pub content: &'static [Particle],
+}
+
+pub struct Attribute {
+    pub name: &'static str,
+    pub required: bool,
+}
+
+pub struct Particle {
+    pub term: Term,
+    pub occurs: Occurs,
+}
+
+pub enum Term {
+    Instruction,
+    Element(&'static Element),
+    /// Any of several elements, designated by a name in errors.
+    Choice(&'static str, &'static [&'static Element]),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Occurs {
+    One,
+    Optional,
+    Many,
+}
+
+impl Term {
+    fn name(&self) -> &'static str {
+        match self {
+            Term::Instruction => "instruction",
+            Term::Element(e) => e.name,
+            Term::Choice(name, _) => name,
+        }
+    }
+
+    fn matches(&self, node: &Node) -> bool {
+        match self {
+            Term::Instruction => instruction(node.tag_name().name()).is_some(),
+            Term::Element(e) => node.tag_name().name() == e.name,
+            Term::Choice(_, elements) => elements.iter().any(|e| node.tag_name().name() == e.name),
+        }
+    }
+}
+
+const fn required(name: &'static str) -> Attribute {
+    Attribute {
+        name,
+        required: true,
+    }
+}
+
+const fn optional(name: &'static str) -> Attribute {
+    Attribute {
+        name,
+        required: false,
+    }
+}
+
+const fn one(term: Term) -> Particle {
+    Particle {
+        term,
+        occurs: Occurs::One,
+    }
+}
+
+const fn maybe(term: Term) -> Particle {
+    Particle {
+        term,
+        occurs: Occurs::Optional,
+    }
+}
+
+const fn many(term: Term) -> Particle {
+    Particle {
+        term,
+        occurs: Occurs::Many,
+    }
+}
+
+const INSTRUCTION: Particle = one(Term::Instruction);
+const INSTRUCTIONS: Particle = many(Term::Instruction);
+
+const fn element(name: &'static str, forms: &'static [Form]) -> Element {
+    Element {
+        name,
+        define: name,
+        forms,
+    }
+}
+
+const fn form(attributes: &'static [Attribute], content: &'static [Particle]) -> Form {
+    Form {
+        attributes,
+        content,
+    }
+}
+
+pub static PROGRAM: Element = element(
+    "program",
+    &[form(
+        &[required("name")],
+        &[
+            many(Term::Element(&IMPORT)),
+            many(DECLARATION),
+            one(Term::Element(&MAIN)),
+            many(DECLARATION),
+        ],
+    )],
+);
+
+static IMPORT: Element = element("import", &[form(&[required("src"), optional("as")], &[])]);
+
+/// Top-level functions and variables.
+const DECLARATION: Term = Term::Choice("declaration", &[&FUNCTION, &GLOBAL, &CONST]);
+
+static GLOBAL: Element = element("global", &[form(&[required("name")], &[INSTRUCTION])]);
+
+static CONST: Element = element("const", &[form(&[required("name")], &[INSTRUCTION])]);
+
+static MAIN: Element = element("main", &[form(&[], &[INSTRUCTIONS])]);
+
+static FUNCTION: Element = element(
+    "function",
+    &[form(
+        &[required("name")],
+        &[one(Term::Element(&ARGUMENTS)), one(Term::Element(&BODY))],
+    )],
+);
+
+static ARGUMENTS: Element = element("arguments", &[form(&[], &[many(Term::Element(&ARGUMENT))])]);
+
+static ARGUMENT: Element = element("argument", &[form(&[required("name")], &[])]);
+
+static BODY: Element = element("body", &[form(&[], &[INSTRUCTIONS])]);
+
+static VALUE: Element = element("value", &[form(&[required("variable")], &[])]);
+
+static ASSIGN: Element = element(
+    "assign",
+    &[form(
+        &[required("variable"), optional("scope")],
+        &[INSTRUCTION],
+    )],
+);
+
+static TRUE: Element = element("true", &[form(&[], &[])]);
+
+static FALSE: Element = element("false", &[form(&[], &[])]);
+
+static NULL: Element = element("null", &[form(&[], &[])]);
+
+/// Literals, which may also cast the value of their child.
+const LITERAL: &[Form] = &[form(&[required("value")], &[]), form(&[], &[INSTRUCTION])];
+
+static INTEGER: Element = element("integer", LITERAL);
+
+static REAL: Element = element("real", LITERAL);
+
+static STRING: Element = element("string", LITERAL);
+
+static ARRAY: Element = element("array", &[form(&[], &[INSTRUCTIONS])]);
+
+static MAP: Element = element("map", &[form(&[], &[many(Term::Element(&ENTRY))])]);
+
+static ENTRY: Element = element(
+    "entry",
+    &[
+        form(&[required("key")], &[INSTRUCTION]),
+        form(&[], &[INSTRUCTION, INSTRUCTION]),
+    ],
+);
+
+static ADD: Element = element("add", &[form(&[], &[INSTRUCTIONS])]);
+
+static SUBTRACT: Element = element("subtract", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static MULTIPLY: Element = element("multiply", &[form(&[], &[INSTRUCTIONS])]);
+
+static DIVIDE: Element = element("divide", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static MODULO: Element = element("modulo", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static INT_DIVIDE: Element = element("int-divide", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static POWER: Element = element("power", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static NEGATE: Element = element("negate", &[form(&[], &[INSTRUCTION])]);
+
+static BIT_AND: Element = element("bit-and", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static BIT_OR: Element = element("bit-or", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static BIT_XOR: Element = element("bit-xor", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static SHIFT_LEFT: Element = element("shift-left", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static SHIFT_RIGHT: Element = element("shift-right", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static AND: Element = element("and", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static OR: Element = element("or", &[form(&[], &[INSTRUCTION, INSTRUCTIONS])]);
+
+static NOT: Element = element("not", &[form(&[], &[INSTRUCTION])]);
+
+static EQUAL: Element = element("equal", &[form(&[], &[INSTRUCTION, INSTRUCTION])]);
+
+static GREATER: Element = element("greater", &[form(&[], &[INSTRUCTION, INSTRUCTION])]);
+
+static LOWER: Element = element("lower", &[form(&[], &[INSTRUCTION, INSTRUCTION])]);
+
+static CALL: Element = element(
+    "call",
+    &[
+        form(
+            &[required("function")],
+            &[one(Term::Element(&CALL_ARGUMENTS))],
+        ),
+        form(&[], &[INSTRUCTION, one(Term::Element(&CALL_ARGUMENTS))]),
+    ],
+);
+
+static CALL_ARGUMENTS: Element = Element {
+    name: "arguments",
+    define: "call-arguments",
+    forms: &[form(&[], &[INSTRUCTIONS])],
+};
+
+static RETURN: Element = element("return", &[form(&[], &[INSTRUCTION])]);
+
+static IF: Element = element(
+    "if",
+    &[form(
+        &[],
+        &[
+            INSTRUCTION,
+            one(Term::Element(&THEN)),
+            maybe(Term::Element(&ELSE)),
+        ],
+    )],
+);
+
+static THEN: Element = element("then", &[form(&[], &[INSTRUCTIONS])]);
+
+static ELSE: Element = element("else", &[form(&[], &[INSTRUCTIONS])]);
+
+static FOR: Element = element(
+    "for",
+    &[form(
+        &[required("variable"), optional("label")],
+        &[
+            one(Term::Element(&FROM)),
+            one(Term::Element(&TO)),
+            one(Term::Element(&STEP)),
+            one(Term::Element(&DO)),
+        ],
+    )],
+);
+
+static FROM: Element = element("from", &[form(&[], &[INSTRUCTION])]);
+
+static TO: Element = element("to", &[form(&[], &[INSTRUCTION])]);
+
+static STEP: Element = element("step", &[form(&[], &[INSTRUCTION])]);
+
+static DO: Element = element("do", &[form(&[], &[INSTRUCTIONS])]);
+
+static EACH: Element = element(
+    "each",
+    &[form(
+        &[required("variable"), optional("key"), optional("label")],
+        &[INSTRUCTION, one(Term::Element(&DO))],
+    )],
+);
+
+static WHILE: Element = element(
+    "while",
+    &[form(
+        &[optional("label")],
+        &[INSTRUCTION, one(Term::Element(&DO))],
+    )],
+);
+
+static BREAK: Element = element("break", &[form(&[optional("label")], &[])]);
+
+static CONTINUE: Element = element("continue", &[form(&[optional("label")], &[])]);
+
+static HANDLE: Element = element(
+    "handle",
+    &[
+        form(
+            &[],
+            &[
+                one(Term::Element(&TRY)),
+                one(Term::Element(&CATCH)),
+                many(Term::Element(&CATCH)),
+                maybe(Term::Element(&FINALLY)),
+            ],
+        ),
+        form(
+            &[],
+            &[one(Term::Element(&TRY)), one(Term::Element(&FINALLY))],
+        ),
+    ],
+);
+
+static TRY: Element = element("try", &[form(&[], &[INSTRUCTIONS])]);
+
+static CATCH: Element = element(
+    "catch",
+    &[form(
+        &[optional("type"), optional("variable")],
+        &[INSTRUCTIONS],
+    )],
+);
+
+static FINALLY: Element = element("finally", &[form(&[], &[INSTRUCTIONS])]);
+
+static THROW: Element = element("throw", &[form(&[optional("type")], &[INSTRUCTION])]);
+
+static LAMBDA: Element = element(
+    "lambda",
+    &[form(
+        &[],
+        &[one(Term::Element(&ARGUMENTS)), one(Term::Element(&BODY))],
+    )],
+);
+
+/// Elements which may appear wherever an instruction is expected.
+pub static INSTRUCTION_ELEMENTS: &[&Element] = &[
+    &VALUE,
+    &ASSIGN,
+    &TRUE,
+    &FALSE,
+    &NULL,
+    &INTEGER,
+    &REAL,
+    &STRING,
+    &ARRAY,
+    &MAP,
+    &ADD,
+    &SUBTRACT,
+    &MULTIPLY,
+    &DIVIDE,
+    &MODULO,
+    &INT_DIVIDE,
+    &POWER,
+    &NEGATE,
+    &BIT_AND,
+    &BIT_OR,
+    &BIT_XOR,
+    &SHIFT_LEFT,
+    &SHIFT_RIGHT,
+    &AND,
+    &OR,
+    &NOT,
+    &EQUAL,
+    &GREATER,
+    &LOWER,
+    &CALL,
+    &RETURN,
+    &IF,
+    &FOR,
+    &EACH,
+    &WHILE,
+    &HANDLE,
+    &THROW,
+    &FUNCTION,
+    &LAMBDA,
+    &BREAK,
+    &CONTINUE,
+];
+
+pub fn instruction(name: &str) -> Option<&'static Element> {
+    INSTRUCTION_ELEMENTS
+        .iter()
+        .copied()
+        .find(|e| e.name == name)
+}
+
+/// Checks that a document follows the grammar exactly.
+pub fn validate(doc: &Document) -> Result<(), Error> {
+    let root = doc.root_element();
+    if root.tag_name().name() != PROGRAM.name {
+        Err(Error::from(InvalidProgram).at(&Location::of(&root)))?
+    }
+    PROGRAM.validate(&root)
+}
+
+impl Element {
+    fn validate(&self, node: &Node) -> Result<(), Error> {
+        // report the error of the form which matched for the longest
+        let mut best: Option<(usize, Error)> = None;
+        for form in self.forms {
+            match form.validate(self, node) {
+                Ok(()) => return Ok(()),
+                Err((progress, e)) => {
+                    if best.as_ref().is_none_or(|(p, _)| progress > *p) {
+                        best = Some((progress, e));
+                    }
+                }
+            }
+        }
+        match best {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Form {
+    /// Validates a node, telling how far it went in case of failure.
+    fn validate(&self, element: &Element, node: &Node) -> Result<(), (usize, Error)> {
+        let location = Location::of(node);
+        for attribute in node.attributes() {
+            if !self.attributes.iter().any(|a| a.name == attribute.name()) {
+                Err((
+                    0,
+                    Error::from(UnexpectedAttribute(
+                        element.name,
+                        String::from(attribute.name()),
+                    ))
+                    .at(&location),
+                ))?
+            }
+        }
+        for attribute in self.attributes.iter().filter(|a| a.required) {
+            if node.attribute(attribute.name).is_none() {
+                Err((
+                    0,
+                    Error::from(MissingAttribute(element.name, attribute.name)).at(&location),
+                ))?
+            }
+        }
+
+        let mut children = Vec::new();
+        for child in node.children() {
+            if child.is_element() {
+                children.push(child);
+            } else if child.is_text() && !child.text().unwrap_or("").trim().is_empty() {
+                Err((
+                    1,
+                    Error::from(UnexpectedText(element.name)).at(&Location::of(&child)),
+                ))?
+            }
+        }
+
+        let mut i = 0;
+        for particle in self.content {
+            let first = i;
+            while i < children.len()
+                && particle.term.matches(&children[i])
+                && (particle.occurs == Occurs::Many || i == first)
+            {
+                Term::validate(&particle.term, &children[i]).map_err(|e| (i + 2, e))?;
+                i += 1;
+            }
+            if particle.occurs == Occurs::One && i == first {
+                Err((
+                    i + 1,
+                    Error::from(MissingChild(element.name, particle.term.name())).at(&location),
+                ))?
+            }
+        }
+        if let Some(child) = children.get(i) {
+            let name = child.tag_name().name();
+            let kind = if instruction(name).is_some() || known(name) {
+                UnexpectedChild(element.name, String::from(name))
+            } else {
+                UnknownTag(String::from(name))
+            };
+            Err((i + 1, Error::from(kind).at(&Location::of(child))))?
+        }
+        Ok(())
+    }
+}
+
+impl Term {
+    fn validate(&self, node: &Node) -> Result<(), Error> {
+        match self {
+            Term::Instruction => match instruction(node.tag_name().name()) {
+                Some(e) => e.validate(node),
+                None => Err(
+                    Error::from(UnknownTag(String::from(node.tag_name().name())))
+                        .at(&Location::of(node)),
+                ),
+            },
+            Term::Element(e) => e.validate(node),
+            Term::Choice(_, elements) => {
+                match elements.iter().find(|e| node.tag_name().name() == e.name) {
+                    Some(e) => e.validate(node),
+                    None => Err(
+                        Error::from(UnknownTag(String::from(node.tag_name().name())))
+                            .at(&Location::of(node)),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+/// Every element of the grammar, each definition appearing once.
+fn elements() -> Vec<&'static Element> {
+    fn visit(element: &'static Element, found: &mut Vec<&'static Element>) {
+        if found.iter().any(|e| e.define == element.define) {
+            return;
+        }
+        found.push(element);
+        for form in element.forms {
+            for particle in form.content {
+                match particle.term {
+                    Term::Instruction => {
+                        for e in INSTRUCTION_ELEMENTS {
+                            visit(e, found);
+                        }
+                    }
+                    Term::Element(e) => visit(e, found),
+                    Term::Choice(_, elements) => {
+                        for e in elements {
+                            visit(e, found);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let mut found = Vec::new();
+    visit(&PROGRAM, &mut found);
+    found
+}
+
+fn known(name: &str) -> bool {
+    elements().iter().any(|e| e.name == name)
+}
+
+/// Generates the RelaxNG schema of the language.
+pub fn schema() -> String {
+    let mut s = String::new();
+    let _ = writeln!(s, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        s,
+        r#"<grammar xmlns="http://relaxng.org/ns/structure/1.0">"#
+    );
+    let _ = writeln!(s, r#"    <start><ref name="{}" /></start>"#, PROGRAM.define);
+    let _ = writeln!(s, r#"    <define name="instruction">"#);
+    let _ = writeln!(s, r#"        <choice>"#);
+    for e in INSTRUCTION_ELEMENTS {
+        let _ = writeln!(s, r#"            <ref name="{}" />"#, e.define);
+    }
+    let _ = writeln!(s, r#"        </choice>"#);
+    let _ = writeln!(s, r#"    </define>"#);
+    for e in elements() {
+        let _ = writeln!(s, r#"    <define name="{}">"#, e.define);
+        let _ = writeln!(s, r#"        <element name="{}">"#, e.name);
+        if e.forms.len() > 1 {
+            let _ = writeln!(s, r#"            <choice>"#);
+            for form in e.forms {
+                let _ = writeln!(s, r#"                <group>"#);
+                write_form(&mut s, form, 5);
+                let _ = writeln!(s, r#"                </group>"#);
+            }
+            let _ = writeln!(s, r#"            </choice>"#);
+        } else {
+            for form in e.forms {
+                write_form(&mut s, form, 3);
+            }
+        }
+        let _ = writeln!(s, r#"        </element>"#);
+        let _ = writeln!(s, r#"    </define>"#);
+    }
+    let _ = writeln!(s, r#"</grammar>"#);
+    s
+}
+
+fn write_form(s: &mut String, form: &Form, depth: usize) {
+    let indent = "    ".repeat(depth);
+    for a in form.attributes {
+        if a.required {
+            let _ = writeln!(s, r#"{}<attribute name="{}" />"#, indent, a.name);
+        } else {
+            let _ = writeln!(
+                s,
+                r#"{}<optional><attribute name="{}" /></optional>"#,
+                indent, a.name
+            );
+        }
+    }
+    if form.attributes.is_empty() && form.content.is_empty() {
+        let _ = writeln!(s, r#"{}<empty />"#, indent);
+    }
+    for p in form.content {
+        let refs = match p.term {
+            Term::Instruction => String::from(r#"<ref name="instruction" />"#),
+            Term::Element(e) => format!(r#"<ref name="{}" />"#, e.define),
+            Term::Choice(_, elements) => format!(
+                "<choice>{}</choice>",
+                elements
+                    .iter()
+                    .map(|e| format!(r#"<ref name="{}" />"#, e.define))
+                    .collect::<String>()
+            ),
+        };
+        let _ = match p.occurs {
+            Occurs::One => writeln!(s, "{}{}", indent, refs),
+            Occurs::Optional => writeln!(s, "{}<optional>{}</optional>", indent, refs),
+            Occurs::Many => writeln!(s, "{}<zeroOrMore>{}</zeroOrMore>", indent, refs),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Instruction;
+
+    /// Value of an attribute which the parser accepts.
+    fn value(attribute: &str) -> &'static str {
+        match attribute {
+            "value" => "1",
+            "scope" => "outer",
+            _ => "x",
+        }
+    }
+
+    /// Number of required attributes and children of a form.
+    fn parts(form: &Form) -> usize {
+        form.attributes.iter().filter(|a| a.required).count()
+            + form
+                .content
+                .iter()
+                .filter(|p| p.occurs == Occurs::One)
+                .count()
+    }
+
+    /// Smallest instance of a form, without one of its required parts
+    /// if given.
+    fn instance(element: &Element, form: &Form, without: Option<usize>) -> String {
+        let mut part = 0;
+        let mut skip = |required: bool| {
+            let skipped = required && without == Some(part);
+            part += usize::from(required);
+            skipped
+        };
+        let mut s = format!("<{}", element.name);
+        for a in form.attributes {
+            if !skip(a.required) {
+                let _ = write!(s, r#" {}="{}""#, a.name, value(a.name));
+            }
+        }
+        s.push('>');
+        for p in form.content.iter().filter(|p| p.occurs == Occurs::One) {
+            if !skip(true) {
+                s.push_str(&match p.term {
+                    Term::Instruction => String::from("<null />"),
+                    Term::Element(e) => instance(e, &e.forms[0], None),
+                    Term::Choice(_, elements) => instance(elements[0], &elements[0].forms[0], None),
+                });
+            }
+        }
+        let _ = write!(s, "</{}>", element.name);
+        s
+    }
+
+    /// Whether the grammar and the parser accept an instruction.
+    fn accepted(element: &Element, instruction: &str) -> (bool, bool) {
+        let doc = Document::parse(instruction).unwrap();
+        let node = doc.root_element();
+        (
+            element.validate(&node).is_ok(),
+            Instruction::new(node).is_ok(),
+        )
+    }
+
+    #[test]
+    fn parser_follows_grammar() {
+        for element in INSTRUCTION_ELEMENTS {
+            for form in element.forms {
+                let complete = instance(element, form, None);
+                assert_eq!(accepted(element, &complete), (true, true), "{}", complete);
+                for part in 0..parts(form) {
+                    let partial = instance(element, form, Some(part));
+                    let (grammar, parser) = accepted(element, &partial);
+                    assert_eq!(grammar, parser, "{}", partial);
+                }
+            }
+        }
+    }
+}
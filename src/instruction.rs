@@ -1,28 +1,88 @@
 use super::error::{
-    BadChildCount, IncompatibleValues, InvalidValue, MissingAttribute, MissingChild,
-    UnknownVariable,
+    Error, ErrorKind,
+    ErrorKind::{
+        BadChildCount, DivisionByZero, IncompatibleValues, InvalidNumber, InvalidScope,
+        InvalidValue, MissingAttribute, MissingChild, OutsideLoop, Overflow, Raised, UnknownTag,
+        UnknownVariable, Unnamed,
+    },
 };
-use super::{util, Context, Value};
+use super::value::{Closure, Function, Key};
+use super::{grammar, util, Context, Location, Name, Value};
 use roxmltree::Node;
 use std::cell::RefCell;
-use std::error::Error;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::rc::Rc;
 
 #[derive(Clone, Debug)]
-pub enum Instruction {
-    Value(String),
-    Assign(String, Box<Instruction>),
-    Integer(String),
+pub struct Instruction {
+    pub kind: InstructionKind,
+    pub location: Location,
+}
+
+/// Scope an assignment writes to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// The innermost scope, where the variable is created if needed.
+    Local,
+    /// The nearest enclosing scope defining the variable.
+    Outer,
+    /// The top-level scope of the program, defining the variable.
+    Global,
+}
+
+impl Target {
+    fn new(scope: Option<&str>) -> Result<Target, Error> {
+        Ok(match scope {
+            None | Some("local") => Target::Local,
+            Some("outer") => Target::Outer,
+            Some("global") => Target::Global,
+            Some(scope) => Err(InvalidScope(String::from(scope)))?,
+        })
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Target::Local => None,
+            Target::Outer => Some("outer"),
+            Target::Global => Some("global"),
+        }
+    }
+}
+
+/// Operands of an arithmetic operation, once promoted.
+enum Numbers {
+    Integers(Vec<i64>),
+    Reals(Vec<f64>),
+}
+
+#[derive(Clone, Debug)]
+pub enum InstructionKind {
+    Value(Name),
+    Assign(Name, Target, Box<Instruction>),
+    Boolean(bool),
+    Null,
+    Integer(i64),
     IntegerCast(Box<Instruction>),
-    Real(String),
+    Real(f64),
     RealCast(Box<Instruction>),
     String(String),
     StringCast(Box<Instruction>),
     Array(Vec<Instruction>),
+    Map(Vec<(Instruction, Instruction)>),
     Add(Vec<Instruction>),
     Subtract(Vec<Instruction>),
     Multiply(Vec<Instruction>),
     Divide(Vec<Instruction>),
+    Modulo(Vec<Instruction>),
+    IntDivide(Vec<Instruction>),
+    Power(Vec<Instruction>),
+    Negate(Box<Instruction>),
+    BitAnd(Vec<Instruction>),
+    BitOr(Vec<Instruction>),
+    BitXor(Vec<Instruction>),
+    ShiftLeft(Vec<Instruction>),
+    ShiftRight(Vec<Instruction>),
     And(Vec<Instruction>),
     Or(Vec<Instruction>),
     Not(Box<Instruction>),
@@ -30,82 +90,249 @@ pub enum Instruction {
     Greater(Box<Instruction>, Box<Instruction>),
     Lower(Box<Instruction>, Box<Instruction>),
     Call(Box<Instruction>, Vec<Instruction>),
-    CallNamed(String, Vec<Instruction>),
+    CallNamed(Name, Vec<Instruction>),
     Return(Box<Instruction>),
     If(Box<Instruction>, Vec<Instruction>),
     IfElse(Box<Instruction>, Vec<Instruction>, Vec<Instruction>),
     For {
-        variable: String,
+        variable: Name,
         from: Box<Instruction>,
         to: Box<Instruction>,
         step: Box<Instruction>,
         body: Vec<Instruction>,
+        label: Option<String>,
     },
-    Each(String, Box<Instruction>, Vec<Instruction>),
-    While(Box<Instruction>, Vec<Instruction>),
-    Handle(Vec<Instruction>, Vec<Instruction>, String),
+    Each {
+        variable: Name,
+        key: Option<Name>,
+        array: Box<Instruction>,
+        body: Vec<Instruction>,
+        label: Option<String>,
+    },
+    While(Box<Instruction>, Vec<Instruction>, Option<String>),
+    Break(Option<String>),
+    Continue(Option<String>),
+    Handle {
+        body: Vec<Instruction>,
+        catches: Vec<Catch>,
+        finally: Vec<Instruction>,
+    },
+    Throw(Option<String>, Box<Instruction>),
+    Function(Name, Rc<Function>),
+    Lambda(Rc<Function>),
+}
+
+/// Ways of leaving an instruction other than completing normally.
+#[derive(Debug)]
+pub enum Flow {
+    Error(Error),
+    Return(Value),
+    Break(Option<String>, Location),
+    Continue(Option<String>, Location),
+}
+
+impl Flow {
+    pub fn at(self, location: &Location) -> Flow {
+        match self {
+            Flow::Error(e) => Flow::Error(e.at(location)),
+            flow => flow,
+        }
+    }
+
+    /// Turns a flow which cannot go any further into an error,
+    /// such as a break or continue outside of a loop.
+    pub fn escaped(self) -> Result<Value, Error> {
+        match self {
+            Flow::Error(e) => Err(e),
+            Flow::Return(v) => Ok(v),
+            Flow::Break(label, location) => {
+                Err(Error::from(OutsideLoop("break", label)).at(&location))
+            }
+            Flow::Continue(label, location) => {
+                Err(Error::from(OutsideLoop("continue", label)).at(&location))
+            }
+        }
+    }
+}
+
+impl From<Error> for Flow {
+    fn from(e: Error) -> Flow {
+        Flow::Error(e)
+    }
+}
+
+impl From<ErrorKind> for Flow {
+    fn from(kind: ErrorKind) -> Flow {
+        Flow::Error(kind.into())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Catch {
+    pub filter: Option<String>,
+    pub variable: Option<Name>,
+    pub body: Vec<Instruction>,
+}
+
+impl Catch {
+    fn new(node: Node) -> Result<Catch, Error> {
+        Ok(Catch {
+            filter: node.attribute("type").map(String::from),
+            variable: node.attribute("variable").map(Name::new),
+            body: Instruction::from_children(node)?,
+        })
+    }
+
+    pub fn matches(&self, error: &Error) -> bool {
+        error.catchable() && self.filter.as_ref().is_none_or(|f| error.is(f))
+    }
+
+    /// Value given to the catch variable: the thrown value, or the
+    /// description of errors raised by the interpreter.
+    pub fn value(error: &Error) -> Value {
+        match error.kind() {
+            Raised(value, _) => value.clone(),
+            _ => Value::String(error.to_string()),
+        }
+    }
 }
 
 impl Instruction {
-    pub fn new(node: Node) -> Result<Instruction, Box<dyn Error>> {
-        Ok(match util::tag_name(&node).as_str() {
-            "value" => Instruction::Value(
+    pub fn new(node: Node) -> Result<Instruction, Error> {
+        let location = Location::of(&node);
+        Ok(Instruction {
+            kind: InstructionKind::new(node).map_err(|e| e.at(&location))?,
+            location,
+        })
+    }
+
+    /// Parses a map entry, whose key is given either as a `key`
+    /// attribute or as a first child.
+    fn entry(node: Node) -> Result<(Instruction, Instruction), Error> {
+        let location = Location::of(&node);
+        if util::tag_name(&node) != "entry" {
+            Err(Error::from(UnknownTag(util::tag_name(&node))).at(&location))?
+        }
+        let children: Vec<Node> = node.children().filter(Node::is_element).collect();
+        match (node.attribute("key"), children.as_slice()) {
+            (Some(key), [value]) => Ok((
+                Instruction {
+                    kind: InstructionKind::String(String::from(key)),
+                    location: location.clone(),
+                },
+                Instruction::new(*value)?,
+            )),
+            (None, [key, value]) => Ok((Instruction::new(*key)?, Instruction::new(*value)?)),
+            _ => Err(Error::from(BadChildCount("entry", children.len())).at(&location)),
+        }
+    }
+
+    pub fn from_children(node: Node) -> Result<Vec<Instruction>, Error> {
+        node.children()
+            .filter(Node::is_element)
+            .map(Instruction::new)
+            .collect()
+    }
+
+    /// Operands of an operation taking at least one of them.
+    fn operands(node: Node, tag: &'static str) -> Result<Vec<Instruction>, Error> {
+        let operands = Instruction::from_children(node)?;
+        if operands.is_empty() {
+            Err(BadChildCount(tag, 0))?
+        }
+        Ok(operands)
+    }
+}
+
+impl InstructionKind {
+    fn new(node: Node) -> Result<InstructionKind, Error> {
+        let tag = util::tag_name(&node);
+        // the grammar tells which elements are instructions
+        if grammar::instruction(&tag).is_none() {
+            Err(UnknownTag(tag.clone()))?
+        }
+        Ok(match tag.as_str() {
+            "value" => InstructionKind::Value(
                 node.attribute("variable")
-                    .and_then(|a| Some(String::from(a)))
+                    .map(Name::new)
                     .ok_or(MissingAttribute("value", "variable"))?,
             ),
-            "assign" => Instruction::Assign(
-                String::from(
+            "assign" => InstructionKind::Assign(
+                Name::new(
                     node.attribute("variable")
                         .ok_or(MissingAttribute("assign", "variable"))?,
                 ),
+                Target::new(node.attribute("scope"))?,
                 Box::new(Instruction::new(
                     node.first_element_child()
                         .ok_or(MissingChild("assign", "value"))?,
                 )?),
             ),
+            "true" => InstructionKind::Boolean(true),
+            "false" => InstructionKind::Boolean(false),
+            "null" => InstructionKind::Null,
             "integer" => {
                 if let Some(v) = node.attribute("value") {
-                    Instruction::Integer(String::from(v))
+                    InstructionKind::Integer(v.parse().map_err(|_| InvalidNumber(String::from(v)))?)
                 } else if let Some(n) = node.first_element_child() {
-                    Instruction::IntegerCast(Box::new(Instruction::new(n)?))
+                    InstructionKind::IntegerCast(Box::new(Instruction::new(n)?))
                 } else {
                     Err(MissingAttribute("integer", "value"))?
                 }
             }
             "real" => {
                 if let Some(v) = node.attribute("value") {
-                    Instruction::Real(String::from(v))
+                    InstructionKind::Real(v.parse().map_err(|_| InvalidNumber(String::from(v)))?)
                 } else if let Some(n) = node.first_element_child() {
-                    Instruction::RealCast(Box::new(Instruction::new(n)?))
+                    InstructionKind::RealCast(Box::new(Instruction::new(n)?))
                 } else {
                     Err(MissingAttribute("real", "value"))?
                 }
             }
             "string" => {
                 if let Some(v) = node.attribute("value") {
-                    Instruction::String(String::from(v))
+                    InstructionKind::String(String::from(v))
                 } else if let Some(n) = node.first_element_child() {
-                    Instruction::StringCast(Box::new(Instruction::new(n)?))
+                    InstructionKind::StringCast(Box::new(Instruction::new(n)?))
                 } else {
                     Err(MissingAttribute("string", "value"))?
                 }
             }
-            "array" => Instruction::Array(Instruction::from_children(node)?),
-            "add" => Instruction::Add(Instruction::from_children(node)?),
-            "subtract" => Instruction::Subtract(Instruction::from_children(node)?),
-            "multiply" => Instruction::Multiply(Instruction::from_children(node)?),
-            "divide" => Instruction::Divide(Instruction::from_children(node)?),
-            "and" => Instruction::And(Instruction::from_children(node)?),
-            "or" => Instruction::Or(Instruction::from_children(node)?),
-            "not" => Instruction::Not(Box::new(Instruction::new(
+            "array" => InstructionKind::Array(Instruction::from_children(node)?),
+            "map" => InstructionKind::Map(
+                node.children()
+                    .filter(Node::is_element)
+                    .map(Instruction::entry)
+                    .collect::<Result<Vec<(Instruction, Instruction)>, Error>>()?,
+            ),
+            "add" => InstructionKind::Add(Instruction::from_children(node)?),
+            "subtract" => InstructionKind::Subtract(Instruction::operands(node, "subtract")?),
+            "multiply" => InstructionKind::Multiply(Instruction::from_children(node)?),
+            "divide" => InstructionKind::Divide(Instruction::operands(node, "divide")?),
+            "modulo" => InstructionKind::Modulo(Instruction::operands(node, "modulo")?),
+            "int-divide" => InstructionKind::IntDivide(Instruction::operands(node, "int-divide")?),
+            "power" => InstructionKind::Power(Instruction::operands(node, "power")?),
+            "negate" => InstructionKind::Negate(Box::new(Instruction::new(
+                node.first_element_child()
+                    .ok_or(MissingChild("negate", "value"))?,
+            )?)),
+            "bit-and" => InstructionKind::BitAnd(Instruction::operands(node, "bit-and")?),
+            "bit-or" => InstructionKind::BitOr(Instruction::operands(node, "bit-or")?),
+            "bit-xor" => InstructionKind::BitXor(Instruction::operands(node, "bit-xor")?),
+            "shift-left" => InstructionKind::ShiftLeft(Instruction::operands(node, "shift-left")?),
+            "shift-right" => {
+                InstructionKind::ShiftRight(Instruction::operands(node, "shift-right")?)
+            }
+            "and" => InstructionKind::And(Instruction::operands(node, "and")?),
+            "or" => InstructionKind::Or(Instruction::operands(node, "or")?),
+            "not" => InstructionKind::Not(Box::new(Instruction::new(
                 node.first_element_child()
                     .ok_or(MissingAttribute("not", "value"))?,
             )?)),
             "equal" => {
                 let children: Vec<Node> = node.children().filter(Node::is_element).collect();
                 if children.len() == 2 {
-                    Instruction::Equal(
+                    InstructionKind::Equal(
                         Box::new(Instruction::new(children[0])?),
                         Box::new(Instruction::new(children[1])?),
                     )
@@ -116,7 +343,7 @@ impl Instruction {
             "greater" => {
                 let children: Vec<Node> = node.children().filter(Node::is_element).collect();
                 if children.len() == 2 {
-                    Instruction::Greater(
+                    InstructionKind::Greater(
                         Box::new(Instruction::new(children[0])?),
                         Box::new(Instruction::new(children[1])?),
                     )
@@ -127,7 +354,7 @@ impl Instruction {
             "lower" => {
                 let children: Vec<Node> = node.children().filter(Node::is_element).collect();
                 if children.len() == 2 {
-                    Instruction::Lower(
+                    InstructionKind::Lower(
                         Box::new(Instruction::new(children[0])?),
                         Box::new(Instruction::new(children[1])?),
                     )
@@ -137,15 +364,15 @@ impl Instruction {
             }
             "call" => {
                 if let Some(function) = node.attribute("function") {
-                    Instruction::CallNamed(
-                        String::from(function),
+                    InstructionKind::CallNamed(
+                        Name::new(function),
                         Instruction::from_children(
                             util::find_node(&node, "arguments")
                                 .ok_or(MissingChild("call", "arguments"))?,
                         )?,
                     )
                 } else {
-                    Instruction::Call(
+                    InstructionKind::Call(
                         Box::new(Instruction::new(
                             node.first_element_child()
                                 .ok_or(MissingChild("call", "function"))?,
@@ -157,13 +384,13 @@ impl Instruction {
                     )
                 }
             }
-            "return" => Instruction::Return(Box::new(Instruction::new(
+            "return" => InstructionKind::Return(Box::new(Instruction::new(
                 node.first_element_child()
                     .ok_or(MissingChild("return", "value"))?,
             )?)),
             "if" => {
                 if let Some(else_node) = node.children().find(|n| util::tag_name(n) == "else") {
-                    Instruction::IfElse(
+                    InstructionKind::IfElse(
                         Box::new(Instruction::new(
                             node.first_element_child()
                                 .ok_or(MissingChild("if", "condition"))?,
@@ -174,7 +401,7 @@ impl Instruction {
                         Instruction::from_children(else_node)?,
                     )
                 } else {
-                    Instruction::If(
+                    InstructionKind::If(
                         Box::new(Instruction::new(
                             node.first_element_child()
                                 .ok_or(MissingChild("if", "condition"))?,
@@ -185,8 +412,8 @@ impl Instruction {
                     )
                 }
             }
-            "for" => Instruction::For {
-                variable: String::from(
+            "for" => InstructionKind::For {
+                variable: Name::new(
                     node.attribute("variable")
                         .ok_or(MissingAttribute("for", "variable"))?,
                 ),
@@ -208,66 +435,114 @@ impl Instruction {
                 body: Instruction::from_children(
                     util::find_node(&node, "do").ok_or(MissingChild("for", "do"))?,
                 )?,
+                label: node.attribute("label").map(String::from),
             },
-            "each" => Instruction::Each(
-                String::from(
+            "each" => InstructionKind::Each {
+                variable: Name::new(
                     node.attribute("variable")
                         .ok_or(MissingAttribute("each", "variable"))?,
                 ),
-                Box::new(Instruction::new(
+                key: node.attribute("key").map(Name::new),
+                array: Box::new(Instruction::new(
                     node.first_element_child()
                         .ok_or(MissingChild("each", "array"))?,
                 )?),
-                Instruction::from_children(
-                    util::find_node(&node, "do").ok_or(MissingChild("each", "from"))?,
+                body: Instruction::from_children(
+                    util::find_node(&node, "do").ok_or(MissingChild("each", "do"))?,
                 )?,
-            ),
-            "while" => Instruction::While(
+                label: node.attribute("label").map(String::from),
+            },
+            "while" => InstructionKind::While(
                 Box::new(Instruction::new(
                     node.first_element_child()
                         .ok_or(MissingChild("while", "condition"))?,
                 )?),
                 Instruction::from_children(
-                    util::find_node(&node, "do").ok_or(MissingChild("while", "from"))?,
+                    util::find_node(&node, "do").ok_or(MissingChild("while", "do"))?,
                 )?,
+                node.attribute("label").map(String::from),
             ),
-            "handle" => Instruction::Handle(
-                Instruction::from_children(
-                    util::find_node(&node, "try").ok_or(MissingChild("handle", "try"))?,
-                )?,
-                Instruction::from_children(
-                    util::find_node(&node, "catch").ok_or(MissingChild("handle", "try"))?,
-                )?,
-                util::find_node(&node, "catch")
-                    .ok_or(MissingChild("handle", "try"))?
-                    .attribute("variable")
-                    .ok_or(MissingAttribute("catch", "variable"))?
-                    .to_string(),
+            "break" => InstructionKind::Break(node.attribute("label").map(String::from)),
+            "continue" => InstructionKind::Continue(node.attribute("label").map(String::from)),
+            "handle" => {
+                let catches = node
+                    .children()
+                    .filter(|n| util::tag_name(n) == "catch")
+                    .map(Catch::new)
+                    .collect::<Result<Vec<Catch>, Error>>()?;
+                let finally = util::find_node(&node, "finally");
+                if catches.is_empty() && finally.is_none() {
+                    Err(MissingChild("handle", "catch"))?
+                }
+                InstructionKind::Handle {
+                    body: Instruction::from_children(
+                        util::find_node(&node, "try").ok_or(MissingChild("handle", "try"))?,
+                    )?,
+                    catches,
+                    finally: match finally {
+                        Some(n) => Instruction::from_children(n)?,
+                        None => Vec::new(),
+                    },
+                }
+            }
+            "throw" => InstructionKind::Throw(
+                node.attribute("type").map(String::from),
+                Box::new(Instruction::new(
+                    node.first_element_child()
+                        .ok_or(MissingChild("throw", "value"))?,
+                )?),
+            ),
+            "function" => InstructionKind::Function(
+                Name::new(node.attribute("name").ok_or(Unnamed("function"))?),
+                Rc::new(Function::from(&node)?),
             ),
-            tag => Err(format!("unknown tag '{}'", tag))?,
+            "lambda" => InstructionKind::Lambda(Rc::new(Function::from(&node)?)),
+            tag => Err(UnknownTag(String::from(tag)))?,
         })
     }
+}
 
-    pub fn from_children(node: Node) -> Result<Vec<Instruction>, Box<dyn Error>> {
-        node.children()
-            .filter(Node::is_element)
-            .map(Instruction::new)
-            .collect()
+impl Instruction {
+    pub fn integer(val: Value) -> Result<Value, Error> {
+        Ok(Value::Integer(match val {
+            Value::Integer(i) => i,
+            Value::Real(f) => f as i64,
+            Value::Boolean(b) => b as i64,
+            Value::String(s) => s.parse().map_err(|_| InvalidNumber(s))?,
+            _ => Err(InvalidValue("integer"))?,
+        }))
+    }
+
+    pub fn real(val: Value) -> Result<Value, Error> {
+        Ok(Value::Real(match val {
+            Value::Integer(i) => i as f64,
+            Value::Real(f) => f,
+            Value::Boolean(b) => b as i64 as f64,
+            Value::String(s) => s.parse().map_err(|_| InvalidNumber(s))?,
+            _ => Err(InvalidValue("real"))?,
+        }))
     }
 
-    fn add(vals: Vec<Value>) -> Result<Value, Box<dyn Error>> {
+    pub fn string(val: Value) -> Result<Value, Error> {
+        Ok(Value::String(match val {
+            v @ (Value::Integer(_)
+            | Value::Real(_)
+            | Value::String(_)
+            | Value::Boolean(_)
+            | Value::Null) => v.to_string(),
+            _ => Err(InvalidValue("string"))?,
+        }))
+    }
+
+    pub fn add(vals: Vec<Value>) -> Result<Value, Error> {
         if vals.iter().all(|v| matches!(v, Value::Integer(_))) {
-            Ok(Value::Integer(
-                vals.iter()
-                    .map(|v| {
-                        if let Value::Integer(i) = v {
-                            Ok(i)
-                        } else {
-                            Err(InvalidValue("add"))?
-                        }
-                    })
-                    .sum::<Result<i64, Box<dyn Error>>>()?,
-            ))
+            Ok(Value::Integer(vals.iter().try_fold(
+                0i64,
+                |sum, v| match v {
+                    Value::Integer(i) => sum.checked_add(*i).ok_or(Overflow("add")),
+                    _ => Err(InvalidValue("add")),
+                },
+            )?))
         } else if vals
             .iter()
             .all(|v| matches!(v, Value::Integer(_)) || matches!(v, Value::Real(_)))
@@ -283,7 +558,7 @@ impl Instruction {
                             Err(InvalidValue("add"))?
                         }
                     })
-                    .sum::<Result<f64, Box<dyn Error>>>()?,
+                    .sum::<Result<f64, Error>>()?,
             ))
         } else if vals.iter().all(|v| {
             matches!(v, Value::Integer(_))
@@ -303,7 +578,7 @@ impl Instruction {
                             Err(InvalidValue("add"))?
                         })
                     })
-                    .collect::<Result<Vec<String>, Box<dyn Error>>>()?
+                    .collect::<Result<Vec<String>, Error>>()?
                     .join(""),
             ))
         } else {
@@ -311,26 +586,12 @@ impl Instruction {
         }
     }
 
-    fn subtract(vals: Vec<Value>) -> Result<Value, Box<dyn Error>> {
+    pub fn subtract(vals: Vec<Value>) -> Result<Value, Error> {
         Ok(if vals.iter().all(|v| matches!(v, Value::Integer(_))) {
-            let first = match vals.first().ok_or(BadChildCount("subtract", 0usize))? {
-                Value::Integer(i) => i,
-                _ => Err(InvalidValue("subtract"))?,
-            };
-            Value::Integer(
-                *first
-                    - vals
-                        .iter()
-                        .skip(1)
-                        .map(|v| {
-                            if let Value::Integer(i) = v {
-                                Ok(i)
-                            } else {
-                                Err(InvalidValue("subtract"))?
-                            }
-                        })
-                        .sum::<Result<i64, Box<dyn Error>>>()?,
-            )
+            let vals = Instruction::integers("subtract", vals)?;
+            Value::Integer(Instruction::fold(vals, |a, b| {
+                Ok(a.checked_sub(b).ok_or(Overflow("subtract"))?)
+            })?)
         } else if vals
             .iter()
             .all(|v| matches!(v, Value::Integer(_)) || matches!(v, Value::Real(_)))
@@ -352,26 +613,22 @@ impl Instruction {
                                 _ => Err(InvalidValue("subtract"))?,
                             })
                         })
-                        .sum::<Result<f64, Box<dyn Error>>>()?,
+                        .sum::<Result<f64, Error>>()?,
             )
         } else {
             Err(InvalidValue("subtract"))?
         })
     }
 
-    fn multiply(vals: Vec<Value>) -> Result<Value, Box<dyn Error>> {
+    pub fn multiply(vals: Vec<Value>) -> Result<Value, Error> {
         if vals.iter().all(|v| matches!(v, Value::Integer(_))) {
-            Ok(Value::Integer(
-                vals.iter()
-                    .map(|v| {
-                        if let Value::Integer(i) = v {
-                            Ok(i)
-                        } else {
-                            Err(InvalidValue("multiply"))?
-                        }
-                    })
-                    .product::<Result<i64, Box<dyn Error>>>()?,
-            ))
+            Ok(Value::Integer(vals.iter().try_fold(
+                1i64,
+                |product, v| match v {
+                    Value::Integer(i) => product.checked_mul(*i).ok_or(Overflow("multiply")),
+                    _ => Err(InvalidValue("multiply")),
+                },
+            )?))
         } else if vals
             .iter()
             .all(|v| matches!(v, Value::Integer(_)) || matches!(v, Value::Real(_)))
@@ -385,14 +642,14 @@ impl Instruction {
                             _ => Err(InvalidValue("multiply"))?,
                         })
                     })
-                    .product::<Result<f64, Box<dyn Error>>>()?,
+                    .product::<Result<f64, Error>>()?,
             ))
         } else {
             Err(InvalidValue("multiply"))?
         }
     }
 
-    fn divide(vals: Vec<Value>) -> Result<Value, Box<dyn Error>> {
+    pub fn divide(vals: Vec<Value>) -> Result<Value, Error> {
         if vals
             .iter()
             .all(|v| matches!(v, Value::Integer(_)) || matches!(v, Value::Real(_)))
@@ -408,325 +665,618 @@ impl Instruction {
                         .iter()
                         .skip(1)
                         .map(|val| {
-                            Ok(match val {
-                                Value::Integer(v) => 1.0 / (*v as f64),
-                                Value::Real(v) => 1.0 / *v,
+                            let divisor = match val {
+                                Value::Integer(v) => *v as f64,
+                                Value::Real(v) => *v,
                                 _ => Err(InvalidValue("divide"))?,
-                            })
+                            };
+                            if divisor == 0.0 {
+                                Err(DivisionByZero("divide"))?
+                            }
+                            Ok(1.0 / divisor)
                         })
-                        .product::<Result<f64, Box<dyn Error>>>()?,
+                        .product::<Result<f64, Error>>()?,
             ))
         } else {
             Err(InvalidValue("divide"))?
         }
     }
 
-    fn and(vals: Vec<Value>) -> Value {
-        Value::Integer(if vals.iter().all(Value::to_bool) {
-            1
-        } else {
-            0
+    pub fn modulo(vals: Vec<Value>) -> Result<Value, Error> {
+        Ok(match Instruction::numbers("modulo", vals)? {
+            Numbers::Integers(vals) => Value::Integer(Instruction::fold(vals, |a, b| {
+                if b == 0 {
+                    Err(DivisionByZero("modulo"))?
+                }
+                Ok(a.checked_rem(b).ok_or(Overflow("modulo"))?)
+            })?),
+            Numbers::Reals(vals) => Value::Real(Instruction::fold(vals, |a, b| {
+                if b == 0.0 {
+                    Err(DivisionByZero("modulo"))?
+                }
+                Ok(a % b)
+            })?),
         })
     }
 
-    fn or(vals: Vec<Value>) -> Value {
-        Value::Integer(if vals.iter().any(Value::to_bool) {
-            1
-        } else {
-            0
+    /// Divides values, rounding the quotient toward zero.
+    pub fn int_divide(vals: Vec<Value>) -> Result<Value, Error> {
+        Ok(match Instruction::numbers("int-divide", vals)? {
+            Numbers::Integers(vals) => Value::Integer(Instruction::fold(vals, |a, b| {
+                if b == 0 {
+                    Err(DivisionByZero("int-divide"))?
+                }
+                Ok(a.checked_div(b).ok_or(Overflow("int-divide"))?)
+            })?),
+            Numbers::Reals(vals) => Value::Real(Instruction::fold(vals, |a, b| {
+                if b == 0.0 {
+                    Err(DivisionByZero("int-divide"))?
+                }
+                Ok((a / b).trunc())
+            })?),
         })
     }
 
-    fn compare(v1: Value, v2: Value) -> Result<i64, Box<dyn Error>> {
-        use std::cmp::Ordering;
-        match v1 {
-            Value::Integer(i1) => match v2 {
-                Value::Integer(i2) => Ok(i1 - i2),
-                Value::Real(f2) => Ok(
-                    match (i1 as f64).partial_cmp(&f2).ok_or(IncompatibleValues)? {
-                        Ordering::Less => -1,
-                        Ordering::Equal => 0,
-                        Ordering::Greater => 1,
-                    },
-                ),
-                _ => Err(IncompatibleValues)?,
-            },
-            Value::Real(f1) => match v2 {
-                Value::Integer(i2) => Ok(
-                    match f1.partial_cmp(&(i2 as f64)).ok_or(IncompatibleValues)? {
-                        Ordering::Less => -1,
-                        Ordering::Equal => 0,
-                        Ordering::Greater => 1,
-                    },
-                ),
-                Value::Real(f2) => Ok(match f1.partial_cmp(&f2).ok_or(IncompatibleValues)? {
-                    Ordering::Less => -1,
-                    Ordering::Equal => 0,
-                    Ordering::Greater => 1,
-                }),
-                _ => Err(IncompatibleValues)?,
-            },
-            Value::String(s1) => {
-                if let Value::String(s2) = v2 {
-                    Ok(match s1.cmp(&s2) {
-                        Ordering::Less => -1,
-                        Ordering::Equal => 0,
-                        Ordering::Greater => 1,
-                    })
-                } else {
-                    Err(IncompatibleValues)?
+    /// Raises the first value to the following ones in turn. Integers
+    /// may not be raised to negative powers.
+    pub fn power(vals: Vec<Value>) -> Result<Value, Error> {
+        Ok(match Instruction::numbers("power", vals)? {
+            Numbers::Integers(vals) => Value::Integer(Instruction::fold(vals, |a, b| {
+                if b < 0 {
+                    Err(InvalidValue("power"))?
                 }
+                Ok(u32::try_from(b)
+                    .ok()
+                    .and_then(|b| a.checked_pow(b))
+                    .ok_or(Overflow("power"))?)
+            })?),
+            Numbers::Reals(vals) => Value::Real(Instruction::fold(vals, |a, b| {
+                if a == 0.0 && b < 0.0 {
+                    Err(DivisionByZero("power"))?
+                }
+                Ok(a.powf(b))
+            })?),
+        })
+    }
+
+    pub fn negate(val: Value) -> Result<Value, Error> {
+        match val {
+            Value::Integer(i) => Ok(Value::Integer(i.checked_neg().ok_or(Overflow("negate"))?)),
+            Value::Real(f) => Ok(Value::Real(-f)),
+            _ => Err(InvalidValue("negate"))?,
+        }
+    }
+
+    pub fn bit_and(vals: Vec<Value>) -> Result<Value, Error> {
+        let vals = Instruction::integers("bit-and", vals)?;
+        Ok(Value::Integer(Instruction::fold(vals, |a, b| Ok(a & b))?))
+    }
+
+    pub fn bit_or(vals: Vec<Value>) -> Result<Value, Error> {
+        let vals = Instruction::integers("bit-or", vals)?;
+        Ok(Value::Integer(Instruction::fold(vals, |a, b| Ok(a | b))?))
+    }
+
+    pub fn bit_xor(vals: Vec<Value>) -> Result<Value, Error> {
+        let vals = Instruction::integers("bit-xor", vals)?;
+        Ok(Value::Integer(Instruction::fold(vals, |a, b| Ok(a ^ b))?))
+    }
+
+    /// Shifts the bits of the first value by the following ones in
+    /// turn, each between 0 and 63.
+    pub fn shift_left(vals: Vec<Value>) -> Result<Value, Error> {
+        let vals = Instruction::integers("shift-left", vals)?;
+        Ok(Value::Integer(Instruction::fold(vals, |a, b| {
+            Ok(u32::try_from(b)
+                .ok()
+                .and_then(|b| a.checked_shl(b))
+                .ok_or(InvalidValue("shift-left"))?)
+        })?))
+    }
+
+    /// Shifts the bits of the first value by the following ones in
+    /// turn, keeping its sign.
+    pub fn shift_right(vals: Vec<Value>) -> Result<Value, Error> {
+        let vals = Instruction::integers("shift-right", vals)?;
+        Ok(Value::Integer(Instruction::fold(vals, |a, b| {
+            Ok(u32::try_from(b)
+                .ok()
+                .and_then(|b| a.checked_shr(b))
+                .ok_or(InvalidValue("shift-right"))?)
+        })?))
+    }
+
+    /// Operands of an arithmetic operation, which are integers if they
+    /// all are, and reals otherwise, as with `add`.
+    fn numbers(tag: &'static str, vals: Vec<Value>) -> Result<Numbers, Error> {
+        if vals.is_empty() {
+            Err(BadChildCount(tag, 0))?
+        }
+        if vals.iter().all(|v| matches!(v, Value::Integer(_))) {
+            Ok(Numbers::Integers(Instruction::integers(tag, vals)?))
+        } else {
+            vals.into_iter()
+                .map(|val| match val {
+                    Value::Integer(v) => Ok(v as f64),
+                    Value::Real(v) => Ok(v),
+                    _ => Err(InvalidValue(tag))?,
+                })
+                .collect::<Result<Vec<f64>, Error>>()
+                .map(Numbers::Reals)
+        }
+    }
+
+    /// Operands of a bitwise operation, which must all be integers.
+    fn integers(tag: &'static str, vals: Vec<Value>) -> Result<Vec<i64>, Error> {
+        if vals.is_empty() {
+            Err(BadChildCount(tag, 0))?
+        }
+        vals.into_iter()
+            .map(|val| match val {
+                Value::Integer(v) => Ok(v),
+                _ => Err(InvalidValue(tag))?,
+            })
+            .collect()
+    }
+
+    /// Combines operands from the left, of which there is at least one.
+    fn fold<T: Copy>(vals: Vec<T>, op: impl Fn(T, T) -> Result<T, Error>) -> Result<T, Error> {
+        vals[1..].iter().try_fold(vals[0], |acc, val| op(acc, *val))
+    }
+
+    pub fn and(vals: Vec<Value>) -> Value {
+        Value::Boolean(vals.iter().all(Value::to_bool))
+    }
+
+    pub fn or(vals: Vec<Value>) -> Value {
+        Value::Boolean(vals.iter().any(Value::to_bool))
+    }
+
+    pub fn equals(v1: Value, v2: Value) -> Result<bool, Error> {
+        match (v1, v2) {
+            (Value::Boolean(b1), Value::Boolean(b2)) => Ok(b1 == b2),
+            (Value::Null, Value::Null) => Ok(true),
+            (Value::Null, _) | (_, Value::Null) => Ok(false),
+            (v1, v2) => Ok(Instruction::compare(v1, v2)?.is_eq()),
+        }
+    }
+
+    pub fn compare(v1: Value, v2: Value) -> Result<Ordering, Error> {
+        Ok(match (v1, v2) {
+            (Value::Integer(i1), Value::Integer(i2)) => i1.cmp(&i2),
+            (Value::Integer(i1), Value::Real(f2)) => {
+                (i1 as f64).partial_cmp(&f2).ok_or(IncompatibleValues)?
+            }
+            (Value::Real(f1), Value::Integer(i2)) => {
+                f1.partial_cmp(&(i2 as f64)).ok_or(IncompatibleValues)?
             }
+            (Value::Real(f1), Value::Real(f2)) => f1.partial_cmp(&f2).ok_or(IncompatibleValues)?,
+            (Value::String(s1), Value::String(s2)) => s1.cmp(&s2),
             _ => Err(IncompatibleValues)?,
+        })
+    }
+
+    /// Name under which a called function is reported in error traces.
+    pub fn describe(&self) -> String {
+        match &self.kind {
+            InstructionKind::Value(name) => name.to_string(),
+            _ => String::from("<anonymous>"),
+        }
+    }
+
+    fn run_block(ins: &[Instruction], ctx: &mut Context) -> Result<(), Flow> {
+        for i in ins {
+            i.run(ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Runs one iteration of a loop, telling whether the loop goes on.
+    fn run_loop_body(
+        body: &[Instruction],
+        ctx: &mut Context,
+        label: &Option<String>,
+    ) -> Result<bool, Flow> {
+        match Instruction::run_block(body, ctx) {
+            Ok(()) => Ok(true),
+            Err(Flow::Break(target, _)) if target.is_none() || target == *label => Ok(false),
+            Err(Flow::Continue(target, _)) if target.is_none() || target == *label => Ok(true),
+            Err(flow) => Err(flow),
+        }
+    }
+
+    fn run_all(ins: &[Instruction], ctx: &mut Context) -> Result<Option<Vec<Value>>, Flow> {
+        ins.iter().map(|i| i.run(ctx)).collect()
+    }
+
+    pub fn run(&self, ctx: &mut Context) -> Result<Option<Value>, Flow> {
+        self.limited(ctx).map_err(|f| f.at(&self.location))
+    }
+
+    /// Executes the instruction within the limits of the sandbox.
+    fn limited(&self, ctx: &mut Context) -> Result<Option<Value>, Flow> {
+        ctx.runtime().step()?;
+        let value = self.execute(ctx)?;
+        if let Some(value) = &value {
+            ctx.runtime().sized(value)?;
+        }
+        Ok(value)
+    }
+
+    /// Executes the instruction. Nodes are executed by functions of
+    /// their own, as a frame of this one is kept on the stack for every
+    /// nested node, and would be as large as all of them together.
+    fn execute(&self, ctx: &mut Context) -> Result<Option<Value>, Flow> {
+        match &self.kind {
+            InstructionKind::Value(key) => match ctx.value(key) {
+                Some(value) => Ok(Some(value)),
+                None => Err(UnknownVariable(key.to_string()))?,
+            },
+            InstructionKind::Assign(key, target, ins) => {
+                Instruction::run_assign(key, *target, ins, ctx)
+            }
+            InstructionKind::Boolean(b) => Ok(Some(Value::Boolean(*b))),
+            InstructionKind::Null => Ok(Some(Value::Null)),
+            InstructionKind::Integer(i) => Ok(Some(Value::Integer(*i))),
+            InstructionKind::IntegerCast(ins) => {
+                Instruction::run_unary(ins, ctx, "integer", Instruction::integer)
+            }
+            InstructionKind::Real(f) => Ok(Some(Value::Real(*f))),
+            InstructionKind::RealCast(ins) => {
+                Instruction::run_unary(ins, ctx, "real", Instruction::real)
+            }
+            InstructionKind::String(val) => Ok(Some(Value::String(val.clone()))),
+            InstructionKind::StringCast(ins) => {
+                Instruction::run_unary(ins, ctx, "string", Instruction::string)
+            }
+            InstructionKind::Array(args) => Instruction::run_array(args, ctx),
+            InstructionKind::Map(entries) => Instruction::run_map(entries, ctx),
+            InstructionKind::Add(args) => {
+                Instruction::run_operation(args, ctx, "add", Instruction::add)
+            }
+            InstructionKind::Subtract(args) => {
+                Instruction::run_operation(args, ctx, "subtract", Instruction::subtract)
+            }
+            InstructionKind::Multiply(args) => {
+                Instruction::run_operation(args, ctx, "multiply", Instruction::multiply)
+            }
+            InstructionKind::Divide(args) => {
+                Instruction::run_operation(args, ctx, "divide", Instruction::divide)
+            }
+            InstructionKind::Modulo(args) => {
+                Instruction::run_operation(args, ctx, "modulo", Instruction::modulo)
+            }
+            InstructionKind::IntDivide(args) => {
+                Instruction::run_operation(args, ctx, "int-divide", Instruction::int_divide)
+            }
+            InstructionKind::Power(args) => {
+                Instruction::run_operation(args, ctx, "power", Instruction::power)
+            }
+            InstructionKind::Negate(arg) => {
+                Instruction::run_unary(arg, ctx, "negate", Instruction::negate)
+            }
+            InstructionKind::BitAnd(args) => {
+                Instruction::run_operation(args, ctx, "bit-and", Instruction::bit_and)
+            }
+            InstructionKind::BitOr(args) => {
+                Instruction::run_operation(args, ctx, "bit-or", Instruction::bit_or)
+            }
+            InstructionKind::BitXor(args) => {
+                Instruction::run_operation(args, ctx, "bit-xor", Instruction::bit_xor)
+            }
+            InstructionKind::ShiftLeft(args) => {
+                Instruction::run_operation(args, ctx, "shift-left", Instruction::shift_left)
+            }
+            InstructionKind::ShiftRight(args) => {
+                Instruction::run_operation(args, ctx, "shift-right", Instruction::shift_right)
+            }
+            InstructionKind::And(args) => {
+                Instruction::run_operation(args, ctx, "and", |vals| Ok(Instruction::and(vals)))
+            }
+            InstructionKind::Or(args) => {
+                Instruction::run_operation(args, ctx, "or", |vals| Ok(Instruction::or(vals)))
+            }
+            InstructionKind::Not(arg) => {
+                Instruction::run_unary(arg, ctx, "not", |val| Ok(Value::Boolean(!val.to_bool())))
+            }
+            InstructionKind::Equal(v1, v2) => {
+                Instruction::run_binary(v1, v2, ctx, "equal", |a, b| {
+                    Ok(Value::Boolean(Instruction::equals(a, b)?))
+                })
+            }
+            InstructionKind::Greater(v1, v2) => {
+                Instruction::run_binary(v1, v2, ctx, "greater", |a, b| {
+                    Ok(Value::Boolean(Instruction::compare(a, b)?.is_gt()))
+                })
+            }
+            InstructionKind::Lower(v1, v2) => {
+                Instruction::run_binary(v1, v2, ctx, "lower", |a, b| {
+                    Ok(Value::Boolean(Instruction::compare(a, b)?.is_lt()))
+                })
+            }
+            InstructionKind::Call(fct_ins, args) => self.run_call(fct_ins, args, ctx),
+            InstructionKind::CallNamed(fct_name, args) => self.run_call_named(fct_name, args, ctx),
+            InstructionKind::Return(ins) => Instruction::run_return(ins, ctx),
+            InstructionKind::If(cond, then) => Instruction::run_if(cond, then, &[], ctx),
+            InstructionKind::IfElse(cond, then, els) => Instruction::run_if(cond, then, els, ctx),
+            InstructionKind::For {
+                variable,
+                from,
+                to,
+                step,
+                body,
+                label,
+            } => Instruction::run_for(variable, [from, to, step], body, label, ctx),
+            InstructionKind::Each {
+                variable,
+                key,
+                array,
+                body,
+                label,
+            } => Instruction::run_each(variable, key, array, body, label, ctx),
+            InstructionKind::While(cond, body, label) => {
+                Instruction::run_while(cond, body, label, ctx)
+            }
+            InstructionKind::Break(label) => Err(Flow::Break(label.clone(), self.location.clone())),
+            InstructionKind::Continue(label) => {
+                Err(Flow::Continue(label.clone(), self.location.clone()))
+            }
+            InstructionKind::Handle {
+                body,
+                catches,
+                finally,
+            } => Instruction::run_handle(body, catches, finally, ctx),
+            InstructionKind::Throw(tag, ins) => Instruction::run_throw(tag, ins, ctx),
+            InstructionKind::Function(name, function) => {
+                let closure = Closure {
+                    function: Rc::clone(function),
+                    env: ctx.clone(),
+                };
+                ctx.assign(name, Value::Function(closure))?;
+                Ok(None)
+            }
+            InstructionKind::Lambda(function) => Ok(Some(Value::Function(Closure {
+                function: Rc::clone(function),
+                env: ctx.clone(),
+            }))),
+        }
+    }
+
+    fn run_assign(
+        key: &Name,
+        target: Target,
+        ins: &Instruction,
+        ctx: &mut Context,
+    ) -> Result<Option<Value>, Flow> {
+        let v = ins.run(ctx)?.ok_or(InvalidValue("assign"))?;
+        match target {
+            Target::Local => ctx.assign(key, v)?,
+            Target::Outer => ctx.assign_outer(key, v)?,
+            Target::Global => ctx.assign_global(key, v)?,
+        }
+        Ok(None)
+    }
+
+    fn run_unary(
+        arg: &Instruction,
+        ctx: &mut Context,
+        tag: &'static str,
+        op: fn(Value) -> Result<Value, Error>,
+    ) -> Result<Option<Value>, Flow> {
+        Ok(Some(op(arg.run(ctx)?.ok_or(InvalidValue(tag))?)?))
+    }
+
+    fn run_binary(
+        v1: &Instruction,
+        v2: &Instruction,
+        ctx: &mut Context,
+        tag: &'static str,
+        op: fn(Value, Value) -> Result<Value, Error>,
+    ) -> Result<Option<Value>, Flow> {
+        Ok(Some(op(
+            v1.run(ctx)?.ok_or(InvalidValue(tag))?,
+            v2.run(ctx)?.ok_or(InvalidValue(tag))?,
+        )?))
+    }
+
+    fn run_operation(
+        args: &[Instruction],
+        ctx: &mut Context,
+        tag: &'static str,
+        op: fn(Vec<Value>) -> Result<Value, Error>,
+    ) -> Result<Option<Value>, Flow> {
+        let vals = Instruction::run_all(args, ctx)?.ok_or(InvalidValue(tag))?;
+        Ok(Some(op(vals)?))
+    }
+
+    fn run_array(args: &[Instruction], ctx: &mut Context) -> Result<Option<Value>, Flow> {
+        Ok(Some(Value::Array(Rc::new(RefCell::new(
+            Instruction::run_all(args, ctx)?.ok_or(InvalidValue("array"))?,
+        )))))
+    }
+
+    fn run_map(
+        entries: &[(Instruction, Instruction)],
+        ctx: &mut Context,
+    ) -> Result<Option<Value>, Flow> {
+        let mut map = BTreeMap::new();
+        for (k, v) in entries {
+            let key = k.run(ctx)?.ok_or(InvalidValue("entry"))?;
+            map.insert(
+                Key::new(&key).ok_or(InvalidValue("entry"))?,
+                v.run(ctx)?.ok_or(InvalidValue("entry"))?,
+            );
         }
+        Ok(Some(Value::Map(Rc::new(RefCell::new(map)))))
     }
 
-    fn run_all(
-        ins: &Vec<Instruction>,
+    fn run_call(
+        &self,
+        fct_ins: &Instruction,
+        args: &[Instruction],
         ctx: &mut Context,
-        globals: &Context,
-    ) -> Result<Option<Vec<Value>>, Box<dyn Error>> {
-        ins.iter().map(|i| i.run(ctx, globals)).collect()
+    ) -> Result<Option<Value>, Flow> {
+        let vals = Instruction::run_all(args, ctx)?.ok_or(InvalidValue("call"))?;
+        let fct_val = fct_ins.run(ctx)?.ok_or(InvalidValue("call"))?;
+        Ok(Some(fct_val.call(
+            vals,
+            ctx.runtime(),
+            Some((&fct_ins.describe(), &self.location)),
+        )?))
     }
 
-    pub fn run(
+    fn run_call_named(
         &self,
+        fct_name: &Name,
+        args: &[Instruction],
         ctx: &mut Context,
-        globals: &Context,
-    ) -> Result<Option<Value>, Box<dyn Error>> {
-        Ok(if let None = ctx.value(&String::from("__return")) {
-            match self {
-                Instruction::Value(key) => {
-                    Some(match ctx.value(key).ok_or(UnknownVariable(key.clone()))? {
-                        Value::Array(vecrc) => Value::Array(Rc::clone(vecrc)),
-                        val => val.clone(),
-                    })
-                }
-                Instruction::Assign(key, ins) => {
-                    let v = ins.run(ctx, globals)?.ok_or(InvalidValue("assign"))?;
-                    ctx.assign(key.clone(), v);
-                    None
-                }
-                Instruction::Integer(val) => Some(Value::Integer(val.parse()?)),
-                Instruction::IntegerCast(ins) => Some(Value::Integer(
-                    match ins.run(ctx, globals)?.ok_or(InvalidValue("integer"))? {
-                        Value::Integer(i) => i,
-                        Value::Real(f) => f as i64,
-                        Value::String(s) => s.parse()?,
-                        _ => Err(InvalidValue("integer"))?,
-                    },
-                )),
-                Instruction::Real(val) => Some(Value::Real(val.parse()?)),
-                Instruction::RealCast(ins) => Some(Value::Real(
-                    match ins.run(ctx, globals)?.ok_or(InvalidValue("real"))? {
-                        Value::Integer(i) => i as f64,
-                        Value::Real(f) => f,
-                        Value::String(s) => s.parse()?,
-                        _ => Err(InvalidValue("real"))?,
-                    },
-                )),
-                Instruction::String(val) => Some(Value::String(val.clone())),
-                Instruction::StringCast(ins) => Some(Value::String(
-                    match ins.run(ctx, globals)?.ok_or(InvalidValue("string"))? {
-                        Value::Integer(i) => i.to_string(),
-                        Value::Real(f) => f.to_string(),
-                        Value::String(s) => s,
-                        _ => Err(InvalidValue("string"))?,
-                    },
-                )),
-                Instruction::Array(args) => Some(Value::Array(Rc::new(RefCell::new(
-                    Instruction::run_all(args, ctx, globals)?.ok_or(InvalidValue("array"))?,
-                )))),
-                Instruction::Add(args) => {
-                    let vals =
-                        Instruction::run_all(args, ctx, globals)?.ok_or(InvalidValue("add"))?;
-                    Some(Instruction::add(vals)?)
-                }
-                Instruction::Subtract(args) => {
-                    let vals = Instruction::run_all(args, ctx, globals)?
-                        .ok_or(InvalidValue("subtract"))?;
-                    Some(Instruction::subtract(vals)?)
-                }
-                Instruction::Multiply(args) => {
-                    let vals = Instruction::run_all(args, ctx, globals)?
-                        .ok_or(InvalidValue("multiply"))?;
-                    Some(Instruction::multiply(vals)?)
-                }
-                Instruction::Divide(args) => {
-                    let vals =
-                        Instruction::run_all(args, ctx, globals)?.ok_or(InvalidValue("divide"))?;
-                    Some(Instruction::divide(vals)?)
-                }
-                Instruction::And(args) => {
-                    let vals =
-                        Instruction::run_all(args, ctx, globals)?.ok_or(InvalidValue("and"))?;
-                    Some(Instruction::and(vals))
-                }
-                Instruction::Or(args) => {
-                    let vals =
-                        Instruction::run_all(args, ctx, globals)?.ok_or(InvalidValue("or"))?;
-                    Some(Instruction::or(vals))
-                }
-                Instruction::Not(arg) => Some(Value::Integer(
-                    if arg.run(ctx, globals)?.ok_or(InvalidValue("not"))?.to_bool() {
-                        0
-                    } else {
-                        1
-                    },
-                )),
-                Instruction::Equal(v1, v2) => Some(Value::Integer(
-                    if Instruction::compare(
-                        v1.run(ctx, globals)?.ok_or(InvalidValue("equal"))?,
-                        v2.run(ctx, globals)?.ok_or(InvalidValue("equal"))?,
-                    )? == 0
-                    {
-                        1
-                    } else {
-                        0
-                    },
-                )),
-                Instruction::Greater(v1, v2) => Some(Value::Integer(
-                    if Instruction::compare(
-                        v1.run(ctx, globals)?.ok_or(InvalidValue("greater"))?,
-                        v2.run(ctx, globals)?.ok_or(InvalidValue("greater"))?,
-                    )? > 0
-                    {
-                        1
-                    } else {
-                        0
-                    },
-                )),
-                Instruction::Lower(v1, v2) => Some(Value::Integer(
-                    if Instruction::compare(
-                        v1.run(ctx, globals)?.ok_or(InvalidValue("lower"))?,
-                        v2.run(ctx, globals)?.ok_or(InvalidValue("lower"))?,
-                    )? < 0
-                    {
-                        1
-                    } else {
-                        0
-                    },
-                )),
-                Instruction::Call(fct_ins, args) => {
-                    let vals =
-                        Instruction::run_all(args, ctx, globals)?.ok_or(InvalidValue("call"))?;
-                    let fct_val = fct_ins.run(ctx, globals)?.ok_or(InvalidValue("call"))?;
-                    if let Value::Function(f) = fct_val {
-                        let mut fun_ctx = Context::new(Some(globals));
-                        f.run(vals, &mut fun_ctx, globals)?
-                    } else if let Value::StdFunction(f) = fct_val {
-                        f(vals)?
-                    } else {
-                        Err(InvalidValue("call"))?
-                    }
-                }
-                Instruction::CallNamed(fct_name, args) => {
-                    let vals: Vec<Value> =
-                        Instruction::run_all(args, ctx, globals)?.ok_or(InvalidValue("call"))?;
-                    let fct_val = ctx
-                        .value(&fct_name)
-                        .ok_or(UnknownVariable(fct_name.clone()))?;
-                    if let Value::Function(f) = fct_val {
-                        let mut local = ctx.clone();
-                        f.run(vals, &mut local, globals)?
-                    } else if let Value::StdFunction(f) = fct_val {
-                        f(vals)?
-                    } else {
-                        Err(InvalidValue("call"))?
-                    }
-                }
-                Instruction::Return(ins) => {
-                    let v = ins.run(ctx, globals)?.ok_or(InvalidValue("return"))?;
-                    ctx.assign(String::from("__return"), v);
-                    None
-                }
-                Instruction::If(cond, then) => {
-                    if cond.run(ctx, globals)?.ok_or(InvalidValue("if"))?.to_bool() {
-                        for i in then {
-                            i.run(ctx, globals)?;
-                        }
-                    }
-                    None
-                }
-                Instruction::IfElse(cond, then, els) => {
-                    if cond.run(ctx, globals)?.ok_or(InvalidValue("if"))?.to_bool() {
-                        for i in then {
-                            i.run(ctx, globals)?;
-                        }
-                    } else {
-                        for i in els {
-                            i.run(ctx, globals)?;
-                        }
-                    }
-                    None
-                }
-                Instruction::For {
-                    variable,
-                    from,
-                    to,
-                    step,
-                    body,
-                } => {
-                    if let Value::Integer(f) = from.run(ctx, globals)?.ok_or(InvalidValue("for"))? {
-                        if let Value::Integer(t) =
-                            to.run(ctx, globals)?.ok_or(InvalidValue("for"))?
-                        {
-                            if let Value::Integer(s) =
-                                step.run(ctx, globals)?.ok_or(InvalidValue("for"))?
-                            {
-                                for i in (f..t).step_by(s.try_into()?) {
-                                    ctx.assign(variable.clone(), Value::Integer(i));
-                                    for ins in body {
-                                        ins.run(ctx, globals)?;
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    None
-                }
-                Instruction::Each(variable, array_ins, body) => {
-                    if let Value::Array(v) =
-                        array_ins.run(ctx, globals)?.ok_or(InvalidValue("each"))?
-                    {
-                        for i in v.borrow().iter() {
-                            ctx.assign(variable.clone(), i.clone());
-                            for ins in body {
-                                ins.run(ctx, globals)?;
-                            }
-                        }
-                    } else {
-                        Err(InvalidValue("each"))?
-                    }
-                    None
-                }
-                Instruction::While(cond, body) => {
-                    while cond
-                        .run(ctx, globals)?
-                        .ok_or(InvalidValue("while"))?
-                        .to_bool()
-                    {
-                        for ins in body {
-                            ins.run(ctx, globals)?;
-                        }
-                    }
-                    None
-                }
-                Instruction::Handle(try_block, catch_block, variable) => {
-                    for ins in try_block {
-                        if let Err(e) = ins.run(ctx, globals) {
-                            ctx.assign(variable.clone(), Value::String(e.to_string()));
-                            for ins in catch_block {
-                                ins.run(ctx, globals)?;
-                            }
+    ) -> Result<Option<Value>, Flow> {
+        let vals: Vec<Value> = Instruction::run_all(args, ctx)?.ok_or(InvalidValue("call"))?;
+        let fct_val = ctx
+            .value(fct_name)
+            .ok_or(UnknownVariable(fct_name.to_string()))?;
+        Ok(Some(fct_val.call(
+            vals,
+            ctx.runtime(),
+            Some((fct_name, &self.location)),
+        )?))
+    }
+
+    fn run_return(ins: &Instruction, ctx: &mut Context) -> Result<Option<Value>, Flow> {
+        Err(Flow::Return(ins.run(ctx)?.ok_or(InvalidValue("return"))?))
+    }
+
+    fn run_if(
+        cond: &Instruction,
+        then: &[Instruction],
+        els: &[Instruction],
+        ctx: &mut Context,
+    ) -> Result<Option<Value>, Flow> {
+        if cond.run(ctx)?.ok_or(InvalidValue("if"))?.to_bool() {
+            Instruction::run_block(then, ctx)?;
+        } else {
+            Instruction::run_block(els, ctx)?;
+        }
+        Ok(None)
+    }
+
+    /// Runs a `for` node, given its `from`, `to` and `step` values.
+    fn run_for(
+        variable: &Name,
+        range: [&Instruction; 3],
+        body: &[Instruction],
+        label: &Option<String>,
+        ctx: &mut Context,
+    ) -> Result<Option<Value>, Flow> {
+        let [from, to, step] = range;
+        if let Value::Integer(f) = from.run(ctx)?.ok_or(InvalidValue("for"))? {
+            if let Value::Integer(t) = to.run(ctx)?.ok_or(InvalidValue("for"))? {
+                if let Value::Integer(s) = step.run(ctx)?.ok_or(InvalidValue("for"))? {
+                    let s = usize::try_from(s)
+                        .ok()
+                        .filter(|s| *s > 0)
+                        .ok_or(InvalidValue("for"))?;
+                    for i in (f..t).step_by(s) {
+                        ctx.assign(variable, Value::Integer(i))?;
+                        if !Instruction::run_loop_body(body, ctx, label)? {
                             break;
                         }
                     }
-                    None
                 }
             }
-        } else {
-            None
-        })
+        }
+        Ok(None)
+    }
+
+    fn run_each(
+        variable: &Name,
+        key: &Option<Name>,
+        array: &Instruction,
+        body: &[Instruction],
+        label: &Option<String>,
+        ctx: &mut Context,
+    ) -> Result<Option<Value>, Flow> {
+        // iterate over a snapshot, the body may modify the collection
+        let entries: Vec<(Value, Value)> = match array.run(ctx)?.ok_or(InvalidValue("each"))? {
+            Value::Array(v) => v
+                .borrow()
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (Value::Integer(i as i64), v.clone()))
+                .collect(),
+            Value::Map(m) => m
+                .borrow()
+                .iter()
+                .map(|(k, v)| (Value::from(k.clone()), v.clone()))
+                .collect(),
+            _ => Err(InvalidValue("each"))?,
+        };
+        for (k, v) in entries {
+            if let Some(key) = key {
+                ctx.assign(key, k)?;
+            }
+            ctx.assign(variable, v)?;
+            if !Instruction::run_loop_body(body, ctx, label)? {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    fn run_while(
+        cond: &Instruction,
+        body: &[Instruction],
+        label: &Option<String>,
+        ctx: &mut Context,
+    ) -> Result<Option<Value>, Flow> {
+        while cond.run(ctx)?.ok_or(InvalidValue("while"))?.to_bool() {
+            if !Instruction::run_loop_body(body, ctx, label)? {
+                break;
+            }
+        }
+        Ok(None)
+    }
+
+    fn run_handle(
+        body: &[Instruction],
+        catches: &[Catch],
+        finally: &[Instruction],
+        ctx: &mut Context,
+    ) -> Result<Option<Value>, Flow> {
+        let result = match Instruction::run_block(body, ctx) {
+            Err(Flow::Error(e)) => match catches.iter().find(|c| c.matches(&e)) {
+                Some(catch) => {
+                    let caught = match &catch.variable {
+                        Some(variable) => ctx.assign(variable, Catch::value(&e)),
+                        None => Ok(()),
+                    };
+                    caught
+                        .map_err(Flow::from)
+                        .and_then(|()| Instruction::run_block(&catch.body, ctx))
+                }
+                None => Err(Flow::Error(e)),
+            },
+            other => other,
+        };
+        // exiting skips finally blocks as well as catch blocks
+        if matches!(&result, Err(Flow::Error(e)) if !e.catchable()) {
+            return result.map(|()| None);
+        }
+        // anything leaving the finally block takes precedence
+        // over the outcome of the try and catch blocks
+        Instruction::run_block(finally, ctx)?;
+        result?;
+        Ok(None)
+    }
+
+    fn run_throw(
+        tag: &Option<String>,
+        ins: &Instruction,
+        ctx: &mut Context,
+    ) -> Result<Option<Value>, Flow> {
+        Err(Raised(
+            ins.run(ctx)?.ok_or(InvalidValue("throw"))?,
+            tag.clone(),
+        ))?
     }
 }
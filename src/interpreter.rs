@@ -0,0 +1,280 @@
+use super::error::{
+    Error,
+    ErrorKind::{CyclicImport, InaccessibleFile, InvalidValue, UnknownVariable},
+};
+use super::instruction::Instruction;
+use super::program::{Global, Import};
+use super::value::{Closure, Native};
+use super::{directory, read, vm, Context, Name, Options, Value};
+use roxmltree::Document;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Main block of a loaded program.
+pub(crate) enum Main {
+    Tree(Vec<Instruction>),
+    Compiled(vm::Closure),
+}
+
+/// Functions defined by a program, in the scope they were defined in.
+struct Module {
+    name: Option<String>,
+    functions: Vec<Name>,
+    scope: Context,
+}
+
+/// Interpreter embedded in a host program, which may share variables
+/// and functions with the programs it runs.
+///
+/// The functions of every program loaded or run are kept, so that
+/// they can be called from the host afterwards.
+///
+/// Dropping the interpreter clears what programs and the host defined,
+/// so functions taken out of it should not be called afterwards.
+/// Functions defined while a program runs, such as in the body of
+/// another function, are freed along with the scope they are defined
+/// in, unless stored in an enclosing scope or in an array or map that
+/// they can see, which they would keep alive.
+pub struct Interpreter {
+    options: Options,
+    globals: Context,
+    /// Imported programs, by canonical path.
+    modules: HashMap<PathBuf, Rc<Module>>,
+    /// Programs being imported, to detect cycles.
+    loading: Vec<PathBuf>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
+    }
+}
+
+/// Compiled functions refer back to the scope they are defined in, as
+/// may functions stored in an enclosing scope, so scopes are cleared
+/// for both to be freed.
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        for module in self.modules.values() {
+            module.scope.clear();
+        }
+        self.globals.clear();
+    }
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Options::new().interpreter()
+    }
+
+    pub(crate) fn with_globals(options: Options, globals: Context) -> Interpreter {
+        Interpreter {
+            options,
+            globals,
+            modules: HashMap::new(),
+            loading: Vec::new(),
+        }
+    }
+
+    /// Scope of the standard library and of what the host defines.
+    pub(crate) fn globals(&self) -> &Context {
+        &self.globals
+    }
+
+    /// Makes programs read what the `input` function gives from
+    /// `input` instead of the standard input.
+    pub fn input(&mut self, input: impl Read + 'static) -> &mut Interpreter {
+        self.globals.runtime().input.replace(Box::new(input));
+        self
+    }
+
+    /// Makes programs write what they print to `output` instead of
+    /// the standard output.
+    pub fn output(&mut self, output: impl Write + 'static) -> &mut Interpreter {
+        self.globals.runtime().output.replace(Box::new(output));
+        self
+    }
+
+    /// Makes programs write what `print-error` prints to `error`
+    /// instead of the standard error.
+    pub fn error(&mut self, error: impl Write + 'static) -> &mut Interpreter {
+        self.globals.runtime().error.replace(Box::new(error));
+        self
+    }
+
+    /// Makes a host function callable by programs under `name`.
+    pub fn register<F>(&mut self, name: &str, function: F) -> Result<(), Error>
+    where
+        F: Fn(Vec<Value>) -> Result<Value, Error> + 'static,
+    {
+        self.set(name, Value::Native(Native(Rc::new(function))))
+    }
+
+    /// Defines a global variable, visible from every program, which
+    /// fails for constants declared by programs.
+    pub fn set(&mut self, name: &str, value: impl Into<Value>) -> Result<(), Error> {
+        self.globals.assign(&Name::new(name), value.into())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.globals.value(&Name::new(name))
+    }
+
+    /// Calls a function defined by a program, by the host, or by the
+    /// standard library.
+    pub fn call(&self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        let function = self
+            .get(name)
+            .ok_or_else(|| UnknownVariable(String::from(name)))?;
+        self.globals.runtime().start();
+        function.call(args, self.globals.runtime(), None)
+    }
+
+    /// Defines the functions of a program without running it.
+    pub fn load(&mut self, program: &str) -> Result<(), Error> {
+        self.globals.runtime().start();
+        self.define(program, Path::new(""), self.globals.clone())
+            .map(|_| ())
+    }
+
+    /// Defines the functions of a program file, whose imports are
+    /// relative to it, without running it.
+    pub fn load_file(&mut self, filename: &str) -> Result<(), Error> {
+        self.globals.runtime().start();
+        self.define_file(filename, self.globals.clone()).map(|_| ())
+    }
+
+    /// Defines the functions of a program and runs it, giving its
+    /// exit status.
+    pub fn run(&mut self, program: &str) -> Result<i32, Error> {
+        self.globals.runtime().start();
+        let defined = self.define(program, Path::new(""), self.globals.clone());
+        self.start(defined.map(|(main, _)| main))
+    }
+
+    /// Runs a program file, whose imports are relative to it.
+    pub fn run_file(&mut self, filename: &str) -> Result<i32, Error> {
+        self.globals.runtime().start();
+        let defined = self.define_file(filename, self.globals.clone());
+        self.start(defined)
+    }
+
+    /// Runs the main block of a program, once defined, which may also
+    /// have exited while setting its variables.
+    fn start(&self, main: Result<Main, Error>) -> Result<i32, Error> {
+        let result = main.and_then(|main| match main {
+            Main::Tree(main) => {
+                let mut ctx = Context::new(Some(&self.globals));
+                main.iter()
+                    .find_map(|ins| ins.run(&mut ctx).err())
+                    .map_or(Ok(()), |flow| flow.escaped().map(|_| ()))
+            }
+            Main::Compiled(main) => main.call(Vec::new()).map(|_| ()),
+        });
+        match result {
+            Ok(()) => Ok(0),
+            Err(e) => e.exit_code().ok_or(e),
+        }
+    }
+
+    /// Defines the functions of a program file in `scope`.
+    pub(crate) fn define_file(&mut self, filename: &str, scope: Context) -> Result<Main, Error> {
+        let source = read(filename)?;
+        let path = Path::new(filename);
+        // the program itself may be imported back
+        let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+        self.loading.push(canonical);
+        let defined = self.define(&source, directory(path), scope);
+        self.loading.pop();
+        Ok(defined?.0)
+    }
+
+    /// Defines the functions of a program, and those it imports from
+    /// `dir`, in `scope`.
+    fn define(
+        &mut self,
+        program: &str,
+        dir: &Path,
+        mut scope: Context,
+    ) -> Result<(Main, Module), Error> {
+        let doc = Document::parse(program)?;
+        let program = self.options.load(&doc)?;
+        for import in &program.imports {
+            self.import(import, dir, &mut scope)
+                .map_err(|e| e.at(&import.location))?;
+        }
+        let module = Module {
+            name: program.name.clone(),
+            functions: program
+                .functions
+                .iter()
+                .map(|(name, _)| name.clone())
+                .collect(),
+            scope: scope.clone(),
+        };
+        if self.options.bytecode {
+            let main = vm::load(&program, &mut scope)?;
+            Interpreter::declare(&program.globals, &mut scope)?;
+            return Ok((Main::Compiled(main), module));
+        }
+        for (name, function) in program.functions {
+            let closure = Closure {
+                function,
+                env: scope.clone(),
+            };
+            scope.assign(&name, Value::Function(closure))?;
+        }
+        Interpreter::declare(&program.globals, &mut scope)?;
+        Ok((Main::Tree(program.main), module))
+    }
+
+    /// Sets the top-level variables of a program, in order, once its
+    /// functions are defined. Their values are computed by walking
+    /// their tree with either engine, as they only run once.
+    fn declare(globals: &[Global], scope: &mut Context) -> Result<(), Error> {
+        for global in globals {
+            let value = match global.value.run(scope) {
+                Ok(value) => value.ok_or(InvalidValue("global"))?,
+                Err(flow) => flow.escaped()?,
+            };
+            if global.constant {
+                scope.constant(&global.name, value);
+            } else {
+                scope.assign(&global.name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Defines the functions of an imported program in `scope`, under
+    /// its prefix. Each program is only loaded once, in a scope of its
+    /// own, and its main block is not run.
+    fn import(&mut self, import: &Import, dir: &Path, scope: &mut Context) -> Result<(), Error> {
+        let path = import.path(dir, &self.globals.runtime().sandbox)?;
+        if self.loading.contains(&path) {
+            Err(CyclicImport(import.src.clone()))?
+        }
+        let module = match self.modules.get(&path) {
+            Some(module) => Rc::clone(module),
+            None => {
+                let source =
+                    fs::read_to_string(&path).map_err(|_| InaccessibleFile(import.src.clone()))?;
+                self.loading.push(path.clone());
+                let defined =
+                    self.define(&source, directory(&path), Context::program(&self.globals));
+                self.loading.pop();
+                let module = Rc::new(defined?.1);
+                self.modules.insert(path, Rc::clone(&module));
+                module
+            }
+        };
+        for function in &module.functions {
+            let value = module.scope.value(function).expect("module function");
+            scope.assign(&import.name(module.name.as_deref(), function)?, value)?;
+        }
+        Ok(())
+    }
+}
@@ -1,58 +1,199 @@
-use std::error::Error;
 use std::fs;
+use std::path::Path;
 
 use roxmltree::Document;
 
+mod bytecode;
+mod check;
 mod context;
+mod dump;
 mod error;
+mod format;
+mod grammar;
 mod instruction;
+mod interpreter;
+mod location;
+mod name;
+mod optimize;
+mod program;
+pub mod repl;
+mod runtime;
+mod sandbox;
 mod stl;
 mod util;
 mod value;
+mod vm;
 
+use check::Checker;
 use context::Context;
-use error::{InvalidProgram, MissingChild, Unnamed};
+use error::ErrorKind::InaccessibleFile;
 use instruction::Instruction;
-use value::{Function, Value};
+use name::Name;
+use program::Program;
+use runtime::Runtime;
 
-pub fn run_file(filename: &str) -> Result<(), Box<dyn Error>> {
-    let contents = fs::read_to_string(filename)?;
-    run(contents)
+pub use check::{Diagnostic, DiagnosticKind};
+pub use error::{Category, Error, ErrorKind, Frame};
+pub use interpreter::Interpreter;
+pub use location::Location;
+pub use runtime::Buffer;
+pub use sandbox::Sandbox;
+pub use value::{Key, Value};
+
+/// Settings for parsing and running programs.
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    strict: bool,
+    bytecode: bool,
+    optimize: bool,
+    args: Vec<String>,
+    sandbox: Sandbox,
 }
 
-pub fn run(program: String) -> Result<(), Box<dyn Error>> {
-    let doc = Document::parse(&program)?;
-    let root = doc.root();
+impl Options {
+    pub fn new() -> Options {
+        Options::default()
+    }
+
+    /// Rejects programs which do not follow the grammar exactly,
+    /// instead of ignoring what is not understood.
+    pub fn strict(&mut self, strict: bool) -> &mut Options {
+        self.strict = strict;
+        self
+    }
+
+    /// Compiles programs to bytecode run by a stack machine, instead
+    /// of walking their tree. Both give the same results.
+    pub fn bytecode(&mut self, bytecode: bool) -> &mut Options {
+        self.bytecode = bytecode;
+        self
+    }
 
-    let mut ctx = Context::new(None);
-    stl::inject_all(&mut ctx);
+    /// Simplifies programs before running them, computing what does
+    /// not depend on the execution ahead of time.
+    pub fn optimize(&mut self, optimize: bool) -> &mut Options {
+        self.optimize = optimize;
+        self
+    }
 
-    let main = root
-        .first_element_child()
-        .ok_or(InvalidProgram)?
-        .children()
-        .find(|node| util::tag_name(&node) == "main")
-        .ok_or(MissingChild("program", "main"))?;
-    let main_ast = Instruction::from_children(main)?;
+    /// Arguments given to the program, as returned by `get-args`.
+    pub fn args(&mut self, args: Vec<String>) -> &mut Options {
+        self.args = args;
+        self
+    }
 
-    let functions = root
-        .first_element_child()
-        .ok_or(InvalidProgram)?
-        .children()
-        .filter(|node| node.tag_name().name() == String::from("function"));
+    /// Restricts what programs may access.
+    pub fn sandbox(&mut self, sandbox: Sandbox) -> &mut Options {
+        self.sandbox = sandbox;
+        self
+    }
 
-    for fun in functions {
-        ctx.assign(
-            String::from(fun.attribute("name").ok_or(Unnamed("function"))?),
-            Value::Function(Function::from(&fun)?),
-        );
+    fn parse(&self, doc: &Document) -> Result<Program, Error> {
+        if self.strict {
+            grammar::validate(doc)?;
+        }
+        Program::new(doc)
     }
 
-    let mut main_ctx = Context::new(Some(&ctx));
+    /// Parses a program to be run, optimizing it if requested.
+    fn load(&self, doc: &Document) -> Result<Program, Error> {
+        let program = self.parse(doc)?;
+        Ok(if self.optimize {
+            optimize::optimize(program)
+        } else {
+            program
+        })
+    }
 
-    for ins in main_ast {
-        ins.run(&mut main_ctx, &ctx)?;
+    /// Creates an interpreter, with the standard library, running
+    /// programs with these options.
+    pub fn interpreter(&self) -> Interpreter {
+        let mut globals = Context::root(Runtime::new(self.args.clone(), self.sandbox.clone()));
+        stl::inject_all(&mut globals);
+        Interpreter::with_globals(self.clone(), globals)
     }
 
-    Ok(())
+    /// Runs a program file, whose imports are relative to it.
+    pub fn run_file(&self, filename: &str) -> Result<i32, Error> {
+        self.interpreter().run_file(filename)
+    }
+
+    /// Runs a program, giving its exit status.
+    pub fn run(&self, program: String) -> Result<i32, Error> {
+        self.interpreter().run(&program)
+    }
+
+    /// Parses a program and writes it back, optimized if requested,
+    /// to show what is actually run.
+    pub fn dump(&self, program: String) -> Result<String, Error> {
+        let doc = Document::parse(&program)?;
+        format(&dump::dump(&self.load(&doc)?))
+    }
+
+    /// Statically checks a program file, whose imports are relative
+    /// to it, without running it.
+    pub fn check_file(&self, filename: &str) -> Result<Vec<Diagnostic>, Error> {
+        self.check_in(read(filename)?, directory(Path::new(filename)))
+    }
+
+    /// Statically checks a program without running it.
+    pub fn check(&self, program: String) -> Result<Vec<Diagnostic>, Error> {
+        self.check_in(program, Path::new(""))
+    }
+
+    fn check_in(&self, program: String, dir: &Path) -> Result<Vec<Diagnostic>, Error> {
+        let doc = Document::parse(&program)?;
+        let program = self.parse(&doc)?;
+        let mut imported = Vec::new();
+        for import in &program.imports {
+            let path = import
+                .path(dir, &self.sandbox)
+                .map_err(|e| e.at(&import.location))?;
+            let source =
+                fs::read_to_string(path).map_err(|_| InaccessibleFile(import.src.clone()))?;
+            let doc = Document::parse(&source)?;
+            let module = self.parse(&doc)?;
+            for (name, function) in &module.functions {
+                let name = import.name(module.name.as_deref(), name)?;
+                imported.push((name, function.args.len()));
+            }
+        }
+        Ok(Checker::check(&program, &imported))
+    }
+}
+
+/// Directory which the imports of a program file are relative to.
+fn directory(path: &Path) -> &Path {
+    path.parent().unwrap_or_else(|| Path::new(""))
+}
+
+fn read(filename: &str) -> Result<String, Error> {
+    Ok(fs::read_to_string(filename).map_err(|_| InaccessibleFile(String::from(filename)))?)
+}
+
+pub fn run_file(filename: &str) -> Result<i32, Error> {
+    Options::new().run_file(filename)
+}
+
+pub fn run(program: String) -> Result<i32, Error> {
+    Options::new().run(program)
+}
+
+pub fn check_file(filename: &str) -> Result<Vec<Diagnostic>, Error> {
+    Options::new().check_file(filename)
+}
+
+pub fn check(program: String) -> Result<Vec<Diagnostic>, Error> {
+    Options::new().check(program)
+}
+
+/// Reindents a program, one node per line.
+pub fn format(program: &str) -> Result<String, Error> {
+    let doc = Document::parse(program)?;
+    Ok(format::format(&doc))
+}
+
+/// RelaxNG schema of the language, in XML syntax.
+pub fn schema() -> String {
+    grammar::schema()
 }
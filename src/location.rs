@@ -0,0 +1,28 @@
+use roxmltree::Node;
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Location {
+    pub range: Range<usize>,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl Location {
+    pub fn of(node: &Node) -> Location {
+        let range = node.range();
+        let pos = node.document().text_pos_at(range.start);
+        Location {
+            range,
+            line: pos.row,
+            column: pos.col,
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
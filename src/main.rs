@@ -1,9 +1,268 @@
-use plxml::run_file;
-use std::env;
+use plxml::repl::{self, Session};
+use plxml::{format, schema, Error, ErrorKind, Options, Sandbox};
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::{env, process, thread};
+
+const USAGE: &str = "\
+usage: plxml <command> [arguments]
+
+commands:
+    run [--strict] [--bytecode] [--optimize] [--sandbox] <file> [--] [args...]
+                                run a program
+    check [--strict] <file>     report problems without running
+    fmt <file>                  print a reindented program
+    dump [--strict] [--optimized] <file>
+                                print a program as it is run
+    repl                        start an interactive session
+    schema                      print the RelaxNG schema
+
+options:
+    --strict                    reject anything outside of the grammar
+    --bytecode                  run on the bytecode virtual machine
+    --optimize                  simplify the program before running it
+    --sandbox                   deny access to files and arguments,
+                                and limit the depth of calls
+    --optimized                 print the program once simplified
+
+A file named '-' is read from the standard input.";
+
+/// Stack size of the thread running commands, large enough for the
+/// call depth allowed by default even in debug builds.
+const STACK_SIZE: usize = 64 << 20;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if let Err(e) = run_file(&args[1]) {
-        eprintln!("Error occurred: {}", e);
+    let args: Vec<String> = env::args().skip(1).collect();
+    let code = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(move || command(&args))
+        .expect("unable to start the interpreter thread")
+        .join()
+        // the panic has already been reported
+        .unwrap_or(101);
+    process::exit(code);
+}
+
+fn command(args: &[String]) -> i32 {
+    match args.first().map(String::as_str) {
+        Some("run") => run(&args[1..]),
+        Some("check") => check(&args[1..]),
+        Some("fmt") => fmt(&args[1..]),
+        Some("dump") => dump(&args[1..]),
+        Some("repl") if args.len() == 1 => run_repl(),
+        Some("schema") if args.len() == 1 => {
+            print!("{}", schema());
+            0
+        }
+        Some("help" | "--help" | "-h") => {
+            println!("{}", USAGE);
+            0
+        }
+        // running a file is the default command
+        Some(_) => run(args),
+        None => usage(),
+    }
+}
+
+fn usage() -> i32 {
+    eprintln!("{}", USAGE);
+    2
+}
+
+fn report(e: &Error) {
+    eprintln!("Error occurred: [{}] {}", e.code(), e);
+    for frame in e.trace() {
+        eprintln!("    in '{}' called at {}", frame.function, frame.location);
+    }
+}
+
+/// Splits the leading flags among `allowed` from the remaining arguments.
+fn flags<'a>(args: &'a [String], allowed: &[&str]) -> (Options, &'a [String]) {
+    let mut options = Options::new();
+    let mut rest = args;
+    while let Some((flag, tail)) = rest.split_first() {
+        match flag.as_str() {
+            "--strict" if allowed.contains(&"--strict") => options.strict(true),
+            "--bytecode" if allowed.contains(&"--bytecode") => options.bytecode(true),
+            "--sandbox" if allowed.contains(&"--sandbox") => options.sandbox(Sandbox::strict()),
+            "--optimize" | "--optimized" if allowed.contains(&flag.as_str()) => {
+                options.optimize(true)
+            }
+            _ => break,
+        };
+        rest = tail;
+    }
+    (options, rest)
+}
+
+fn read(filename: &str) -> Result<String, Error> {
+    if filename == "-" {
+        let mut contents = String::new();
+        io::stdin().read_to_string(&mut contents)?;
+        Ok(contents)
+    } else {
+        fs::read_to_string(filename)
+            .map_err(|_| ErrorKind::InaccessibleFile(String::from(filename)).into())
+    }
+}
+
+fn run(args: &[String]) -> i32 {
+    let (mut options, args) = flags(args, &["--strict", "--bytecode", "--optimize", "--sandbox"]);
+    let (filename, args) = match args.split_first() {
+        Some(split) => split,
+        None => return usage(),
+    };
+    let args = match args.first() {
+        Some(separator) if separator == "--" => &args[1..],
+        _ => args,
+    };
+    options.args(args.to_vec());
+    let result = match filename.as_str() {
+        "-" => read(filename).and_then(|program| options.run(program)),
+        _ => options.run_file(filename),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            report(&e);
+            1
+        }
+    }
+}
+
+fn check(args: &[String]) -> i32 {
+    let (options, args) = flags(args, &["--strict"]);
+    let filename = match args {
+        [filename] => filename,
+        _ => return usage(),
+    };
+    let result = match filename.as_str() {
+        "-" => read(filename).and_then(|program| options.check(program)),
+        _ => options.check_file(filename),
+    };
+    match result {
+        Ok(diagnostics) => {
+            for d in &diagnostics {
+                println!(
+                    "{}:{}:{}: [{}] {}",
+                    filename,
+                    d.location.line,
+                    d.location.column,
+                    d.kind.code(),
+                    d.kind
+                );
+            }
+            if diagnostics.is_empty() {
+                0
+            } else {
+                1
+            }
+        }
+        Err(e) => {
+            report(&e);
+            1
+        }
+    }
+}
+
+fn fmt(args: &[String]) -> i32 {
+    let filename = match args {
+        [filename] => filename,
+        _ => return usage(),
+    };
+    match read(filename).and_then(|program| format(&program)) {
+        Ok(formatted) => {
+            print!("{}", formatted);
+            0
+        }
+        Err(e) => {
+            report(&e);
+            1
+        }
+    }
+}
+
+fn dump(args: &[String]) -> i32 {
+    let (options, args) = flags(args, &["--strict", "--optimized"]);
+    let filename = match args {
+        [filename] => filename,
+        _ => return usage(),
+    };
+    match read(filename).and_then(|program| options.dump(program)) {
+        Ok(dumped) => {
+            print!("{}", dumped);
+            0
+        }
+        Err(e) => {
+            report(&e);
+            1
+        }
+    }
+}
+
+fn run_repl() -> i32 {
+    let mut session = Session::new();
+    let mut buffer = String::new();
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!(
+            "{}",
+            if buffer.is_empty() {
+                "plxml> "
+            } else {
+                "  ...> "
+            }
+        );
+        let _ = io::stdout().flush();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => return 0,
+        };
+
+        if line.trim().is_empty() {
+            // a blank line gives up on unfinished input
+            buffer.clear();
+            continue;
+        }
+        if line.trim_start().starts_with(':') {
+            let mut words = line.split_whitespace();
+            match (words.next(), words.next()) {
+                (Some(":vars"), None) => {
+                    for (name, value) in session.variables() {
+                        println!("{} = {}", name, value);
+                    }
+                }
+                (Some(":load"), Some(filename)) => {
+                    if let Err(e) = session.load(filename) {
+                        match e.exit_code() {
+                            Some(code) => return code,
+                            None => report(&e),
+                        }
+                    }
+                }
+                (Some(":reset"), None) => {
+                    session.reset();
+                    buffer.clear();
+                }
+                (Some(":quit"), None) => return 0,
+                _ => println!("commands: :vars, :load <file>, :reset, :quit"),
+            }
+            continue;
+        }
+
+        buffer.push_str(&line);
+        buffer.push('\n');
+        if repl::complete(&buffer) {
+            match session.eval(&buffer) {
+                Ok(Some(value)) => println!("{}", value),
+                Ok(None) => {}
+                Err(e) => match e.exit_code() {
+                    Some(code) => return code,
+                    None => report(&e),
+                },
+            }
+            buffer.clear();
+        }
     }
 }
@@ -0,0 +1,110 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::rc::Rc;
+
+/// A variable name, interned when the program is loaded so that
+/// looking variables up never hashes or compares strings.
+#[derive(Clone)]
+pub struct Name(Rc<str>);
+
+thread_local! {
+    // each distinct name in use is kept once, and forgotten along
+    // with its last copy
+    static NAMES: RefCell<HashSet<Rc<str>>> = RefCell::default();
+}
+
+impl Name {
+    pub fn new(name: &str) -> Name {
+        NAMES.with(|names| {
+            let mut names = names.borrow_mut();
+            match names.get(name) {
+                Some(name) => Name(Rc::clone(name)),
+                None => {
+                    let name: Rc<str> = Rc::from(name);
+                    names.insert(Rc::clone(&name));
+                    Name(name)
+                }
+            }
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for Name {
+    fn drop(&mut self) {
+        // the last copy besides the one of the table; names may outlive
+        // the table when dropped along with other thread locals
+        if Rc::strong_count(&self.0) == 2 {
+            let _ = NAMES.try_with(|names| names.borrow_mut().remove(&self.0));
+        }
+    }
+}
+
+impl PartialEq for Name {
+    fn eq(&self, other: &Name) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Name {}
+
+impl Hash for Name {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        Rc::as_ptr(&self.0).cast::<u8>().hash(state);
+    }
+}
+
+impl Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for Name {
+    fn from(name: &str) -> Name {
+        Name::new(name)
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn interned(name: &str) -> bool {
+        NAMES.with(|names| names.borrow().contains(name))
+    }
+
+    #[test]
+    fn names_are_shared_then_freed() {
+        let a = Name::new("interned-name");
+        let b = Name::from("interned-name");
+        assert_eq!(a, b);
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+        assert_ne!(a, Name::new("other-name"));
+        assert!(!interned("other-name"));
+        drop(a);
+        assert!(interned("interned-name"));
+        drop(b);
+        assert!(!interned("interned-name"));
+    }
+}
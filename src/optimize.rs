@@ -0,0 +1,642 @@
+use super::instruction::{Catch, Instruction, InstructionKind};
+use super::program::Global;
+use super::value::Function;
+use super::{Name, Program, Value};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Rewrites a program into one doing less work, with the same output
+/// and errors:
+///
+/// - operations on constants are computed, unless they fail,
+/// - `if` and `while` nodes with a constant condition are replaced
+///   by the branch taken, if any,
+/// - calls with constant arguments to functions which only return
+///   an expression of their arguments are replaced by their value.
+pub fn optimize(program: Program) -> Program {
+    let mut optimizer = Optimizer::new(&program);
+    Program {
+        name: program.name,
+        imports: program.imports,
+        globals: program
+            .globals
+            .into_iter()
+            .map(|global| Global {
+                value: optimizer.instruction(global.value),
+                ..global
+            })
+            .collect(),
+        functions: program
+            .functions
+            .iter()
+            .map(|(name, function)| (name.clone(), optimizer.function(function)))
+            .collect(),
+        main: optimizer.block(program.main),
+    }
+}
+
+struct Optimizer {
+    /// Parameters and returned expression of inlinable functions.
+    inlinable: HashMap<Name, (Vec<Name>, Instruction)>,
+    /// Functions being inlined, which are not inlined again.
+    inlining: Vec<Name>,
+}
+
+/// Value of a literal instruction.
+fn literal(ins: &Instruction) -> Option<Value> {
+    match &ins.kind {
+        InstructionKind::Boolean(b) => Some(Value::Boolean(*b)),
+        InstructionKind::Null => Some(Value::Null),
+        InstructionKind::Integer(i) => Some(Value::Integer(*i)),
+        InstructionKind::Real(f) => Some(Value::Real(*f)),
+        InstructionKind::String(s) => Some(Value::String(s.clone())),
+        _ => None,
+    }
+}
+
+/// Literal instruction giving a value, if it has one.
+fn constant(value: Value) -> Option<InstructionKind> {
+    match value {
+        Value::Boolean(b) => Some(InstructionKind::Boolean(b)),
+        Value::Null => Some(InstructionKind::Null),
+        Value::Integer(i) => Some(InstructionKind::Integer(i)),
+        Value::Real(f) => Some(InstructionKind::Real(f)),
+        Value::String(s) => Some(InstructionKind::String(s)),
+        _ => None,
+    }
+}
+
+fn literals(ins: &[Instruction]) -> Option<Vec<Value>> {
+    ins.iter().map(literal).collect()
+}
+
+/// Whether an instruction always gives a boolean.
+fn boolean(ins: &Instruction) -> bool {
+    matches!(
+        ins.kind,
+        InstructionKind::Boolean(_)
+            | InstructionKind::And(_)
+            | InstructionKind::Or(_)
+            | InstructionKind::Not(_)
+            | InstructionKind::Equal(..)
+            | InstructionKind::Greater(..)
+            | InstructionKind::Lower(..)
+    )
+}
+
+/// Collects every name a program binds, other than its top-level
+/// functions, which may shadow them.
+fn bound(ins: &[Instruction], names: &mut HashSet<Name>) {
+    fn function(function: &Function, names: &mut HashSet<Name>) {
+        names.extend(function.args.iter().cloned());
+        bound(&function.ins, names);
+    }
+
+    for i in ins {
+        match &i.kind {
+            InstructionKind::Assign(name, _, value) => {
+                names.insert(name.clone());
+                bound(std::slice::from_ref(value), names);
+            }
+            InstructionKind::Value(_)
+            | InstructionKind::Boolean(_)
+            | InstructionKind::Null
+            | InstructionKind::Integer(_)
+            | InstructionKind::Real(_)
+            | InstructionKind::String(_)
+            | InstructionKind::Break(_)
+            | InstructionKind::Continue(_) => {}
+            InstructionKind::IntegerCast(value)
+            | InstructionKind::RealCast(value)
+            | InstructionKind::StringCast(value)
+            | InstructionKind::Not(value)
+            | InstructionKind::Negate(value)
+            | InstructionKind::Return(value)
+            | InstructionKind::Throw(_, value) => bound(std::slice::from_ref(value), names),
+            InstructionKind::Array(values)
+            | InstructionKind::Add(values)
+            | InstructionKind::Subtract(values)
+            | InstructionKind::Multiply(values)
+            | InstructionKind::Divide(values)
+            | InstructionKind::Modulo(values)
+            | InstructionKind::IntDivide(values)
+            | InstructionKind::Power(values)
+            | InstructionKind::BitAnd(values)
+            | InstructionKind::BitOr(values)
+            | InstructionKind::BitXor(values)
+            | InstructionKind::ShiftLeft(values)
+            | InstructionKind::ShiftRight(values)
+            | InstructionKind::And(values)
+            | InstructionKind::Or(values)
+            | InstructionKind::CallNamed(_, values) => bound(values, names),
+            InstructionKind::Map(entries) => {
+                for (key, value) in entries {
+                    bound(std::slice::from_ref(key), names);
+                    bound(std::slice::from_ref(value), names);
+                }
+            }
+            InstructionKind::Equal(v1, v2)
+            | InstructionKind::Greater(v1, v2)
+            | InstructionKind::Lower(v1, v2) => {
+                bound(std::slice::from_ref(v1), names);
+                bound(std::slice::from_ref(v2), names);
+            }
+            InstructionKind::Call(f, args) => {
+                bound(std::slice::from_ref(f), names);
+                bound(args, names);
+            }
+            InstructionKind::If(cond, then) => {
+                bound(std::slice::from_ref(cond), names);
+                bound(then, names);
+            }
+            InstructionKind::IfElse(cond, then, els) => {
+                bound(std::slice::from_ref(cond), names);
+                bound(then, names);
+                bound(els, names);
+            }
+            InstructionKind::For {
+                variable,
+                from,
+                to,
+                step,
+                body,
+                ..
+            } => {
+                names.insert(variable.clone());
+                bound(std::slice::from_ref(from), names);
+                bound(std::slice::from_ref(to), names);
+                bound(std::slice::from_ref(step), names);
+                bound(body, names);
+            }
+            InstructionKind::Each {
+                variable,
+                key,
+                array,
+                body,
+                ..
+            } => {
+                names.insert(variable.clone());
+                names.extend(key.iter().cloned());
+                bound(std::slice::from_ref(array), names);
+                bound(body, names);
+            }
+            InstructionKind::While(cond, body, _) => {
+                bound(std::slice::from_ref(cond), names);
+                bound(body, names);
+            }
+            InstructionKind::Handle {
+                body,
+                catches,
+                finally,
+            } => {
+                bound(body, names);
+                for catch in catches {
+                    names.extend(catch.variable.iter().cloned());
+                    bound(&catch.body, names);
+                }
+                bound(finally, names);
+            }
+            InstructionKind::Function(name, f) => {
+                names.insert(name.clone());
+                function(f, names);
+            }
+            InstructionKind::Lambda(f) => function(f, names),
+        }
+    }
+}
+
+/// Whether an expression only computes a value out of the given
+/// parameters, without any other effect.
+fn pure(ins: &Instruction, params: &[Name]) -> bool {
+    match &ins.kind {
+        InstructionKind::Value(name) => params.contains(name),
+        InstructionKind::Boolean(_)
+        | InstructionKind::Null
+        | InstructionKind::Integer(_)
+        | InstructionKind::Real(_)
+        | InstructionKind::String(_) => true,
+        InstructionKind::IntegerCast(value)
+        | InstructionKind::RealCast(value)
+        | InstructionKind::StringCast(value)
+        | InstructionKind::Not(value)
+        | InstructionKind::Negate(value) => pure(value, params),
+        InstructionKind::Add(values)
+        | InstructionKind::Subtract(values)
+        | InstructionKind::Multiply(values)
+        | InstructionKind::Divide(values)
+        | InstructionKind::Modulo(values)
+        | InstructionKind::IntDivide(values)
+        | InstructionKind::Power(values)
+        | InstructionKind::BitAnd(values)
+        | InstructionKind::BitOr(values)
+        | InstructionKind::BitXor(values)
+        | InstructionKind::ShiftLeft(values)
+        | InstructionKind::ShiftRight(values)
+        | InstructionKind::And(values)
+        | InstructionKind::Or(values)
+        | InstructionKind::CallNamed(_, values) => values.iter().all(|v| pure(v, params)),
+        InstructionKind::Equal(v1, v2)
+        | InstructionKind::Greater(v1, v2)
+        | InstructionKind::Lower(v1, v2) => pure(v1, params) && pure(v2, params),
+        _ => false,
+    }
+}
+
+/// Replaces the parameters of an expression by the arguments.
+fn substitute(ins: &Instruction, params: &[Name], args: &[Instruction]) -> Instruction {
+    let sub = |i: &Instruction| Box::new(substitute(i, params, args));
+    let all = |is: &[Instruction]| is.iter().map(|i| substitute(i, params, args)).collect();
+    let kind = match &ins.kind {
+        InstructionKind::Value(name) => match params.iter().rposition(|p| p == name) {
+            Some(i) => return args[i].clone(),
+            None => InstructionKind::Value(name.clone()),
+        },
+        InstructionKind::IntegerCast(value) => InstructionKind::IntegerCast(sub(value)),
+        InstructionKind::RealCast(value) => InstructionKind::RealCast(sub(value)),
+        InstructionKind::StringCast(value) => InstructionKind::StringCast(sub(value)),
+        InstructionKind::Not(value) => InstructionKind::Not(sub(value)),
+        InstructionKind::Add(values) => InstructionKind::Add(all(values)),
+        InstructionKind::Subtract(values) => InstructionKind::Subtract(all(values)),
+        InstructionKind::Multiply(values) => InstructionKind::Multiply(all(values)),
+        InstructionKind::Divide(values) => InstructionKind::Divide(all(values)),
+        InstructionKind::Modulo(values) => InstructionKind::Modulo(all(values)),
+        InstructionKind::IntDivide(values) => InstructionKind::IntDivide(all(values)),
+        InstructionKind::Power(values) => InstructionKind::Power(all(values)),
+        InstructionKind::Negate(value) => InstructionKind::Negate(sub(value)),
+        InstructionKind::BitAnd(values) => InstructionKind::BitAnd(all(values)),
+        InstructionKind::BitOr(values) => InstructionKind::BitOr(all(values)),
+        InstructionKind::BitXor(values) => InstructionKind::BitXor(all(values)),
+        InstructionKind::ShiftLeft(values) => InstructionKind::ShiftLeft(all(values)),
+        InstructionKind::ShiftRight(values) => InstructionKind::ShiftRight(all(values)),
+        InstructionKind::And(values) => InstructionKind::And(all(values)),
+        InstructionKind::Or(values) => InstructionKind::Or(all(values)),
+        InstructionKind::CallNamed(name, values) => {
+            InstructionKind::CallNamed(name.clone(), all(values))
+        }
+        InstructionKind::Equal(v1, v2) => InstructionKind::Equal(sub(v1), sub(v2)),
+        InstructionKind::Greater(v1, v2) => InstructionKind::Greater(sub(v1), sub(v2)),
+        InstructionKind::Lower(v1, v2) => InstructionKind::Lower(sub(v1), sub(v2)),
+        kind => kind.clone(),
+    };
+    Instruction {
+        kind,
+        location: ins.location.clone(),
+    }
+}
+
+impl Optimizer {
+    fn new(program: &Program) -> Optimizer {
+        let mut names = HashSet::new();
+        bound(&program.main, &mut names);
+        for global in &program.globals {
+            names.insert(global.name.clone());
+            bound(std::slice::from_ref(&global.value), &mut names);
+        }
+        let mut defined = HashMap::new();
+        for (name, function) in &program.functions {
+            bound(&function.ins, &mut names);
+            names.extend(function.args.iter().cloned());
+            *defined.entry(name.clone()).or_insert(0) += 1;
+        }
+
+        // calls are only inlined when their name cannot designate
+        // anything but the function
+        let inlinable = program
+            .functions
+            .iter()
+            .filter(|(name, _)| !names.contains(name) && defined[name] == 1)
+            .filter_map(|(name, function)| match function.ins.as_slice() {
+                [Instruction {
+                    kind: InstructionKind::Return(value),
+                    ..
+                }] if pure(value, &function.args) => {
+                    Some((name.clone(), (function.args.clone(), (**value).clone())))
+                }
+                _ => None,
+            })
+            .collect();
+        Optimizer {
+            inlinable,
+            inlining: Vec::new(),
+        }
+    }
+
+    fn function(&mut self, function: &Function) -> Rc<Function> {
+        Rc::new(Function {
+            args: function.args.clone(),
+            ins: self.block(function.ins.clone()),
+        })
+    }
+
+    fn boxed(&mut self, ins: Instruction) -> Box<Instruction> {
+        Box::new(self.instruction(ins))
+    }
+
+    fn all(&mut self, ins: Vec<Instruction>) -> Vec<Instruction> {
+        ins.into_iter().map(|i| self.instruction(i)).collect()
+    }
+
+    /// Optimizes a sequence of instructions, whose values are unused,
+    /// so that they may be replaced by any number of instructions.
+    fn block(&mut self, ins: Vec<Instruction>) -> Vec<Instruction> {
+        let mut block = Vec::new();
+        for i in ins {
+            let i = self.instruction(i);
+            match i.kind {
+                InstructionKind::If(cond, then) => match literal(&cond) {
+                    Some(value) => {
+                        if value.to_bool() {
+                            block.extend(then);
+                        }
+                    }
+                    None => block.push(Instruction {
+                        kind: InstructionKind::If(cond, then),
+                        location: i.location,
+                    }),
+                },
+                InstructionKind::IfElse(cond, then, els) => match literal(&cond) {
+                    Some(value) => block.extend(if value.to_bool() { then } else { els }),
+                    None => block.push(Instruction {
+                        kind: InstructionKind::IfElse(cond, then, els),
+                        location: i.location,
+                    }),
+                },
+                InstructionKind::While(cond, _, _)
+                    if literal(&cond).is_some_and(|v| !v.to_bool()) => {}
+                kind => block.push(Instruction {
+                    kind,
+                    location: i.location,
+                }),
+            }
+        }
+        block
+    }
+
+    /// Replaces a call by the value it gives, when it is known.
+    fn inline(&mut self, name: &Name, args: &[Instruction]) -> Option<InstructionKind> {
+        let (params, value) = self.inlinable.get(name)?;
+        if params.len() != args.len() || self.inlining.contains(name) {
+            return None;
+        }
+        literals(args)?;
+        let ins = substitute(value, params, args);
+        self.inlining.push(name.clone());
+        let ins = self.instruction(ins);
+        self.inlining.pop();
+        literal(&ins).map(|_| ins.kind)
+    }
+
+    fn instruction(&mut self, ins: Instruction) -> Instruction {
+        let kind = match ins.kind {
+            InstructionKind::Assign(name, target, value) => {
+                InstructionKind::Assign(name, target, self.boxed(*value))
+            }
+            InstructionKind::IntegerCast(value) => {
+                let value = self.boxed(*value);
+                literal(&value)
+                    .and_then(|v| Instruction::integer(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::IntegerCast(value))
+            }
+            InstructionKind::RealCast(value) => {
+                let value = self.boxed(*value);
+                literal(&value)
+                    .and_then(|v| Instruction::real(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::RealCast(value))
+            }
+            InstructionKind::StringCast(value) => {
+                let value = self.boxed(*value);
+                literal(&value)
+                    .and_then(|v| Instruction::string(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::StringCast(value))
+            }
+            InstructionKind::Array(values) => InstructionKind::Array(self.all(values)),
+            InstructionKind::Map(entries) => InstructionKind::Map(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (self.instruction(k), self.instruction(v)))
+                    .collect(),
+            ),
+            InstructionKind::Add(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::add(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::Add(values))
+            }
+            InstructionKind::Subtract(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::subtract(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::Subtract(values))
+            }
+            InstructionKind::Multiply(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::multiply(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::Multiply(values))
+            }
+            InstructionKind::Divide(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::divide(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::Divide(values))
+            }
+            InstructionKind::Modulo(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::modulo(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::Modulo(values))
+            }
+            InstructionKind::IntDivide(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::int_divide(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::IntDivide(values))
+            }
+            InstructionKind::Power(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::power(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::Power(values))
+            }
+            InstructionKind::Negate(value) => {
+                let value = self.boxed(*value);
+                literal(&value)
+                    .and_then(|v| Instruction::negate(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::Negate(value))
+            }
+            InstructionKind::BitAnd(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::bit_and(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::BitAnd(values))
+            }
+            InstructionKind::BitOr(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::bit_or(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::BitOr(values))
+            }
+            InstructionKind::BitXor(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::bit_xor(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::BitXor(values))
+            }
+            InstructionKind::ShiftLeft(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::shift_left(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::ShiftLeft(values))
+            }
+            InstructionKind::ShiftRight(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| Instruction::shift_right(v).ok())
+                    .and_then(constant)
+                    .unwrap_or(InstructionKind::ShiftRight(values))
+            }
+            InstructionKind::And(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| constant(Instruction::and(v)))
+                    .unwrap_or(InstructionKind::And(values))
+            }
+            InstructionKind::Or(values) => {
+                let values = self.all(values);
+                literals(&values)
+                    .and_then(|v| constant(Instruction::or(v)))
+                    .unwrap_or(InstructionKind::Or(values))
+            }
+            InstructionKind::Not(value) => {
+                let value = self.boxed(*value);
+                match (literal(&value), value.kind) {
+                    (Some(v), _) => InstructionKind::Boolean(!v.to_bool()),
+                    // negating twice gives back a boolean unchanged
+                    (None, InstructionKind::Not(inner)) if boolean(&inner) => inner.kind,
+                    (None, kind) => InstructionKind::Not(Box::new(Instruction {
+                        kind,
+                        location: value.location,
+                    })),
+                }
+            }
+            InstructionKind::Equal(v1, v2) => {
+                let (v1, v2) = (self.boxed(*v1), self.boxed(*v2));
+                match (literal(&v1), literal(&v2)) {
+                    (Some(l1), Some(l2)) => Instruction::equals(l1, l2)
+                        .ok()
+                        .map(InstructionKind::Boolean),
+                    _ => None,
+                }
+                .unwrap_or(InstructionKind::Equal(v1, v2))
+            }
+            InstructionKind::Greater(v1, v2) => {
+                let (v1, v2) = (self.boxed(*v1), self.boxed(*v2));
+                match (literal(&v1), literal(&v2)) {
+                    (Some(l1), Some(l2)) => Instruction::compare(l1, l2)
+                        .ok()
+                        .map(|c| InstructionKind::Boolean(c.is_gt())),
+                    _ => None,
+                }
+                .unwrap_or(InstructionKind::Greater(v1, v2))
+            }
+            InstructionKind::Lower(v1, v2) => {
+                let (v1, v2) = (self.boxed(*v1), self.boxed(*v2));
+                match (literal(&v1), literal(&v2)) {
+                    (Some(l1), Some(l2)) => Instruction::compare(l1, l2)
+                        .ok()
+                        .map(|c| InstructionKind::Boolean(c.is_lt())),
+                    _ => None,
+                }
+                .unwrap_or(InstructionKind::Lower(v1, v2))
+            }
+            InstructionKind::Call(function, args) => {
+                let args = self.all(args);
+                InstructionKind::Call(self.boxed(*function), args)
+            }
+            InstructionKind::CallNamed(name, args) => {
+                let args = self.all(args);
+                self.inline(&name, &args)
+                    .unwrap_or(InstructionKind::CallNamed(name, args))
+            }
+            InstructionKind::Return(value) => InstructionKind::Return(self.boxed(*value)),
+            InstructionKind::If(cond, then) => {
+                InstructionKind::If(self.boxed(*cond), self.block(then))
+            }
+            InstructionKind::IfElse(cond, then, els) => {
+                InstructionKind::IfElse(self.boxed(*cond), self.block(then), self.block(els))
+            }
+            InstructionKind::For {
+                variable,
+                from,
+                to,
+                step,
+                body,
+                label,
+            } => InstructionKind::For {
+                variable,
+                from: self.boxed(*from),
+                to: self.boxed(*to),
+                step: self.boxed(*step),
+                body: self.block(body),
+                label,
+            },
+            InstructionKind::Each {
+                variable,
+                key,
+                array,
+                body,
+                label,
+            } => InstructionKind::Each {
+                variable,
+                key,
+                array: self.boxed(*array),
+                body: self.block(body),
+                label,
+            },
+            InstructionKind::While(cond, body, label) => {
+                InstructionKind::While(self.boxed(*cond), self.block(body), label)
+            }
+            InstructionKind::Handle {
+                body,
+                catches,
+                finally,
+            } => InstructionKind::Handle {
+                body: self.block(body),
+                catches: catches
+                    .into_iter()
+                    .map(|c| Catch {
+                        filter: c.filter,
+                        variable: c.variable,
+                        body: self.block(c.body),
+                    })
+                    .collect(),
+                finally: self.block(finally),
+            },
+            InstructionKind::Throw(tag, value) => InstructionKind::Throw(tag, self.boxed(*value)),
+            InstructionKind::Function(name, function) => {
+                InstructionKind::Function(name, self.function(&function))
+            }
+            InstructionKind::Lambda(function) => InstructionKind::Lambda(self.function(&function)),
+            kind => kind,
+        };
+        Instruction {
+            kind,
+            location: ins.location,
+        }
+    }
+}
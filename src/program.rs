@@ -0,0 +1,153 @@
+use super::error::{
+    Error,
+    ErrorKind::{
+        BadChildCount, ConstantAssignment, InaccessibleFile, InvalidProgram, MissingAttribute,
+        MissingChild, Unnamed,
+    },
+};
+use super::sandbox::Sandbox;
+use super::value::Function;
+use super::{util, Instruction, Location, Name};
+use roxmltree::{Document, Node};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{self, Path, PathBuf};
+use std::rc::Rc;
+
+/// A parsed program, made of its imports, top-level functions and
+/// variables, and main block.
+pub struct Program {
+    pub name: Option<String>,
+    pub imports: Vec<Import>,
+    pub functions: Vec<(Name, Rc<Function>)>,
+    pub globals: Vec<Global>,
+    pub main: Vec<Instruction>,
+}
+
+/// Functions of another program, made available under a prefix.
+pub struct Import {
+    /// Path of the imported program, relative to the importing one.
+    pub src: String,
+    /// Prefix of the imported names, which defaults to the name of
+    /// the imported program.
+    pub prefix: Option<String>,
+    pub location: Location,
+}
+
+/// Top-level variable, set before the main block runs.
+pub struct Global {
+    pub name: Name,
+    pub value: Instruction,
+    pub constant: bool,
+}
+
+impl Global {
+    fn new(node: &Node) -> Result<Global, Error> {
+        let tag = if node.tag_name().name() == "const" {
+            "const"
+        } else {
+            "global"
+        };
+        let mut value = Instruction::from_children(*node)?;
+        if value.len() != 1 {
+            Err(BadChildCount(tag, value.len()))?
+        }
+        Ok(Global {
+            name: Name::new(node.attribute("name").ok_or(Unnamed(tag))?),
+            value: value.remove(0),
+            constant: tag == "const",
+        })
+    }
+}
+
+impl Import {
+    fn new(node: &Node) -> Result<Import, Error> {
+        Ok(Import {
+            src: String::from(
+                node.attribute("src")
+                    .ok_or(MissingAttribute("import", "src"))?,
+            ),
+            prefix: node.attribute("as").map(String::from),
+            location: Location::of(node),
+        })
+    }
+
+    /// Canonical path of the imported program, imported from `dir`.
+    pub fn path(&self, dir: &Path, sandbox: &Sandbox) -> Result<PathBuf, Error> {
+        let inaccessible = || Error::from(InaccessibleFile(self.src.clone()));
+        // the path is relative to the importing program rather than to
+        // the root of the sandbox, so it is made absolute beforehand
+        let path = dir.join(&self.src);
+        let path = fs::canonicalize(&path)
+            .or_else(|_| path::absolute(&path))
+            .map_err(|_| inaccessible())?;
+        let path = sandbox.file("import", &path.to_string_lossy())?;
+        fs::canonicalize(path).map_err(|_| inaccessible())
+    }
+
+    /// Name under which a function of the imported program `name` is
+    /// made available.
+    pub fn name(&self, name: Option<&str>, function: &Name) -> Result<Name, Error> {
+        let prefix = self.prefix.as_deref().or(name).ok_or(Unnamed("import"))?;
+        Ok(Name::new(&format!("{}.{}", prefix, function)))
+    }
+}
+
+impl Program {
+    pub fn new(doc: &Document) -> Result<Program, Error> {
+        let root = doc.root().first_element_child().ok_or(InvalidProgram)?;
+
+        let main = root
+            .children()
+            .find(|node| util::tag_name(node) == "main")
+            .ok_or(MissingChild("program", "main"))?;
+
+        let imports = root
+            .children()
+            .filter(|node| node.tag_name().name() == "import")
+            .map(|node| Import::new(&node).map_err(|e| e.at(&Location::of(&node))))
+            .collect::<Result<Vec<Import>, Error>>()?;
+
+        let functions = root
+            .children()
+            .filter(|node| node.tag_name().name() == "function")
+            .map(|fun| {
+                Ok((
+                    Name::new(fun.attribute("name").ok_or(Unnamed("function"))?),
+                    Rc::new(Function::from(&fun)?),
+                ))
+            })
+            .collect::<Result<Vec<(Name, Rc<Function>)>, Error>>()?;
+
+        let globals = root
+            .children()
+            .filter(|node| matches!(node.tag_name().name(), "global" | "const"))
+            .map(|node| Global::new(&node).map_err(|e| e.at(&Location::of(&node))))
+            .collect::<Result<Vec<Global>, Error>>()?;
+
+        // constants may not be declared again, as either constants or
+        // globals, which would replace them
+        let mut declared = HashMap::new();
+        for node in root
+            .children()
+            .filter(|node| matches!(node.tag_name().name(), "global" | "const"))
+        {
+            let name = node.attribute("name").unwrap_or_default();
+            let constant = node.tag_name().name() == "const";
+            if let Some(before) = declared.insert(name, constant) {
+                if constant || before {
+                    Err(Error::from(ConstantAssignment(String::from(name)))
+                        .at(&Location::of(&node)))?
+                }
+            }
+        }
+
+        Ok(Program {
+            name: root.attribute("name").map(String::from),
+            imports,
+            functions,
+            globals,
+            main: Instruction::from_children(main)?,
+        })
+    }
+}
@@ -0,0 +1,179 @@
+use super::error::{Error, ErrorKind::Xml};
+use super::instruction::Instruction;
+use super::interpreter::Main;
+use super::{Context, Interpreter, Value};
+use roxmltree::{Document, Node, TextPos};
+
+/// Element fragments are wrapped in, as documents have a single root.
+const WRAPPER: (&str, &str) = ("<repl>", "</repl>");
+
+/// State kept between the fragments evaluated in a REPL.
+pub struct Session {
+    interpreter: Interpreter,
+    ctx: Context,
+}
+
+impl Default for Session {
+    fn default() -> Session {
+        Session::new()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.ctx.clear();
+    }
+}
+
+impl Session {
+    pub fn new() -> Session {
+        let interpreter = Interpreter::new();
+        let ctx = Context::program(interpreter.globals());
+        Session { interpreter, ctx }
+    }
+
+    /// Forgets every variable and function defined so far.
+    pub fn reset(&mut self) {
+        *self = Session::new();
+    }
+
+    /// Variables defined in the session, excluding the standard library.
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        self.ctx.variables()
+    }
+
+    /// Runs a sequence of instructions, giving the value of the last one.
+    pub fn eval(&mut self, fragment: &str) -> Result<Option<Value>, Error> {
+        let ins = Session::parse(fragment)?;
+        let mut result = None;
+        for i in ins {
+            result = match i.run(&mut self.ctx) {
+                Ok(value) => value,
+                Err(flow) => Some(flow.escaped()?),
+            };
+        }
+        Ok(result)
+    }
+
+    /// Parses the instructions of a fragment, located within the
+    /// fragment rather than within the element it is wrapped in.
+    fn parse(fragment: &str) -> Result<Vec<Instruction>, Error> {
+        let (start, end) = WRAPPER;
+        let wrapped = format!("{}{}{}", start, fragment, end);
+        let doc = Document::parse(&wrapped).map_err(|e| {
+            // only the first line is shifted by the wrapper
+            let pos = e.pos();
+            let message = e.to_string();
+            Error::from(Xml(if pos.row == 1 && pos.col as usize > start.len() {
+                message.replace(
+                    &format!("at {}", pos),
+                    &format!("at 1:{}", pos.col as usize - start.len()),
+                )
+            } else {
+                message
+            }))
+        })?;
+        doc.root_element()
+            .children()
+            .filter(Node::is_element)
+            .map(|node| {
+                // each instruction is parsed on its own, with the rest of
+                // the fragment blanked out so that it keeps its place
+                let range = node.range().start - start.len()..node.range().end - start.len();
+                let alone: String = fragment
+                    .char_indices()
+                    .map(|(i, c)| {
+                        if range.contains(&i) || c == '\n' {
+                            c
+                        } else {
+                            ' '
+                        }
+                    })
+                    .collect();
+                Instruction::new(Document::parse(&alone)?.root_element())
+            })
+            .collect()
+    }
+
+    /// Defines the functions of a program and runs its main block
+    /// in the session.
+    pub fn load(&mut self, filename: &str) -> Result<(), Error> {
+        match self.interpreter.define_file(filename, self.ctx.clone())? {
+            Main::Tree(main) => {
+                for ins in main {
+                    if let Err(flow) = ins.run(&mut self.ctx) {
+                        flow.escaped()?;
+                        break;
+                    }
+                }
+            }
+            Main::Compiled(main) => {
+                main.call(Vec::new())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a fragment holds complete elements, and can be evaluated.
+///
+/// Fragments are incomplete when the XML parser only fails once it
+/// reaches their end, like within an unclosed element, attribute or
+/// comment. Fragments which are malformed in other ways are considered
+/// complete, so that evaluating them reports the error.
+pub fn complete(fragment: &str) -> bool {
+    let (start, end) = WRAPPER;
+    let wrapped = format!("{}{}{}", start, fragment, end);
+    match Document::parse(&wrapped) {
+        Ok(_) => true,
+        Err(e) => match failure(&e) {
+            // errors in trailing spaces come from what follows them
+            Some(pos) => offset(&wrapped, pos) < start.len() + fragment.trim_end().len(),
+            None => false,
+        },
+    }
+}
+
+/// Where the parser failed, which is more precise than the position of
+/// the error for tokens, or nothing when it ran out of text.
+fn failure(e: &roxmltree::Error) -> Option<TextPos> {
+    use xmlparser::{Error::*, StreamError::*};
+    let (cause, pos) = match e {
+        roxmltree::Error::ParserError(
+            InvalidDeclaration(cause, pos)
+            | InvalidComment(cause, pos)
+            | InvalidPI(cause, pos)
+            | InvalidDoctype(cause, pos)
+            | InvalidEntity(cause, pos)
+            | InvalidElement(cause, pos)
+            | InvalidAttribute(cause, pos)
+            | InvalidCdata(cause, pos)
+            | InvalidCharData(cause, pos),
+        ) => (cause, *pos),
+        e => return Some(e.pos()),
+    };
+    match cause {
+        UnexpectedEndOfStream => None,
+        NonXmlChar(_, pos)
+        | InvalidChar(_, _, pos)
+        | InvalidCharMultiple(_, _, pos)
+        | InvalidQuote(_, pos)
+        | InvalidSpace(_, pos)
+        | InvalidString(_, pos) => Some(*pos),
+        _ => Some(pos),
+    }
+}
+
+/// Byte offset of a position within a text, given as a line and a
+/// column counted in characters.
+fn offset(text: &str, pos: TextPos) -> usize {
+    let line: usize = text
+        .split_inclusive('\n')
+        .take(pos.row as usize - 1)
+        .map(str::len)
+        .sum();
+    text[line..]
+        .char_indices()
+        .nth(pos.col as usize - 1)
+        .map_or(text.len(), |(i, _)| line + i)
+}
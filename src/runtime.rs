@@ -0,0 +1,161 @@
+use super::error::Error;
+use super::sandbox::Sandbox;
+use super::value::Value;
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{self, stderr, stdin, stdout, Read, Write};
+use std::rc::Rc;
+
+/// State shared by everything running in the same program.
+pub struct Runtime {
+    /// Arguments given to the program itself.
+    pub args: Vec<String>,
+    /// Streams used by the input and output functions, which default
+    /// to those of the process.
+    pub input: RefCell<Box<dyn Read>>,
+    pub output: RefCell<Box<dyn Write>>,
+    pub error: RefCell<Box<dyn Write>>,
+    pub sandbox: Sandbox,
+    /// Bytes printed so far, counted against the sandbox limit.
+    written: Cell<usize>,
+    steps: Cell<usize>,
+    depth: Cell<usize>,
+}
+
+impl Runtime {
+    pub fn new(args: Vec<String>, sandbox: Sandbox) -> Runtime {
+        Runtime {
+            args,
+            sandbox,
+            written: Cell::new(0),
+            steps: Cell::new(0),
+            depth: Cell::new(0),
+            input: RefCell::new(Box::new(stdin())),
+            output: RefCell::new(Box::new(stdout())),
+            error: RefCell::new(Box::new(stderr())),
+        }
+    }
+
+    /// Starts counting the resources used by a run or a call from the
+    /// host anew.
+    pub fn start(&self) {
+        self.written.set(0);
+        self.steps.set(0);
+        self.depth.set(0);
+    }
+
+    /// Counts a step against the sandbox limit.
+    pub fn step(&self) -> Result<(), Error> {
+        let steps = self.steps.get() + 1;
+        self.sandbox.steps(steps)?;
+        self.steps.set(steps);
+        Ok(())
+    }
+
+    /// Runs a function call, one level deeper than the current one.
+    pub fn nested<T>(&self, call: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+        let depth = self.depth.get();
+        self.sandbox.depth(depth + 1)?;
+        self.depth.set(depth + 1);
+        let result = call();
+        self.depth.set(depth);
+        result
+    }
+
+    /// Checks the size of a value against the sandbox limit.
+    pub fn sized(&self, value: &Value) -> Result<(), Error> {
+        match value {
+            Value::String(s) => self.sandbox.size(s.len()),
+            Value::Array(v) => self.sandbox.size(v.borrow().len()),
+            Value::Map(m) => self.sandbox.size(m.borrow().len()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Prints `text` on a stream on behalf of `function`, within the
+    /// output limit of the sandbox.
+    pub fn print(
+        &self,
+        function: &'static str,
+        stream: &RefCell<Box<dyn Write>>,
+        text: &str,
+    ) -> Result<(), Error> {
+        let written = self.written.get() + text.len();
+        self.sandbox.output(function, written)?;
+        self.written.set(written);
+        let mut stream = stream.borrow_mut();
+        stream.write_all(text.as_bytes())?;
+        let _ = stream.flush();
+        Ok(())
+    }
+
+    /// Reads a line from the input, without its line feed.
+    ///
+    /// Bytes are read one at a time so that nothing past the line is
+    /// taken from a stream which may be shared with the host.
+    pub fn read_line(&self) -> io::Result<String> {
+        let mut input = self.input.borrow_mut();
+        let mut line = Vec::new();
+        let mut byte = [0];
+        while input.read(&mut byte)? == 1 && byte[0] != b'\n' {
+            line.push(byte[0]);
+        }
+        String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl Default for Runtime {
+    fn default() -> Runtime {
+        Runtime::new(Vec::new(), Sandbox::default())
+    }
+}
+
+impl fmt::Debug for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Runtime")
+            .field("args", &self.args)
+            .field("sandbox", &self.sandbox)
+            .finish_non_exhaustive()
+    }
+}
+
+/// In-memory stream, shared by its clones, with which a host can feed
+/// input to programs or capture what they write.
+#[derive(Clone, Debug, Default)]
+pub struct Buffer(Rc<RefCell<io::Cursor<Vec<u8>>>>);
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer::default()
+    }
+
+    /// Everything written to the buffer so far, as text.
+    pub fn contents(&self) -> String {
+        String::from_utf8_lossy(self.0.borrow().get_ref()).into_owned()
+    }
+}
+
+impl From<&str> for Buffer {
+    fn from(contents: &str) -> Buffer {
+        Buffer(Rc::new(RefCell::new(io::Cursor::new(
+            contents.as_bytes().to_vec(),
+        ))))
+    }
+}
+
+impl Read for Buffer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.borrow_mut().read(buf)
+    }
+}
+
+/// Writes are appended, regardless of what has been read.
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().get_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
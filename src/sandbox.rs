@@ -0,0 +1,208 @@
+use super::error::{
+    Error,
+    ErrorKind::{Denied, InaccessibleFile, LimitExceeded},
+};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+/// Depth of nested function calls allowed unless set otherwise, which
+/// the stack of the `plxml` command holds even in debug builds.
+const DEPTH_LIMIT: usize = 1000;
+
+/// Restrictions on what programs may access and on the resources
+/// they may use. Everything is allowed unless restricted, except for
+/// function calls, nested at most 1000 deep so that recursion ends
+/// with an error rather than overflowing the stack.
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    deny_files: bool,
+    root: Option<PathBuf>,
+    deny_args: bool,
+    output_limit: Option<usize>,
+    step_limit: Option<usize>,
+    depth_limit: Option<usize>,
+    size_limit: Option<usize>,
+}
+
+impl Default for Sandbox {
+    fn default() -> Sandbox {
+        Sandbox {
+            deny_files: false,
+            root: None,
+            deny_args: false,
+            output_limit: None,
+            step_limit: None,
+            depth_limit: Some(DEPTH_LIMIT),
+            size_limit: None,
+        }
+    }
+}
+
+impl Sandbox {
+    pub fn new() -> Sandbox {
+        Sandbox::default()
+    }
+
+    /// Sandbox allowing neither files nor arguments, with function
+    /// calls nested at most 256 deep so that recursion ends with an
+    /// error rather than overflowing the stack.
+    pub fn strict() -> Sandbox {
+        let mut sandbox = Sandbox::new();
+        sandbox.files(false).args(false).depth_limit(256);
+        sandbox
+    }
+
+    /// Allows `read-file` and `write-file`.
+    pub fn files(&mut self, allowed: bool) -> &mut Sandbox {
+        self.deny_files = !allowed;
+        self
+    }
+
+    /// Restricts files to those below `root`, which relative paths
+    /// are resolved from.
+    pub fn root(&mut self, root: impl Into<PathBuf>) -> &mut Sandbox {
+        self.root = Some(root.into());
+        self
+    }
+
+    /// Allows `get-args`.
+    pub fn args(&mut self, allowed: bool) -> &mut Sandbox {
+        self.deny_args = !allowed;
+        self
+    }
+
+    /// Limits the number of bytes programs may print, in total.
+    pub fn output_limit(&mut self, bytes: usize) -> &mut Sandbox {
+        self.output_limit = Some(bytes);
+        self
+    }
+
+    /// Limits the number of steps programs may run for, each being a
+    /// node evaluated, or an operation with the bytecode machine.
+    pub fn step_limit(&mut self, steps: usize) -> &mut Sandbox {
+        self.step_limit = Some(steps);
+        self
+    }
+
+    /// Limits the number of nested function calls, which a host running
+    /// programs on a smaller stack than the `plxml` command should lower.
+    pub fn depth_limit(&mut self, depth: usize) -> &mut Sandbox {
+        self.depth_limit = Some(depth);
+        self
+    }
+
+    /// Limits the number of elements of arrays and maps, and the
+    /// number of bytes of strings.
+    pub fn size_limit(&mut self, size: usize) -> &mut Sandbox {
+        self.size_limit = Some(size);
+        self
+    }
+
+    /// Path of a file accessed by `function`, if allowed.
+    pub(crate) fn file(&self, function: &'static str, path: &str) -> Result<PathBuf, Error> {
+        let denied = || Error::from(Denied(function, format!("file '{}'", path)));
+        if self.deny_files {
+            return Err(denied());
+        }
+        let root = match &self.root {
+            Some(root) => fs::canonicalize(root).map_err(|_| denied())?,
+            None => return Ok(PathBuf::from(path)),
+        };
+        let full = root.join(path);
+        // files about to be written may not exist yet, but their
+        // directory must, which resolves links and parent components
+        let resolved = fs::canonicalize(&full).or_else(|_| {
+            let name = full.file_name().ok_or_else(denied)?;
+            let parent = full.parent().unwrap_or_else(|| Path::new("/"));
+            let resolved = fs::canonicalize(parent).map_err(|_| denied())?.join(name);
+            // a link which could not be resolved leads to a missing
+            // file, which may well be outside of the root
+            match fs::symlink_metadata(&resolved) {
+                Ok(_) => Err(denied()),
+                Err(_) => Ok(resolved),
+            }
+        })?;
+        if resolved.starts_with(&root) {
+            Ok(resolved)
+        } else {
+            Err(denied())
+        }
+    }
+
+    /// Opens a file accessed by `function`, if allowed. Below a root,
+    /// missing files are created without following links, and opened
+    /// files are checked to still be the ones allowed, in case their
+    /// path changed in the meantime.
+    pub(crate) fn open(
+        &self,
+        function: &'static str,
+        path: &str,
+        options: &mut OpenOptions,
+    ) -> Result<File, Error> {
+        let inaccessible = || Error::from(InaccessibleFile(String::from(path)));
+        let resolved = self.file(function, path)?;
+        if self.root.is_none() {
+            return options.open(resolved).map_err(|_| inaccessible());
+        }
+        if fs::symlink_metadata(&resolved).is_err() {
+            options.create_new(true);
+        }
+        let file = options.open(&resolved).map_err(|_| inaccessible())?;
+        // the file must still be found at the path checked
+        match fs::canonicalize(&resolved) {
+            Ok(canonical) if canonical == resolved && same(&file, &canonical) => Ok(file),
+            _ => Err(Denied(function, format!("file '{}'", path)))?,
+        }
+    }
+
+    pub(crate) fn arguments(&self) -> Result<(), Error> {
+        if self.deny_args {
+            Err(Denied("get-args", String::from("arguments")))?
+        }
+        Ok(())
+    }
+
+    /// Whether `total` bytes of output are allowed.
+    pub(crate) fn output(&self, function: &'static str, total: usize) -> Result<(), Error> {
+        match self.output_limit {
+            Some(limit) if total > limit => {
+                Err(Denied(function, format!("output beyond {} bytes", limit)))?
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn steps(&self, steps: usize) -> Result<(), Error> {
+        Sandbox::within("step", self.step_limit, steps)
+    }
+
+    pub(crate) fn depth(&self, depth: usize) -> Result<(), Error> {
+        Sandbox::within("call depth", self.depth_limit, depth)
+    }
+
+    pub(crate) fn size(&self, size: usize) -> Result<(), Error> {
+        Sandbox::within("size", self.size_limit, size)
+    }
+
+    fn within(name: &'static str, limit: Option<usize>, value: usize) -> Result<(), Error> {
+        match limit {
+            Some(limit) if value > limit => Err(LimitExceeded(name, limit))?,
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether an opened file is the one found at a path.
+#[cfg(unix)]
+fn same(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(opened), Ok(found)) => (opened.dev(), opened.ino()) == (found.dev(), found.ino()),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same(_: &File, path: &Path) -> bool {
+    path.is_file()
+}
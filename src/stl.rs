@@ -1,78 +1,84 @@
-use crate::error::InaccessibleFile;
-
-use super::error::{BadArgumentCount, InvalidArgument};
-use super::{Context, Value};
+use super::error::{
+    Error,
+    ErrorKind::{BadArgumentCount, Exit, InaccessibleFile, InvalidArgument},
+};
+use super::runtime::Runtime;
+use super::value::{Key, StdFunction};
+use super::{Context, Name, Value};
 use std::cell::RefCell;
-use std::error::Error;
-use std::fs::{self, OpenOptions};
-use std::io::{stdin, stdout, Write};
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
 use std::rc::Rc;
 
+/// Standard functions, along with the number of arguments they take.
+pub const FUNCTIONS: &[(&str, StdFunction, usize)] = &[
+    ("print", print, 1),
+    ("print-line", print_line, 1),
+    ("print-error", print_error, 1),
+    ("input", input, 0),
+    ("string-split", string_split, 2),
+    ("array-set", array_set, 3),
+    ("array-push", array_push, 2),
+    ("array-pop", array_pop, 1),
+    ("array-get", array_get, 2),
+    ("array-length", array_length, 1),
+    ("map-get", map_get, 2),
+    ("map-set", map_set, 3),
+    ("map-has", map_has, 2),
+    ("map-remove", map_remove, 2),
+    ("map-keys", map_keys, 1),
+    ("map-values", map_values, 1),
+    ("to-ascii", to_ascii, 1),
+    ("from-ascii", from_ascii, 1),
+    ("get-args", get_args, 0),
+    ("write-file", write_file, 3),
+    ("read-file", read_file, 1),
+    ("exit", exit, 1),
+];
+
 pub fn inject_all(ctx: &mut Context) {
-    ctx.assign(String::from("print"), Value::StdFunction(print));
-    ctx.assign(String::from("print-line"), Value::StdFunction(print_line));
-    ctx.assign(String::from("input"), Value::StdFunction(input));
-    ctx.assign(
-        String::from("string-split"),
-        Value::StdFunction(string_split),
-    );
-    ctx.assign(String::from("array-set"), Value::StdFunction(array_set));
-    ctx.assign(String::from("array-push"), Value::StdFunction(array_push));
-    ctx.assign(String::from("array-pop"), Value::StdFunction(array_pop));
-    ctx.assign(String::from("array-get"), Value::StdFunction(array_get));
-    ctx.assign(
-        String::from("array-length"),
-        Value::StdFunction(array_length),
-    );
-    ctx.assign(String::from("to-ascii"), Value::StdFunction(to_ascii));
-    ctx.assign(String::from("from-ascii"), Value::StdFunction(from_ascii));
-    ctx.assign(String::from("get-args"), Value::StdFunction(get_args));
-    ctx.assign(String::from("write-file"), Value::StdFunction(write_file));
-    ctx.assign(String::from("read-file"), Value::StdFunction(read_file));
+    for (name, function, _) in FUNCTIONS {
+        ctx.assign(&Name::new(name), Value::StdFunction(*function))
+            .expect("standard function");
+    }
 }
 
-fn print(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn print(runtime: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 1 {
-        match &vals[0] {
-            Value::Integer(i) => print!("{}", i),
-            Value::Real(f) => print!("{}", f),
-            Value::String(s) => print!("{}", s),
-            v => print!("{:?}", v), // _ => Err("unprintable value")?,
-        };
-        let _ = stdout().flush();
-        Ok(Some(vals[0].clone()))
+        runtime.print("print", &runtime.output, &format!("{}", vals[0]))?;
+        Ok(vals[0].clone())
     } else {
         Err(BadArgumentCount("print", vals.len(), 1).into())
     }
 }
 
-fn print_line(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn print_line(runtime: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 1 {
-        match &vals[0] {
-            Value::Integer(i) => println!("{}", i),
-            Value::Real(f) => println!("{}", f),
-            Value::String(s) => println!("{}", s),
-            v => println!("{:?}", v), // _ => Err("unprintable value")?,
-        };
-        let _ = stdout().flush();
-        Ok(Some(vals[0].clone()))
+        runtime.print("print-line", &runtime.output, &format!("{}\n", vals[0]))?;
+        Ok(vals[0].clone())
     } else {
         Err(BadArgumentCount("print-line", vals.len(), 1).into())
     }
 }
 
-fn input(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
-    if vals.len() == 0 {
-        let mut line = String::new();
-        stdin().read_line(&mut line)?;
-        line.pop();
-        Ok(Some(Value::String(line)))
+fn print_error(runtime: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.len() == 1 {
+        runtime.print("print-error", &runtime.error, &format!("{}\n", vals[0]))?;
+        Ok(vals[0].clone())
+    } else {
+        Err(BadArgumentCount("print-error", vals.len(), 1).into())
+    }
+}
+
+fn input(runtime: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.is_empty() {
+        Ok(Value::String(runtime.read_line()?))
     } else {
         Err(BadArgumentCount("input", vals.len(), 0).into())
     }
 }
 
-fn string_split(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn string_split(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 2 {
         if let Value::String(s) = &vals[0] {
             if let Value::String(d) = &vals[1] {
@@ -82,7 +88,7 @@ fn string_split(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
                     .collect::<Vec<Value>>();
                 v.remove(0);
                 v.pop();
-                Ok(Some(Value::Array(Rc::new(RefCell::new(v)))))
+                Ok(Value::Array(Rc::new(RefCell::new(v))))
             } else {
                 Err(InvalidArgument("string-split", "delimiter").into())
             }
@@ -94,14 +100,15 @@ fn string_split(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
     }
 }
 
-fn array_set(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn array_set(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 3 {
         if let Value::Array(v) = &vals[0] {
             if let Value::Integer(i) = &vals[1] {
-                let index: usize = (*i).try_into()?;
+                let index =
+                    usize::try_from(*i).map_err(|_| InvalidArgument("array-set", "index"))?;
                 if v.borrow().len() > index {
                     v.borrow_mut()[index] = vals[2].clone();
-                    Ok(None)
+                    Ok(Value::Null)
                 } else {
                     Err(InvalidArgument("array-set", "index").into())
                 }
@@ -116,11 +123,12 @@ fn array_set(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
     }
 }
 
-fn array_push(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn array_push(runtime: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 2 {
         if let Value::Array(v) = &vals[0] {
+            runtime.sandbox.size(v.borrow().len() + 1)?;
             v.borrow_mut().push(vals[1].clone());
-            Ok(None)
+            Ok(Value::Null)
         } else {
             Err(InvalidArgument("array-push", "array").into())
         }
@@ -129,14 +137,12 @@ fn array_push(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
     }
 }
 
-fn array_pop(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn array_pop(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 1 {
         if let Value::Array(v) = &vals[0] {
-            Ok(Some(
-                v.borrow_mut()
-                    .pop()
-                    .ok_or(InvalidArgument("array-pop", "array"))?,
-            ))
+            Ok(v.borrow_mut()
+                .pop()
+                .ok_or(InvalidArgument("array-pop", "array"))?)
         } else {
             Err(InvalidArgument("array-pop", "array").into())
         }
@@ -145,13 +151,14 @@ fn array_pop(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
     }
 }
 
-fn array_get(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn array_get(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 2 {
         if let Value::Array(v) = &vals[0] {
             if let Value::Integer(i) = &vals[1] {
-                let index: usize = (*i).try_into()?;
+                let index =
+                    usize::try_from(*i).map_err(|_| InvalidArgument("array-get", "index"))?;
                 if v.borrow().len() > index {
-                    Ok(Some(v.borrow_mut()[index].clone()))
+                    Ok(v.borrow_mut()[index].clone())
                 } else {
                     Err(InvalidArgument("array-get", "index").into())
                 }
@@ -159,17 +166,17 @@ fn array_get(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
                 Err(InvalidArgument("array-get", "index").into())
             }
         } else {
-            Err(InvalidArgument("array-set", "array").into())
+            Err(InvalidArgument("array-get", "array").into())
         }
     } else {
         Err(BadArgumentCount("array-get", vals.len(), 2).into())
     }
 }
 
-fn array_length(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn array_length(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 1 {
         if let Value::Array(v) = &vals[0] {
-            Ok(Some(Value::Integer(v.borrow().len() as i64)))
+            Ok(Value::Integer(v.borrow().len() as i64))
         } else {
             Err(InvalidArgument("array-length", "array").into())
         }
@@ -178,11 +185,101 @@ fn array_length(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
     }
 }
 
-fn to_ascii(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn map_get(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.len() == 2 {
+        if let Value::Map(m) = &vals[0] {
+            let key = Key::new(&vals[1]).ok_or(InvalidArgument("map-get", "key"))?;
+            Ok(m.borrow()
+                .get(&key)
+                .cloned()
+                .ok_or(InvalidArgument("map-get", "key"))?)
+        } else {
+            Err(InvalidArgument("map-get", "map").into())
+        }
+    } else {
+        Err(BadArgumentCount("map-get", vals.len(), 2).into())
+    }
+}
+
+fn map_set(runtime: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.len() == 3 {
+        if let Value::Map(m) = &vals[0] {
+            let key = Key::new(&vals[1]).ok_or(InvalidArgument("map-set", "key"))?;
+            let grown = !m.borrow().contains_key(&key);
+            runtime
+                .sandbox
+                .size(m.borrow().len() + usize::from(grown))?;
+            m.borrow_mut().insert(key, vals[2].clone());
+            Ok(Value::Null)
+        } else {
+            Err(InvalidArgument("map-set", "map").into())
+        }
+    } else {
+        Err(BadArgumentCount("map-set", vals.len(), 3).into())
+    }
+}
+
+fn map_has(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.len() == 2 {
+        if let Value::Map(m) = &vals[0] {
+            let key = Key::new(&vals[1]).ok_or(InvalidArgument("map-has", "key"))?;
+            Ok(Value::Boolean(m.borrow().contains_key(&key)))
+        } else {
+            Err(InvalidArgument("map-has", "map").into())
+        }
+    } else {
+        Err(BadArgumentCount("map-has", vals.len(), 2).into())
+    }
+}
+
+fn map_remove(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.len() == 2 {
+        if let Value::Map(m) = &vals[0] {
+            let key = Key::new(&vals[1]).ok_or(InvalidArgument("map-remove", "key"))?;
+            Ok(m.borrow_mut()
+                .remove(&key)
+                .ok_or(InvalidArgument("map-remove", "key"))?)
+        } else {
+            Err(InvalidArgument("map-remove", "map").into())
+        }
+    } else {
+        Err(BadArgumentCount("map-remove", vals.len(), 2).into())
+    }
+}
+
+fn map_keys(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.len() == 1 {
+        if let Value::Map(m) = &vals[0] {
+            Ok(Value::Array(Rc::new(RefCell::new(
+                m.borrow().keys().cloned().map(Value::from).collect(),
+            ))))
+        } else {
+            Err(InvalidArgument("map-keys", "map").into())
+        }
+    } else {
+        Err(BadArgumentCount("map-keys", vals.len(), 1).into())
+    }
+}
+
+fn map_values(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.len() == 1 {
+        if let Value::Map(m) = &vals[0] {
+            Ok(Value::Array(Rc::new(RefCell::new(
+                m.borrow().values().cloned().collect(),
+            ))))
+        } else {
+            Err(InvalidArgument("map-values", "map").into())
+        }
+    } else {
+        Err(BadArgumentCount("map-values", vals.len(), 1).into())
+    }
+}
+
+fn to_ascii(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 1 {
         if let Value::Integer(i) = &vals[0] {
-            if &0 <= i && i <= &255 {
-                Ok(Some(Value::String(String::from_utf8(vec![*i as u8])?)))
+            if (0..=127).contains(i) {
+                Ok(Value::String(String::from(*i as u8 as char)))
             } else {
                 Err(InvalidArgument("to-ascii", "integer").into())
             }
@@ -194,11 +291,11 @@ fn to_ascii(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
     }
 }
 
-fn from_ascii(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn from_ascii(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 1 {
         if let Value::String(s) = &vals[0] {
             if s.len() == 1 {
-                Ok(Some(Value::Integer(s.as_bytes()[0] as i64)))
+                Ok(Value::Integer(s.as_bytes()[0] as i64))
             } else {
                 Err(InvalidArgument("from-ascii", "string").into())
             }
@@ -210,34 +307,31 @@ fn from_ascii(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
     }
 }
 
-fn get_args(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
-    if vals.len() == 0 {
-        Ok(Some(Value::Array(Rc::new(RefCell::new(
-            std::env::args()
-                .skip(1)
-                .map(|arg| Value::String(arg))
-                .collect(),
-        )))))
+fn get_args(runtime: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.is_empty() {
+        runtime.sandbox.arguments()?;
+        Ok(Value::Array(Rc::new(RefCell::new(
+            runtime.args.iter().cloned().map(Value::String).collect(),
+        ))))
     } else {
         Err(BadArgumentCount("get-args", vals.len(), 0).into())
     }
 }
 
-fn write_file(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn write_file(runtime: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 3 {
         if let Value::String(path) = &vals[0] {
             if let Value::String(contents) = &vals[1] {
-                if let Ok(mut file) = OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .append(Value::to_bool(&vals[2]))
-                    .open(path)
-                {
-                    if let Ok(_) = write!(file, "{}", contents) {
-                        Ok(None)
-                    } else {
-                        Err(InaccessibleFile(path.clone()).into())
-                    }
+                let mut file = runtime.sandbox.open(
+                    "write-file",
+                    path,
+                    OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .append(Value::to_bool(&vals[2])),
+                )?;
+                if write!(file, "{}", contents).is_ok() {
+                    Ok(Value::Null)
                 } else {
                     Err(InaccessibleFile(path.clone()).into())
                 }
@@ -252,11 +346,16 @@ fn write_file(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
     }
 }
 
-fn read_file(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
+fn read_file(runtime: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
     if vals.len() == 1 {
         if let Value::String(path) = &vals[0] {
-            if let Ok(contents) = fs::read_to_string(path) {
-                Ok(Some(Value::String(contents)))
+            let mut file =
+                runtime
+                    .sandbox
+                    .open("read-file", path, OpenOptions::new().read(true))?;
+            let mut contents = String::new();
+            if file.read_to_string(&mut contents).is_ok() {
+                Ok(Value::String(contents))
             } else {
                 Err(InaccessibleFile(path.clone()).into())
             }
@@ -267,3 +366,16 @@ fn read_file(vals: Vec<Value>) -> Result<Option<Value>, Box<dyn Error>> {
         Err(BadArgumentCount("read-file", vals.len(), 1).into())
     }
 }
+
+fn exit(_: &Runtime, vals: Vec<Value>) -> Result<Value, Error> {
+    if vals.len() == 1 {
+        if let Value::Integer(code) = &vals[0] {
+            let code = i32::try_from(*code).map_err(|_| InvalidArgument("exit", "code"))?;
+            Err(Exit(code).into())
+        } else {
+            Err(InvalidArgument("exit", "code").into())
+        }
+    } else {
+        Err(BadArgumentCount("exit", vals.len(), 1).into())
+    }
+}
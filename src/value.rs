@@ -1,70 +1,307 @@
-use super::error::{BadArgumentCount, MissingChild, Unnamed};
-use super::{util, Context, Instruction};
+use super::error::{
+    Error,
+    ErrorKind::{BadArgumentCount, InvalidValue, MissingChild, UnexpectedType, Unnamed},
+};
+use super::runtime::Runtime;
+use super::vm;
+use super::{util, Context, Instruction, Location, Name};
 use roxmltree::Node;
 use std::cell::RefCell;
-use std::error::Error;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::rc::Rc;
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub struct Function {
-    pub args: Vec<String>,
+    pub args: Vec<Name>,
     pub ins: Vec<Instruction>,
 }
 
 impl Function {
-    pub fn run(
-        &self,
-        args: Vec<Value>,
-        ctx: &mut Context,
-        globals: &Context,
-    ) -> Result<Option<Value>, Box<dyn Error>> {
+    pub fn run(&self, args: Vec<Value>, ctx: &mut Context) -> Result<Value, Error> {
         if args.len() != self.args.len() {
             Err(BadArgumentCount("function", args.len(), self.args.len()))?
         }
         self.args
             .iter()
-            .zip(args.into_iter())
-            .for_each(|(p, a)| ctx.assign(p.clone(), a));
+            .zip(args)
+            .try_for_each(|(p, a)| ctx.assign(p, a))?;
         for i in self.ins.iter() {
-            i.run(ctx, globals)?;
+            if let Err(flow) = i.run(ctx) {
+                return flow.escaped();
+            }
         }
-        Ok(ctx.take(&String::from("__return")))
+        Ok(Value::Null)
     }
 
-    pub fn from(fun: &Node<'_, '_>) -> Result<Function, Box<dyn Error>> {
+    pub fn from(fun: &Node<'_, '_>) -> Result<Function, Error> {
+        Function::parse(fun).map_err(|e| e.at(&Location::of(fun)))
+    }
+
+    fn parse(fun: &Node<'_, '_>) -> Result<Function, Error> {
         Ok(Function {
-            args: util::find_node(&fun, "arguments")
-                .ok_or(MissingChild("call", "arguments"))?
+            args: util::find_node(fun, "arguments")
+                .ok_or(MissingChild("function", "arguments"))?
                 .children()
                 .filter(Node::is_element)
-                .map(|n| n.attribute("name").and_then(|s| Some(String::from(s))))
-                .collect::<Option<Vec<String>>>()
+                .map(|n| n.attribute("name").map(Name::new))
+                .collect::<Option<Vec<Name>>>()
                 .ok_or(Unnamed("argument"))?,
             ins: Instruction::from_children(
-                util::find_node(&fun, "body").ok_or(MissingChild("call", "body"))?,
+                util::find_node(fun, "body").ok_or(MissingChild("function", "body"))?,
             )?,
         })
     }
 }
 
+/// A function along with the scope it was defined in.
+#[derive(Clone, Debug)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub env: Context,
+}
+
+impl Closure {
+    pub fn call(&self, args: Vec<Value>) -> Result<Value, Error> {
+        self.function.run(args, &mut Context::new(Some(&self.env)))
+    }
+}
+
+pub type StdFunction = fn(&Runtime, Vec<Value>) -> Result<Value, Error>;
+
+/// A function provided by the program embedding the interpreter.
+#[derive(Clone)]
+pub struct Native(pub Rc<dyn Fn(Vec<Value>) -> Result<Value, Error>>);
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Native {{ .. }}")
+    }
+}
+
+/// Values usable as map keys, which are kept in order.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Key {
+    Integer(i64),
+    String(String),
+}
+
+impl Key {
+    pub fn new(value: &Value) -> Option<Key> {
+        match value {
+            Value::Integer(i) => Some(Key::Integer(*i)),
+            Value::String(s) => Some(Key::String(s.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl From<i64> for Key {
+    fn from(i: i64) -> Key {
+        Key::Integer(i)
+    }
+}
+
+impl From<&str> for Key {
+    fn from(s: &str) -> Key {
+        Key::String(String::from(s))
+    }
+}
+
+impl From<Key> for Value {
+    fn from(key: Key) -> Value {
+        match key {
+            Key::Integer(i) => Value::Integer(i),
+            Key::String(s) => Value::String(s),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum Value {
+    Null,
+    Boolean(bool),
     Integer(i64),
     Real(f64),
     String(String),
     Array(Rc<RefCell<Vec<Value>>>),
-    Function(Function),
-    StdFunction(fn(Vec<Value>) -> Result<Option<Value>, Box<dyn Error>>),
+    Map(Rc<RefCell<BTreeMap<Key, Value>>>),
+    Function(Closure),
+    Compiled(vm::Closure),
+    StdFunction(StdFunction),
+    Native(Native),
 }
 
 impl Value {
     pub fn to_bool(&self) -> bool {
         match self {
+            Value::Null => false,
+            Value::Boolean(b) => *b,
             Value::Integer(i) => *i != 0,
             Value::Real(f) => *f != 0.0,
-            Value::String(s) => s.len() != 0,
-            Value::Array(v) => v.borrow().len() != 0,
+            Value::String(s) => !s.is_empty(),
+            Value::Array(v) => !v.borrow().is_empty(),
+            Value::Map(m) => !m.borrow().is_empty(),
             _ => true,
         }
     }
+
+    /// Calls a function value. Calls to functions written in PL/XML
+    /// from a known `caller` location are recorded in error traces.
+    pub(crate) fn call(
+        &self,
+        args: Vec<Value>,
+        runtime: &Runtime,
+        caller: Option<(&str, &Location)>,
+    ) -> Result<Value, Error> {
+        let traced = |e: Error| match caller {
+            Some((name, location)) => e.called(name, location),
+            None => e,
+        };
+        match self {
+            Value::Function(f) => runtime.nested(|| f.call(args)).map_err(traced),
+            Value::Compiled(f) => runtime.nested(|| f.call(args)).map_err(traced),
+            Value::StdFunction(f) => f(runtime, args),
+            Value::Native(f) => (f.0)(args),
+            _ => Err(InvalidValue("call"))?,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Boolean(b) => write!(f, "{}", b),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Real(r) => write!(f, "{}", r),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Function(_) | Value::Compiled(_) | Value::StdFunction(_) | Value::Native(_) => {
+                write!(f, "<function>")
+            }
+            v => write!(f, "{:?}", v),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Boolean(b)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(i: i64) -> Value {
+        Value::Integer(i)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(f: f64) -> Value {
+        Value::Real(f)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(String::from(s))
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(values: Vec<T>) -> Value {
+        Value::Array(Rc::new(RefCell::new(
+            values.into_iter().map(Into::into).collect(),
+        )))
+    }
+}
+
+impl<T: Into<Value>> From<BTreeMap<Key, T>> for Value {
+    fn from(entries: BTreeMap<Key, T>) -> Value {
+        Value::Map(Rc::new(RefCell::new(
+            entries.into_iter().map(|(k, v)| (k, v.into())).collect(),
+        )))
+    }
+}
+
+impl TryFrom<Value> for bool {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<bool, Error> {
+        match value {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(UnexpectedType("boolean"))?,
+        }
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<i64, Error> {
+        match value {
+            Value::Integer(i) => Ok(i),
+            _ => Err(UnexpectedType("integer"))?,
+        }
+    }
+}
+
+/// Integers are converted to reals, as in arithmetic operations.
+impl TryFrom<Value> for f64 {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<f64, Error> {
+        match value {
+            Value::Integer(i) => Ok(i as f64),
+            Value::Real(f) => Ok(f),
+            _ => Err(UnexpectedType("real"))?,
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<String, Error> {
+        match value {
+            Value::String(s) => Ok(s),
+            _ => Err(UnexpectedType("string"))?,
+        }
+    }
+}
+
+/// Arrays are copied, since the program may still hold them.
+impl TryFrom<Value> for Vec<Value> {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<Vec<Value>, Error> {
+        match value {
+            Value::Array(v) => Ok(v.borrow().clone()),
+            _ => Err(UnexpectedType("array"))?,
+        }
+    }
+}
+
+/// Maps are copied, since the program may still hold them.
+impl TryFrom<Value> for BTreeMap<Key, Value> {
+    type Error = Error;
+
+    fn try_from(value: Value) -> Result<BTreeMap<Key, Value>, Error> {
+        match value {
+            Value::Map(m) => Ok(m.borrow().clone()),
+            _ => Err(UnexpectedType("map"))?,
+        }
+    }
 }
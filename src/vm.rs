@@ -0,0 +1,573 @@
+use super::bytecode::{self, Op, Proto, Slot, Variable};
+use super::error::{
+    Error,
+    ErrorKind::{BadArgumentCount, InvalidValue, Raised, UnknownVariable},
+};
+use super::instruction::{Catch, Flow, Instruction};
+use super::value::Key;
+use super::{Context, Program, Value};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// Local variables of a running function.
+pub struct Env {
+    slots: RefCell<Vec<Option<Local>>>,
+    parent: Option<Rc<Env>>,
+}
+
+/// Value of a local variable.
+#[derive(Clone)]
+enum Local {
+    Value(Value),
+    /// Function defined in the very variables it is kept in, which are
+    /// only attached to it once read, as they would keep each other alive.
+    Function(Rc<Proto>),
+}
+
+impl Env {
+    fn new(size: usize, parent: Option<Rc<Env>>) -> Env {
+        Env {
+            slots: RefCell::new(vec![None; size]),
+            parent,
+        }
+    }
+
+    fn outer(self: &Rc<Env>, depth: usize) -> Option<&Rc<Env>> {
+        match depth {
+            0 => Some(self),
+            _ => self.parent.as_ref().and_then(|p| p.outer(depth - 1)),
+        }
+    }
+
+    fn get(self: &Rc<Env>, slot: usize, machine: &Rc<Machine>) -> Option<Value> {
+        Some(match self.slots.borrow()[slot].as_ref()? {
+            Local::Value(value) => value.clone(),
+            Local::Function(proto) => Value::Compiled(Closure {
+                proto: Rc::clone(proto),
+                env: Some(Rc::clone(self)),
+                machine: Rc::clone(machine),
+            }),
+        })
+    }
+
+    fn set(self: &Rc<Env>, slot: usize, value: Value) {
+        let local = match value {
+            Value::Compiled(closure)
+                if closure
+                    .env
+                    .as_ref()
+                    .is_some_and(|env| Rc::ptr_eq(env, self)) =>
+            {
+                Local::Function(closure.proto)
+            }
+            value => Local::Value(value),
+        };
+        self.slots.borrow_mut()[slot] = Some(local);
+    }
+}
+
+/// A compiled function along with the variables it was defined with.
+#[derive(Clone)]
+pub struct Closure {
+    pub proto: Rc<Proto>,
+    pub env: Option<Rc<Env>>,
+    machine: Rc<Machine>,
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // environments may contain closures referring back to them
+        write!(f, "Closure {{ .. }}")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Try,
+    Catch,
+    Finally,
+}
+
+enum Iteration {
+    Range {
+        next: Option<i64>,
+        to: i64,
+        step: i64,
+    },
+    Entries(std::vec::IntoIter<(Value, Value)>),
+    While,
+}
+
+/// Loop or error handler entered in the running function, along with
+/// the height of the stack it was entered at.
+enum Block {
+    Loop {
+        info: usize,
+        height: usize,
+        iteration: Iteration,
+    },
+    Handler {
+        info: usize,
+        height: usize,
+        phase: Phase,
+        /// What left the try or catch block, resumed after the
+        /// finally block.
+        pending: Option<Flow>,
+    },
+}
+
+#[derive(Default)]
+struct Frame {
+    stack: Vec<Value>,
+    blocks: Vec<Block>,
+}
+
+impl Frame {
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("vm stack")
+    }
+
+    fn take(&mut self, count: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count)
+    }
+}
+
+/// Stack machine running compiled programs, with the same semantics
+/// as the tree-walking interpreter.
+pub struct Machine {
+    globals: Context,
+}
+
+/// Compiles a program, defining its functions among `globals`, and
+/// gives its main block as a function without arguments.
+pub fn load(program: &Program, globals: &mut Context) -> Result<Closure, Error> {
+    let module = bytecode::compile(program);
+    let machine = Rc::new(Machine {
+        globals: globals.clone(),
+    });
+    for (name, proto) in module.functions {
+        let closure = Closure {
+            proto,
+            env: None,
+            machine: Rc::clone(&machine),
+        };
+        globals.assign(&name, Value::Compiled(closure))?;
+    }
+    Ok(Closure {
+        proto: module.main,
+        env: None,
+        machine,
+    })
+}
+
+impl Closure {
+    pub fn call(&self, args: Vec<Value>) -> Result<Value, Error> {
+        self.machine.call(self, args)
+    }
+}
+
+impl Machine {
+    fn call(self: &Rc<Machine>, closure: &Closure, args: Vec<Value>) -> Result<Value, Error> {
+        let proto = &closure.proto;
+        if args.len() != proto.args.len() {
+            Err(BadArgumentCount("function", args.len(), proto.args.len()))?
+        }
+        let env = Rc::new(Env::new(proto.slots, closure.env.clone()));
+        for (slot, arg) in proto.args.iter().zip(args) {
+            env.set(*slot, arg);
+        }
+        self.execute(proto, &env).or_else(Flow::escaped)
+    }
+
+    fn execute(self: &Rc<Machine>, proto: &Proto, env: &Rc<Env>) -> Result<Value, Flow> {
+        let mut frame = Frame::default();
+        let mut pc = 0;
+        loop {
+            let op = proto.code[pc];
+            pc += 1;
+            if let Err(flow) = self.step(proto, env, &mut frame, op, &mut pc) {
+                pc = Machine::unwind(proto, env, &mut frame, flow.at(&proto.locations[pc - 1]))?;
+            }
+        }
+    }
+
+    fn load(self: &Rc<Machine>, env: &Rc<Env>, variable: &Variable) -> Option<Value> {
+        variable.slots.iter().find_map(|slot| match slot {
+            Slot::Local(s) => env.get(*s, self),
+            Slot::Outer(depth, s) => env.outer(*depth).and_then(|e| e.get(*s, self)),
+            Slot::Global => self.globals.value(&variable.name),
+        })
+    }
+
+    /// Updates a variable defined outside of the current function.
+    fn store(&self, env: &Rc<Env>, variable: &Variable, value: Value) -> Result<(), Error> {
+        for slot in &variable.slots {
+            match slot {
+                Slot::Local(_) => {}
+                Slot::Outer(depth, s) => {
+                    if let Some(outer) = env.outer(*depth) {
+                        if outer.slots.borrow()[*s].is_some() {
+                            outer.set(*s, value);
+                            return Ok(());
+                        }
+                    }
+                }
+                Slot::Global => return self.globals.update(&variable.name, value),
+            }
+        }
+        Err(UnknownVariable(variable.name.to_string()))?
+    }
+
+    fn step(
+        self: &Rc<Machine>,
+        proto: &Proto,
+        env: &Rc<Env>,
+        frame: &mut Frame,
+        op: Op,
+        pc: &mut usize,
+    ) -> Result<(), Flow> {
+        let runtime = self.globals.runtime();
+        runtime.step()?;
+        match op {
+            Op::Push(c) => frame.stack.push(proto.constants[c].clone()),
+            Op::Fail(e) => Err(proto.errors[e].clone())?,
+            Op::Load(n) => {
+                let variable = &proto.variables[n];
+                let value = self
+                    .load(env, variable)
+                    .ok_or_else(|| UnknownVariable(variable.name.to_string()))?;
+                frame.stack.push(value);
+            }
+            Op::Store(s) => {
+                let value = frame.pop();
+                env.set(s, value);
+            }
+            Op::StoreOuter(n) => {
+                let value = frame.pop();
+                self.store(env, &proto.variables[n], value)?;
+            }
+            Op::StoreGlobal(n) => {
+                let value = frame.pop();
+                self.globals
+                    .assign_global(&proto.variables[n].name, value)?;
+            }
+            Op::Pop => {
+                frame.pop();
+            }
+            Op::Array(n) => {
+                let vals = frame.take(n);
+                runtime.sandbox.size(vals.len())?;
+                frame.stack.push(Value::Array(Rc::new(RefCell::new(vals))));
+            }
+            Op::Key => {
+                let key = frame.stack.last().expect("vm stack");
+                Key::new(key).ok_or(InvalidValue("entry"))?;
+            }
+            Op::Map(n) => {
+                let mut vals = frame.take(2 * n).into_iter();
+                let mut map = BTreeMap::new();
+                while let (Some(k), Some(v)) = (vals.next(), vals.next()) {
+                    map.insert(Key::new(&k).ok_or(InvalidValue("entry"))?, v);
+                }
+                runtime.sandbox.size(map.len())?;
+                frame.stack.push(Value::Map(Rc::new(RefCell::new(map))));
+            }
+            Op::IntegerCast => {
+                let value = Instruction::integer(frame.pop())?;
+                frame.stack.push(value);
+            }
+            Op::RealCast => {
+                let value = Instruction::real(frame.pop())?;
+                frame.stack.push(value);
+            }
+            Op::StringCast => {
+                let value = Instruction::string(frame.pop())?;
+                frame.stack.push(value);
+            }
+            Op::Add(n) => {
+                let value = Instruction::add(frame.take(n))?;
+                runtime.sized(&value)?;
+                frame.stack.push(value);
+            }
+            Op::Subtract(n) => {
+                let value = Instruction::subtract(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::Multiply(n) => {
+                let value = Instruction::multiply(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::Divide(n) => {
+                let value = Instruction::divide(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::Modulo(n) => {
+                let value = Instruction::modulo(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::IntDivide(n) => {
+                let value = Instruction::int_divide(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::Power(n) => {
+                let value = Instruction::power(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::Negate => {
+                let value = Instruction::negate(frame.pop())?;
+                frame.stack.push(value);
+            }
+            Op::BitAnd(n) => {
+                let value = Instruction::bit_and(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::BitOr(n) => {
+                let value = Instruction::bit_or(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::BitXor(n) => {
+                let value = Instruction::bit_xor(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::ShiftLeft(n) => {
+                let value = Instruction::shift_left(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::ShiftRight(n) => {
+                let value = Instruction::shift_right(frame.take(n))?;
+                frame.stack.push(value);
+            }
+            Op::And(n) => {
+                let value = Instruction::and(frame.take(n));
+                frame.stack.push(value);
+            }
+            Op::Or(n) => {
+                let value = Instruction::or(frame.take(n));
+                frame.stack.push(value);
+            }
+            Op::Not => {
+                let value = !frame.pop().to_bool();
+                frame.stack.push(Value::Boolean(value));
+            }
+            Op::Equal => {
+                let v2 = frame.pop();
+                let v1 = frame.pop();
+                frame
+                    .stack
+                    .push(Value::Boolean(Instruction::equals(v1, v2)?));
+            }
+            Op::Greater => {
+                let v2 = frame.pop();
+                let v1 = frame.pop();
+                frame
+                    .stack
+                    .push(Value::Boolean(Instruction::compare(v1, v2)?.is_gt()));
+            }
+            Op::Lower => {
+                let v2 = frame.pop();
+                let v1 = frame.pop();
+                frame
+                    .stack
+                    .push(Value::Boolean(Instruction::compare(v1, v2)?.is_lt()));
+            }
+            Op::Call(count, name) => {
+                let function = frame.pop();
+                let args = frame.take(count);
+                let caller = (proto.strings[name].as_str(), &proto.locations[*pc - 1]);
+                let value = function.call(args, runtime, Some(caller))?;
+                runtime.sized(&value)?;
+                frame.stack.push(value);
+            }
+            Op::Return => Err(Flow::Return(frame.pop()))?,
+            Op::Jump(target) => *pc = target,
+            Op::JumpUnless(target) => {
+                if !frame.pop().to_bool() {
+                    *pc = target;
+                }
+            }
+            Op::SkipUnlessInteger(target, drop) => {
+                if !matches!(frame.stack.last(), Some(Value::Integer(_))) {
+                    frame.stack.truncate(frame.stack.len() - drop);
+                    *pc = target;
+                }
+            }
+            Op::For(info) => {
+                let bounds = frame.take(3);
+                if let [Value::Integer(from), Value::Integer(to), Value::Integer(step)] = bounds[..]
+                {
+                    if step <= 0 {
+                        Err(InvalidValue("for"))?
+                    }
+                    frame.blocks.push(Block::Loop {
+                        info,
+                        height: frame.stack.len(),
+                        iteration: Iteration::Range {
+                            next: Some(from),
+                            to,
+                            step,
+                        },
+                    });
+                }
+            }
+            Op::ForNext(slot, exit) => {
+                if let Some(Block::Loop {
+                    iteration: Iteration::Range { next, to, step },
+                    ..
+                }) = frame.blocks.last_mut()
+                {
+                    match next {
+                        Some(i) if *i < *to => {
+                            env.set(slot, Value::Integer(*i));
+                            *next = i.checked_add(*step);
+                        }
+                        _ => *pc = exit,
+                    }
+                }
+            }
+            Op::Each(info) => {
+                // iterate over a snapshot, the body may modify the collection
+                let entries: Vec<(Value, Value)> = match frame.pop() {
+                    Value::Array(v) => v
+                        .borrow()
+                        .iter()
+                        .enumerate()
+                        .map(|(i, v)| (Value::Integer(i as i64), v.clone()))
+                        .collect(),
+                    Value::Map(m) => m
+                        .borrow()
+                        .iter()
+                        .map(|(k, v)| (Value::from(k.clone()), v.clone()))
+                        .collect(),
+                    _ => Err(InvalidValue("each"))?,
+                };
+                frame.blocks.push(Block::Loop {
+                    info,
+                    height: frame.stack.len(),
+                    iteration: Iteration::Entries(entries.into_iter()),
+                });
+            }
+            Op::EachNext(key, slot, exit) => {
+                if let Some(Block::Loop {
+                    iteration: Iteration::Entries(entries),
+                    ..
+                }) = frame.blocks.last_mut()
+                {
+                    match entries.next() {
+                        Some((k, v)) => {
+                            if let Some(key) = key {
+                                env.set(key, k);
+                            }
+                            env.set(slot, v);
+                        }
+                        None => *pc = exit,
+                    }
+                }
+            }
+            Op::While(info) => frame.blocks.push(Block::Loop {
+                info,
+                height: frame.stack.len(),
+                iteration: Iteration::While,
+            }),
+            Op::EndLoop => {
+                frame.blocks.pop();
+            }
+            Op::Break(label) => Err(Flow::Break(
+                label.map(|l| proto.strings[l].clone()),
+                proto.locations[*pc - 1].clone(),
+            ))?,
+            Op::Continue(label) => Err(Flow::Continue(
+                label.map(|l| proto.strings[l].clone()),
+                proto.locations[*pc - 1].clone(),
+            ))?,
+            Op::Try(info) => frame.blocks.push(Block::Handler {
+                info,
+                height: frame.stack.len(),
+                phase: Phase::Try,
+                pending: None,
+            }),
+            Op::Finally(info) => {
+                if let Some(Block::Handler { phase, .. }) = frame.blocks.last_mut() {
+                    *phase = Phase::Finally;
+                }
+                *pc = proto.handlers[info].finally;
+            }
+            Op::EndFinally => {
+                if let Some(Block::Handler {
+                    pending: Some(flow),
+                    ..
+                }) = frame.blocks.pop()
+                {
+                    Err(flow)?
+                }
+            }
+            Op::Throw(tag) => {
+                let value = frame.pop();
+                Err(Raised(value, tag.map(|t| proto.strings[t].clone())))?
+            }
+            Op::Closure(p) => frame.stack.push(Value::Compiled(Closure {
+                proto: Rc::clone(&proto.protos[p]),
+                env: Some(Rc::clone(env)),
+                machine: Rc::clone(self),
+            })),
+        }
+        Ok(())
+    }
+
+    /// Leaves the blocks a flow goes through until one handles it,
+    /// giving where to resume, or the flow if it leaves the function.
+    fn unwind(proto: &Proto, env: &Rc<Env>, frame: &mut Frame, flow: Flow) -> Result<usize, Flow> {
+        while let Some(block) = frame.blocks.last_mut() {
+            match block {
+                Block::Loop { info, height, .. } => {
+                    let info = &proto.loops[*info];
+                    match &flow {
+                        Flow::Break(target, _) if target.is_none() || *target == info.label => {
+                            frame.stack.truncate(*height);
+                            return Ok(info.end);
+                        }
+                        Flow::Continue(target, _) if target.is_none() || *target == info.label => {
+                            frame.stack.truncate(*height);
+                            return Ok(info.next);
+                        }
+                        _ => {}
+                    }
+                }
+                Block::Handler {
+                    info,
+                    height,
+                    phase,
+                    pending,
+                } => {
+                    let handler = &proto.handlers[*info];
+                    // exiting skips finally blocks as well as catch blocks,
+                    // and anything leaving a finally block takes precedence
+                    // over the outcome of the try and catch blocks
+                    let uncaught = matches!(&flow, Flow::Error(e) if !e.catchable());
+                    if !uncaught && *phase != Phase::Finally {
+                        frame.stack.truncate(*height);
+                        if let (Phase::Try, Flow::Error(e)) = (*phase, &flow) {
+                            let catch = handler
+                                .catches
+                                .iter()
+                                .find(|c| c.filter.as_ref().is_none_or(|f| e.is(f)));
+                            if let Some(catch) = catch {
+                                if let Some(variable) = catch.variable {
+                                    env.set(variable, Catch::value(e));
+                                }
+                                *phase = Phase::Catch;
+                                return Ok(catch.target);
+                            }
+                        }
+                        *phase = Phase::Finally;
+                        *pending = Some(flow);
+                        return Ok(handler.finally);
+                    }
+                }
+            }
+            frame.blocks.pop();
+        }
+        Err(flow)
+    }
+}
@@ -0,0 +1,121 @@
+//! Evaluates arithmetic and bitwise operations, which must give the
+//! same values and errors with every engine, folded or not.
+
+use plxml::{Buffer, Options};
+
+/// What printing an expression gives, or the code of its error, which
+/// must be the same for every engine.
+fn evaluate(expression: &str) -> Result<String, &'static str> {
+    let program = format!(
+        r#"<program name="arithmetic">
+            <main><call function="print"><arguments>{}</arguments></call></main>
+        </program>"#,
+        expression
+    );
+    let mut results = [(false, false), (true, false), (false, true), (true, true)]
+        .iter()
+        .map(|(bytecode, optimize)| {
+            let output = Buffer::new();
+            let mut options = Options::new();
+            options.bytecode(*bytecode).optimize(*optimize);
+            let mut interpreter = options.interpreter();
+            interpreter.output(output.clone());
+            match interpreter.run(&program) {
+                Ok(_) => Ok(output.contents()),
+                Err(e) => Err(e.code()),
+            }
+        });
+    let result = results.next().expect("tree walker");
+    for other in results {
+        assert_eq!(other, result, "{}", expression);
+    }
+    result
+}
+
+/// An operation on literal operands, written as `i:1` for integers,
+/// `r:1.5` for reals and as they are for strings.
+fn operation(tag: &str, operands: &[&str]) -> String {
+    let operands: String = operands
+        .iter()
+        .map(|operand| match operand.split_once(':') {
+            Some(("i", v)) => format!(r#"<integer value="{}" />"#, v),
+            Some(("r", v)) => format!(r#"<real value="{}" />"#, v),
+            _ => format!(r#"<string value="{}" />"#, operand),
+        })
+        .collect();
+    format!("<{0}>{1}</{0}>", tag, operands)
+}
+
+fn check(cases: &[(&str, &[&str], Result<&str, &str>)]) {
+    for (tag, operands, expected) in cases {
+        let expression = operation(tag, operands);
+        assert_eq!(
+            evaluate(&expression),
+            expected.map(String::from),
+            "{}",
+            expression
+        );
+    }
+}
+
+#[test]
+fn division() {
+    check(&[
+        ("int-divide", &["i:127", "i:3"], Ok("42")),
+        ("int-divide", &["i:-7", "i:2"], Ok("-3")),
+        ("int-divide", &["r:7.5", "i:2"], Ok("3")),
+        ("modulo", &["i:127", "i:3"], Ok("1")),
+        ("modulo", &["i:-7", "i:2"], Ok("-1")),
+        ("modulo", &["i:7", "i:-2"], Ok("1")),
+        ("modulo", &["r:7.5", "i:2"], Ok("1.5")),
+        ("divide", &["i:1", "i:0"], Err("E205")),
+        ("int-divide", &["i:1", "i:0"], Err("E205")),
+        ("modulo", &["i:1", "i:0"], Err("E205")),
+        ("modulo", &["r:1.5", "r:0.0"], Err("E205")),
+        (
+            "int-divide",
+            &["i:-9223372036854775808", "i:-1"],
+            Err("E206"),
+        ),
+        ("modulo", &["i:-9223372036854775808", "i:-1"], Err("E206")),
+        ("modulo", &["x", "i:2"], Err("E201")),
+    ]);
+}
+
+#[test]
+fn power_and_negation() {
+    check(&[
+        ("power", &["i:2", "i:10"], Ok("1024")),
+        ("power", &["i:2", "i:3", "i:2"], Ok("64")),
+        ("power", &["r:2.0", "i:-1"], Ok("0.5")),
+        ("power", &["i:4", "r:0.5"], Ok("2")),
+        ("power", &["i:2", "i:-1"], Err("E201")),
+        ("power", &["i:2", "i:63"], Err("E206")),
+        ("negate", &["i:5"], Ok("-5")),
+        ("negate", &["r:4.2"], Ok("-4.2")),
+        ("negate", &["i:-9223372036854775808"], Err("E206")),
+        ("negate", &["x"], Err("E201")),
+    ]);
+}
+
+#[test]
+fn bitwise_operations() {
+    check(&[
+        ("bit-and", &["i:12", "i:10"], Ok("8")),
+        ("bit-or", &["i:12", "i:10"], Ok("14")),
+        ("bit-xor", &["i:12", "i:10", "i:1"], Ok("7")),
+        ("bit-and", &["r:1.5"], Err("E201")),
+        ("bit-or", &[], Err("E105")),
+        ("shift-left", &["i:1", "i:8"], Ok("256")),
+        // bits shifted out are lost rather than overflowing
+        (
+            "shift-left",
+            &["i:1", "i:62", "i:1"],
+            Ok("-9223372036854775808"),
+        ),
+        ("shift-right", &["i:-16", "i:2"], Ok("-4")),
+        ("shift-left", &["i:1", "i:64"], Err("E201")),
+        ("shift-right", &["i:1", "i:-1"], Err("E201")),
+        ("shift-left", &["r:1.0", "i:1"], Err("E201")),
+    ]);
+}
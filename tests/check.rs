@@ -0,0 +1,150 @@
+//! Checks programs statically, asserting what is reported and where.
+
+use plxml::check;
+
+/// Code, message and line of each problem reported.
+fn diagnostics(program: &str) -> Vec<(&'static str, String, u32)> {
+    check(String::from(program))
+        .expect("checked program")
+        .into_iter()
+        .map(|d| (d.kind.code(), d.kind.to_string(), d.location.line))
+        .collect()
+}
+
+#[test]
+fn clean_program() {
+    let program = r#"<program name="clean">
+        <function name="square">
+            <arguments><argument name="x" /></arguments>
+            <body>
+                <return><multiply><value variable="x" /><value variable="x" /></multiply></return>
+            </body>
+        </function>
+        <main>
+            <assign variable="y"><integer value="3" /></assign>
+            <call function="print-line">
+                <arguments><call function="square"><arguments><value variable="y" /></arguments></call></arguments>
+            </call>
+        </main>
+    </program>"#;
+    assert_eq!(diagnostics(program), Vec::new());
+}
+
+#[test]
+fn undefined_names() {
+    let program = r#"<program name="undefined">
+        <main>
+            <call function="print-line">
+                <arguments><value variable="missing" /></arguments>
+            </call>
+            <call function="nowhere"><arguments /></call>
+        </main>
+    </program>"#;
+    assert_eq!(
+        diagnostics(program),
+        vec![
+            ("W001", String::from("undefined name 'missing'"), 4),
+            ("W001", String::from("undefined name 'nowhere'"), 6),
+        ]
+    );
+}
+
+#[test]
+fn arity_mismatches() {
+    let program = r#"<program name="arity">
+        <function name="pair">
+            <arguments><argument name="a" /><argument name="b" /></arguments>
+            <body><return><array><value variable="a" /><value variable="b" /></array></return></body>
+        </function>
+        <main>
+            <call function="pair"><arguments><integer value="1" /></arguments></call>
+            <call function="print-line"><arguments /></call>
+        </main>
+    </program>"#;
+    assert_eq!(
+        diagnostics(program),
+        vec![
+            (
+                "W002",
+                String::from("bad argument count (1, expected 2) in call to 'pair'"),
+                7
+            ),
+            (
+                "W002",
+                String::from("bad argument count (0, expected 1) in call to 'print-line'"),
+                8
+            ),
+        ]
+    );
+}
+
+#[test]
+fn unreachable_instructions() {
+    let program = r#"<program name="unreachable">
+        <function name="early">
+            <arguments />
+            <body>
+                <return><integer value="1" /></return>
+                <call function="print-line"><arguments><string value="never" /></arguments></call>
+                <call function="print-line"><arguments><string value="reported once" /></arguments></call>
+            </body>
+        </function>
+        <main>
+            <while>
+                <true />
+                <do>
+                    <break />
+                    <call function="early"><arguments /></call>
+                </do>
+            </while>
+        </main>
+    </program>"#;
+    assert_eq!(
+        diagnostics(program),
+        vec![
+            ("W003", String::from("unreachable instruction"), 6),
+            ("W003", String::from("unreachable instruction"), 15),
+        ]
+    );
+}
+
+#[test]
+fn unused_variables() {
+    let program = r#"<program name="unused">
+        <function name="f">
+            <arguments><argument name="ignored" /></arguments>
+            <body>
+                <assign variable="kept"><integer value="1" /></assign>
+                <assign variable="lost"><integer value="2" /></assign>
+                <return><value variable="kept" /></return>
+            </body>
+        </function>
+        <main>
+            <call function="f"><arguments><null /></arguments></call>
+        </main>
+    </program>"#;
+    // arguments are not reported, as the caller decides on them
+    assert_eq!(
+        diagnostics(program),
+        vec![("W004", String::from("unused variable 'lost'"), 6)]
+    );
+}
+
+#[test]
+fn names_assigned_later_in_a_body() {
+    let program = r#"<program name="later">
+        <main>
+            <assign variable="show">
+                <lambda>
+                    <arguments />
+                    <body>
+                        <call function="print-line"><arguments><value variable="message" /></arguments></call>
+                    </body>
+                </lambda>
+            </assign>
+            <assign variable="message"><string value="hello" /></assign>
+            <call><value variable="show" /><arguments /></call>
+        </main>
+    </program>"#;
+    assert_eq!(diagnostics(program), Vec::new());
+}
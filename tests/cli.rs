@@ -0,0 +1,155 @@
+//! Runs the `plxml` command, checking its output and exit status.
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Exit status, standard output and standard error of the command run
+/// with the given arguments and input.
+fn plxml(args: &[&str], input: &str) -> (i32, String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_plxml"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("plxml");
+    child
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(input.as_bytes())
+        .expect("input");
+    let output = child.wait_with_output().expect("output");
+    (
+        output.status.code().expect("exit status"),
+        String::from_utf8(output.stdout).expect("utf-8"),
+        String::from_utf8(output.stderr).expect("utf-8"),
+    )
+}
+
+/// Writes a program to a file of its own in the temporary directory.
+fn file(name: &str, program: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("plxml-{}-{}", std::process::id(), name));
+    fs::write(&path, program).expect("program file");
+    path
+}
+
+/// Prints the arguments of the program, one per line.
+const ARGS: &str = r#"<program name="args">
+    <main>
+        <each variable="arg">
+            <call function="get-args"><arguments /></call>
+            <do><call function="print-line"><arguments><value variable="arg" /></arguments></call></do>
+        </each>
+    </main>
+</program>"#;
+
+#[test]
+fn program_arguments() {
+    let path = file("args.pl.xml", ARGS);
+    let path = path.to_str().expect("path");
+    assert_eq!(
+        plxml(&["run", path, "--", "a", "--strict"], ""),
+        (0, String::from("a\n--strict\n"), String::new())
+    );
+    assert_eq!(
+        plxml(&["run", path, "b"], ""),
+        (0, String::from("b\n"), String::new())
+    );
+    // running is the default command
+    assert_eq!(
+        plxml(&[path, "c"], ""),
+        (0, String::from("c\n"), String::new())
+    );
+    assert_eq!(
+        plxml(&["run", "--bytecode", "-", "--", "d"], ARGS),
+        (0, String::from("d\n"), String::new())
+    );
+    fs::remove_file(path).expect("remove program file");
+}
+
+#[test]
+fn exit_status() {
+    let program = r#"<program name="exit">
+        <main>
+            <call function="print-line"><arguments><string value="before" /></arguments></call>
+            <call function="exit"><arguments><integer value="7" /></arguments></call>
+            <call function="print-line"><arguments><string value="after" /></arguments></call>
+        </main>
+    </program>"#;
+    for engine in ["--optimize", "--bytecode"] {
+        assert_eq!(
+            plxml(&["run", engine, "-"], program),
+            (7, String::from("before\n"), String::new())
+        );
+    }
+}
+
+#[test]
+fn errors_fail() {
+    let program = r#"<program name="error">
+        <main>
+            <value variable="missing" />
+        </main>
+    </program>"#;
+    let (code, stdout, stderr) = plxml(&["run", "-"], program);
+    assert_eq!((code, stdout.as_str()), (1, ""));
+    assert!(stderr.starts_with("Error occurred: [E400]"), "{}", stderr);
+
+    let (code, _, stderr) = plxml(&["run", "missing.pl.xml"], "");
+    assert_eq!(code, 1);
+    assert!(stderr.starts_with("Error occurred: [E500]"), "{}", stderr);
+
+    let (code, _, stderr) = plxml(&["fmt", "-"], "<program></main>");
+    assert_eq!(code, 1);
+    assert!(stderr.starts_with("Error occurred: [E100]"), "{}", stderr);
+}
+
+#[test]
+fn usage() {
+    for args in [&[][..], &["run"], &["check", "a", "b"], &["fmt"], &["dump"]] {
+        let (code, stdout, stderr) = plxml(args, "");
+        assert_eq!((code, stdout.as_str()), (2, ""), "{:?}", args);
+        assert!(stderr.starts_with("usage: plxml"), "{:?}", args);
+    }
+    let (code, stdout, _) = plxml(&["help"], "");
+    assert_eq!(code, 0);
+    assert!(stdout.starts_with("usage: plxml"));
+}
+
+#[test]
+fn check_reports_problems() {
+    let program =
+        "<program name=\"check\">\n<main>\n<value variable=\"missing\" />\n</main>\n</program>";
+    assert_eq!(
+        plxml(&["check", "-"], program),
+        (
+            1,
+            String::from("-:3:1: [W001] undefined name 'missing'\n"),
+            String::new()
+        )
+    );
+    assert_eq!(
+        plxml(&["check", "-"], ARGS),
+        (0, String::new(), String::new())
+    );
+}
+
+#[test]
+fn fmt_reindents() {
+    assert_eq!(
+        plxml(
+            &["fmt", "-"],
+            "<program name=\"fmt\"><main><true/></main></program>"
+        ),
+        (
+            0,
+            String::from(
+                "<program name=\"fmt\">\n    <main>\n        <true />\n    </main>\n</program>\n"
+            ),
+            String::new()
+        )
+    );
+}
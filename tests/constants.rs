@@ -0,0 +1,170 @@
+//! Declares constants, which keep their value for as long as the
+//! interpreter does.
+
+use plxml::repl::Session;
+use plxml::{Buffer, Interpreter, Options};
+use std::fs;
+
+/// Declares `K`, and prints it from a function.
+const CONSTANT: &str = r#"<program name="constant">
+    <const name="K"><integer value="1" /></const>
+    <function name="show">
+        <arguments />
+        <body><call function="print-line"><arguments><value variable="K" /></arguments></call></body>
+    </function>
+    <main><call function="show"><arguments /></call></main>
+</program>"#;
+
+/// Interpreters for every engine, with the buffer they print to.
+fn interpreters() -> Vec<(Interpreter, Buffer)> {
+    [false, true]
+        .iter()
+        .map(|bytecode| {
+            let output = Buffer::new();
+            let mut interpreter = Options::new().bytecode(*bytecode).interpreter();
+            interpreter.output(output.clone());
+            (interpreter, output)
+        })
+        .collect()
+}
+
+/// Code of the error a program fails with.
+fn failure(interpreter: &mut Interpreter, program: &str) -> &'static str {
+    interpreter.run(program).expect_err(program).code()
+}
+
+#[test]
+fn assignments_fail() {
+    let global = CONSTANT.replace(
+        "<main>",
+        r#"<main><assign variable="K" scope="global"><integer value="2" /></assign>"#,
+    );
+    let outer = CONSTANT.replace(
+        "<main>",
+        r#"<function name="change">
+            <arguments />
+            <body><assign variable="K" scope="outer"><integer value="2" /></assign></body>
+        </function>
+        <main><call function="change"><arguments /></call>"#,
+    );
+    for (mut interpreter, output) in interpreters() {
+        assert_eq!(failure(&mut interpreter, &global), "E112");
+        assert_eq!(failure(&mut interpreter, &outer), "E112");
+        assert_eq!(interpreter.run(CONSTANT).expect("run"), 0);
+        assert_eq!(output.contents(), "1\n");
+    }
+}
+
+#[test]
+fn assignments_by_later_programs_fail() {
+    let later = r#"<program name="later">
+        <main><assign variable="K" scope="global"><integer value="2" /></assign></main>
+    </program>"#;
+    let redeclared = r#"<program name="redeclared">
+        <global name="K"><integer value="2" /></global>
+        <main />
+    </program>"#;
+    for (mut interpreter, output) in interpreters() {
+        interpreter.run(CONSTANT).expect("run");
+        assert_eq!(failure(&mut interpreter, later), "E112");
+        assert_eq!(failure(&mut interpreter, redeclared), "E112");
+        // but the program declaring it may run again
+        interpreter.run(CONSTANT).expect("run again");
+        assert_eq!(output.contents(), "1\n1\n");
+    }
+}
+
+#[test]
+fn assignments_by_the_host_fail() {
+    for (mut interpreter, _) in interpreters() {
+        interpreter.set("K", 0).expect("variable");
+        interpreter.load(CONSTANT).expect("load");
+        let error = interpreter.set("K", 2).expect_err("constant");
+        assert_eq!(error.code(), "E112");
+        let error = interpreter
+            .register("K", |_| Ok(2.into()))
+            .expect_err("constant");
+        assert_eq!(error.code(), "E112");
+        assert_eq!(interpreter.get("K").expect("K").to_string(), "1");
+    }
+}
+
+#[test]
+fn assignments_in_the_repl_fail() {
+    let path = std::env::temp_dir().join(format!("plxml-{}-constant.pl.xml", std::process::id()));
+    fs::write(&path, CONSTANT).expect("program file");
+    let mut session = Session::new();
+    session.load(path.to_str().expect("path")).expect("load");
+    fs::remove_file(&path).expect("remove program file");
+    for fragment in [
+        r#"<assign variable="K"><integer value="2" /></assign>"#,
+        r#"<assign variable="K" scope="global"><integer value="2" /></assign>"#,
+        r#"<for variable="K"><from><integer value="0" /></from><to><integer value="1" /></to><step><integer value="1" /></step><do /></for>"#,
+    ] {
+        let error = session.eval(fragment).expect_err(fragment);
+        assert_eq!(error.code(), "E112", "{}", fragment);
+    }
+    let value = session.eval(r#"<value variable="K" />"#).expect("K");
+    assert_eq!(value.expect("value").to_string(), "1");
+}
+
+#[test]
+fn local_variables_hide_constants() {
+    let program = r#"<program name="hidden">
+        <const name="K"><integer value="1" /></const>
+        <function name="argument">
+            <arguments><argument name="K" /></arguments>
+            <body><return><value variable="K" /></return></body>
+        </function>
+        <function name="loops">
+            <arguments />
+            <body>
+                <assign variable="sum"><integer value="0" /></assign>
+                <for variable="K">
+                    <from><integer value="0" /></from>
+                    <to><integer value="4" /></to>
+                    <step><integer value="1" /></step>
+                    <do><assign variable="sum"><add><value variable="sum" /><value variable="K" /></add></assign></do>
+                </for>
+                <each variable="K">
+                    <array><integer value="10" /></array>
+                    <do><assign variable="sum"><add><value variable="sum" /><value variable="K" /></add></assign></do>
+                </each>
+                <handle>
+                    <try><throw><integer value="100" /></throw></try>
+                    <catch variable="K"><assign variable="sum"><add><value variable="sum" /><value variable="K" /></add></assign></catch>
+                </handle>
+                <return><value variable="sum" /></return>
+            </body>
+        </function>
+        <main>
+            <call function="print-line"><arguments><call function="argument"><arguments><integer value="2" /></arguments></call></arguments></call>
+            <call function="print-line"><arguments><call function="loops"><arguments /></call></arguments></call>
+            <call function="print-line"><arguments><value variable="K" /></arguments></call>
+            <assign variable="K"><integer value="3" /></assign>
+            <call function="print-line"><arguments><value variable="K" /></arguments></call>
+        </main>
+    </program>"#;
+    for (mut interpreter, output) in interpreters() {
+        assert_eq!(interpreter.run(program).expect("run"), 0);
+        assert_eq!(output.contents(), "2\n116\n1\n3\n");
+        assert_eq!(interpreter.get("K").expect("K").to_string(), "1");
+    }
+}
+
+#[test]
+fn declarations_again_fail() {
+    for declarations in [
+        r#"<const name="K"><integer value="1" /></const><const name="K"><integer value="2" /></const>"#,
+        r#"<const name="K"><integer value="1" /></const><global name="K"><integer value="2" /></global>"#,
+        r#"<global name="K"><integer value="1" /></global><const name="K"><integer value="2" /></const>"#,
+    ] {
+        let program = format!(
+            r#"<program name="again">{}<main /></program>"#,
+            declarations
+        );
+        for (mut interpreter, _) in interpreters() {
+            assert_eq!(failure(&mut interpreter, &program), "E112", "{}", program);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Embeds the interpreter in a host, which shares variables and
+//! functions with the programs it runs.
+
+use plxml::{Buffer, Error, ErrorKind, Interpreter, Key, Options, Value};
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::rc::Rc;
+
+const LIBRARY: &str = r#"<program name="library">
+    <function name="greet">
+        <arguments><argument name="who" /></arguments>
+        <body>
+            <return><add><string value="hello " /><value variable="who" /></add></return>
+        </body>
+    </function>
+    <function name="twice">
+        <arguments><argument name="f" /><argument name="x" /></arguments>
+        <body>
+            <return>
+                <call><value variable="f" /><arguments><call><value variable="f" /><arguments><value variable="x" /></arguments></call></arguments></call>
+            </return>
+        </body>
+    </function>
+    <main />
+</program>"#;
+
+/// How a value is printed, as values cannot be compared.
+fn shown(value: Option<Value>) -> Option<String> {
+    value.map(|v| v.to_string())
+}
+
+/// Interpreters for every engine.
+fn interpreters() -> Vec<Interpreter> {
+    [false, true]
+        .iter()
+        .map(|bytecode| Options::new().bytecode(*bytecode).interpreter())
+        .collect()
+}
+
+#[test]
+fn registered_functions() {
+    for mut interpreter in interpreters() {
+        let calls = Rc::new(Cell::new(0));
+        let counted = Rc::clone(&calls);
+        interpreter
+            .register("host-add", move |args| {
+                counted.set(counted.get() + 1);
+                let mut sum = 0;
+                for arg in args {
+                    sum += i64::try_from(arg)?;
+                }
+                Ok(Value::Integer(sum))
+            })
+            .expect("register");
+        let program = r#"<program name="host">
+            <main>
+                <call function="exit">
+                    <arguments>
+                        <call function="host-add">
+                            <arguments>
+                                <integer value="40" />
+                                <call function="host-add"><arguments><integer value="2" /></arguments></call>
+                            </arguments>
+                        </call>
+                    </arguments>
+                </call>
+            </main>
+        </program>"#;
+        assert_eq!(interpreter.run(program).expect("run"), 42);
+        assert_eq!(calls.get(), 2);
+
+        // errors of host functions reach the program, which may catch them
+        let program = r#"<program name="host">
+            <main>
+                <handle>
+                    <try>
+                        <call function="host-add"><arguments><string value="x" /></arguments></call>
+                    </try>
+                    <catch type="E204">
+                        <call function="exit"><arguments><integer value="3" /></arguments></call>
+                    </catch>
+                </handle>
+            </main>
+        </program>"#;
+        assert_eq!(interpreter.run(program).expect("run"), 3);
+        assert_eq!(calls.get(), 3);
+    }
+}
+
+#[test]
+fn global_variables() {
+    for mut interpreter in interpreters() {
+        let output = Buffer::new();
+        interpreter.output(output.clone());
+        interpreter.set("limit", 7).expect("set");
+        interpreter.set("name", "host").expect("set");
+        assert_eq!(shown(interpreter.get("limit")), Some(String::from("7")));
+        assert_eq!(shown(interpreter.get("name")), Some(String::from("host")));
+        assert!(interpreter.get("missing").is_none());
+        let program = r#"<program name="globals">
+            <main>
+                <call function="print-line"><arguments><value variable="name" /></arguments></call>
+                <call function="exit"><arguments><value variable="limit" /></arguments></call>
+            </main>
+        </program>"#;
+        assert_eq!(interpreter.run(program).expect("run"), 7);
+        interpreter.set("limit", 1).expect("set");
+        interpreter.set("name", Value::Null).expect("set");
+        assert_eq!(interpreter.run(program).expect("run"), 1);
+        assert_eq!(output.contents(), "host\nnull\n");
+    }
+}
+
+#[test]
+fn calling_functions() {
+    for mut interpreter in interpreters() {
+        interpreter.load(LIBRARY).expect("load");
+        match interpreter.call("greet", vec![Value::from("host")]) {
+            Ok(Value::String(s)) => assert_eq!(s, "hello host"),
+            other => panic!("greet gave {:?}", other),
+        }
+        // functions of programs may be given functions of the host
+        interpreter
+            .register("increment", |args| match args.as_slice() {
+                [Value::Integer(i)] => Ok(Value::Integer(i + 1)),
+                _ => Err(Error::new(ErrorKind::InvalidArgument(
+                    "increment",
+                    "integer",
+                ))),
+            })
+            .expect("register");
+        let increment = interpreter.get("increment").expect("increment");
+        match interpreter.call("twice", vec![increment, Value::Integer(1)]) {
+            Ok(Value::Integer(i)) => assert_eq!(i, 3),
+            other => panic!("twice gave {:?}", other),
+        }
+        match interpreter.call("array-length", vec![Value::from(vec![1, 2, 3])]) {
+            Ok(Value::Integer(i)) => assert_eq!(i, 3),
+            other => panic!("array-length gave {:?}", other),
+        }
+
+        let error = interpreter
+            .call("nowhere", Vec::new())
+            .expect_err("nowhere");
+        assert_eq!(error.code(), "E400");
+        let error = interpreter.call("greet", Vec::new()).expect_err("arity");
+        assert_eq!(error.code(), "E300");
+        interpreter.set("number", 1).expect("set");
+        let error = interpreter.call("number", Vec::new()).expect_err("number");
+        assert_eq!(error.code(), "E201");
+    }
+}
+
+#[test]
+fn value_conversions() {
+    assert!(bool::try_from(Value::from(true)).expect("bool"));
+    assert_eq!(i64::try_from(Value::from(-4)).expect("integer"), -4);
+    assert_eq!(f64::try_from(Value::from(1.5)).expect("real"), 1.5);
+    // integers are taken as reals, as in arithmetic
+    assert_eq!(f64::try_from(Value::from(2)).expect("real"), 2.0);
+    assert_eq!(
+        String::try_from(Value::from(String::from("text"))).expect("string"),
+        "text"
+    );
+    assert!(matches!(Value::from(None::<i64>), Value::Null));
+    assert_eq!(
+        shown(Some(Value::from(Some("some")))),
+        Some(String::from("some"))
+    );
+
+    let array = Vec::<Value>::try_from(Value::from(vec!["a", "b"])).expect("array");
+    let array: Vec<String> = array.iter().map(Value::to_string).collect();
+    assert_eq!(array, ["a", "b"]);
+
+    let mut map = BTreeMap::new();
+    map.insert(Key::from("one"), 1);
+    map.insert(Key::from(2), 2);
+    let map = BTreeMap::<Key, Value>::try_from(Value::from(map)).expect("map");
+    assert!(matches!(
+        map.get(&Key::from("one")),
+        Some(Value::Integer(1))
+    ));
+    assert!(matches!(map.get(&Key::from(2)), Some(Value::Integer(2))));
+
+    for error in [
+        i64::try_from(Value::from(1.5)).err(),
+        bool::try_from(Value::Null).err(),
+        String::try_from(Value::from(1)).err(),
+        Vec::<Value>::try_from(Value::from("a")).err(),
+    ] {
+        assert_eq!(error.expect("conversion error").code(), "E204");
+    }
+}
+
+#[test]
+fn values_shared_with_programs() {
+    let output = Buffer::new();
+    let mut interpreter = Interpreter::new();
+    interpreter.output(output.clone());
+    let items = Value::from(vec![1, 2]);
+    interpreter.set("items", items.clone()).expect("set");
+    let program = r#"<program name="shared">
+        <main>
+            <call function="array-push"><arguments><value variable="items" /><integer value="3" /></arguments></call>
+            <call function="print-line"><arguments><call function="array-get"><arguments><value variable="items" /><integer value="2" /></arguments></call></arguments></call>
+        </main>
+    </program>"#;
+    interpreter.run(program).expect("run");
+    // arrays are shared, so the host sees what the program added
+    assert_eq!(Vec::<Value>::try_from(items).expect("array").len(), 3);
+    assert_eq!(output.contents(), "3\n");
+}
@@ -0,0 +1,334 @@
+//! Runs programs with every engine, with and without optimizations,
+//! which must all give the same output and the same errors.
+
+use plxml::{Buffer, Error, Frame, Interpreter, Options};
+use std::env;
+use std::fs;
+
+/// Engines other than the tree walker, as `(bytecode, optimize)`.
+const ENGINES: [(bool, bool); 3] = [(true, false), (false, true), (true, true)];
+
+/// What a program printed, and its exit status or error.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    output: String,
+    errors: String,
+    result: Result<i32, (String, Vec<Frame>)>,
+}
+
+fn outcome<F>(bytecode: bool, optimize: bool, args: &[String], run: F) -> Outcome
+where
+    F: Fn(&mut Interpreter) -> Result<i32, Error>,
+{
+    let mut options = Options::new();
+    options
+        .bytecode(bytecode)
+        .optimize(optimize)
+        .args(args.to_vec());
+    let (output, errors) = (Buffer::new(), Buffer::new());
+    let mut interpreter = options.interpreter();
+    interpreter
+        .input(Buffer::from("5\n"))
+        .output(output.clone())
+        .error(errors.clone());
+    let result =
+        run(&mut interpreter).map_err(|e| (format!("[{}] {}", e.code(), e), e.trace().to_vec()));
+    Outcome {
+        output: output.contents(),
+        errors: errors.contents(),
+        result,
+    }
+}
+
+/// Runs a program with every engine, giving what they all gave.
+fn same<F>(name: &str, args: &[String], run: F) -> Outcome
+where
+    F: Fn(&mut Interpreter) -> Result<i32, Error>,
+{
+    let expected = outcome(false, false, args, &run);
+    for (bytecode, optimize) in ENGINES {
+        assert_eq!(
+            outcome(bytecode, optimize, args, &run),
+            expected,
+            "{} (bytecode: {}, optimize: {})",
+            name,
+            bytecode,
+            optimize
+        );
+    }
+    expected
+}
+
+fn program(name: &str, program: &str) -> Outcome {
+    same(name, &[], |interpreter| interpreter.run(program))
+}
+
+#[test]
+fn samples() {
+    let copy = env::temp_dir().join(format!("plxml-engines-{}", std::process::id()));
+    let mut samples: Vec<_> = fs::read_dir("sample")
+        .expect("sample directory")
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    samples.sort();
+    for sample in samples {
+        let path = sample.to_str().expect("sample path");
+        let args: Vec<String> = match sample.file_stem().and_then(|s| s.to_str()) {
+            Some("bf.pl") => vec![String::from("++++++++[>++++++++<-]>+.+.+.,.")],
+            Some("cp.pl") => vec![String::from(path), copy.display().to_string()],
+            _ => Vec::new(),
+        };
+        same(path, &args, |interpreter| interpreter.run_file(path));
+    }
+    let _ = fs::remove_file(copy);
+}
+
+#[test]
+fn finally_with_return() {
+    let outcome = program(
+        "finally with return",
+        r#"<program name="finally">
+            <function name="early">
+                <arguments />
+                <body>
+                    <handle>
+                        <try>
+                            <return><string value="try" /></return>
+                        </try>
+                        <finally>
+                            <call function="print-line">
+                                <arguments><string value="finally" /></arguments>
+                            </call>
+                        </finally>
+                    </handle>
+                    <return><string value="after" /></return>
+                </body>
+            </function>
+            <function name="override">
+                <arguments />
+                <body>
+                    <handle>
+                        <try>
+                            <throw><string value="thrown" /></throw>
+                        </try>
+                        <catch variable="e">
+                            <return><value variable="e" /></return>
+                        </catch>
+                        <finally>
+                            <return><string value="finally" /></return>
+                        </finally>
+                    </handle>
+                </body>
+            </function>
+            <main>
+                <call function="print-line">
+                    <arguments><call function="early"><arguments /></call></arguments>
+                </call>
+                <call function="print-line">
+                    <arguments><call function="override"><arguments /></call></arguments>
+                </call>
+            </main>
+        </program>"#,
+    );
+    assert_eq!(outcome.output, "finally\ntry\nfinally\n");
+}
+
+#[test]
+fn nested_loops() {
+    let outcome = program(
+        "nested loops",
+        r#"<program name="loops">
+            <main>
+                <for variable="i" label="outer">
+                    <from><integer value="0" /></from>
+                    <to><integer value="4" /></to>
+                    <step><integer value="1" /></step>
+                    <do>
+                        <each variable="j">
+                            <array>
+                                <integer value="0" />
+                                <integer value="1" />
+                                <integer value="2" />
+                            </array>
+                            <do>
+                                <if>
+                                    <equal><value variable="j" /><integer value="1" /></equal>
+                                    <then><continue /></then>
+                                </if>
+                                <if>
+                                    <equal><value variable="i" /><integer value="1" /></equal>
+                                    <then><continue label="outer" /></then>
+                                </if>
+                                <if>
+                                    <equal><value variable="i" /><integer value="3" /></equal>
+                                    <then><break label="outer" /></then>
+                                </if>
+                                <call function="print">
+                                    <arguments>
+                                        <add>
+                                            <value variable="i" />
+                                            <value variable="j" />
+                                            <string value=" " />
+                                        </add>
+                                    </arguments>
+                                </call>
+                            </do>
+                        </each>
+                    </do>
+                </for>
+                <assign variable="n"><integer value="0" /></assign>
+                <while>
+                    <true />
+                    <do>
+                        <assign variable="n">
+                            <add><value variable="n" /><integer value="1" /></add>
+                        </assign>
+                        <if>
+                            <greater><value variable="n" /><integer value="2" /></greater>
+                            <then><break /></then>
+                        </if>
+                    </do>
+                </while>
+                <call function="print-line">
+                    <arguments><value variable="n" /></arguments>
+                </call>
+            </main>
+        </program>"#,
+    );
+    assert_eq!(outcome.output, "00 02 20 22 3\n");
+}
+
+#[test]
+fn closures() {
+    let outcome = program(
+        "closures",
+        r#"<program name="closures">
+            <function name="counter">
+                <arguments />
+                <body>
+                    <assign variable="count"><integer value="0" /></assign>
+                    <return>
+                        <lambda>
+                            <arguments />
+                            <body>
+                                <assign variable="count" scope="outer">
+                                    <add><value variable="count" /><integer value="1" /></add>
+                                </assign>
+                                <return><value variable="count" /></return>
+                            </body>
+                        </lambda>
+                    </return>
+                </body>
+            </function>
+            <function name="adder">
+                <arguments><argument name="n" /></arguments>
+                <body>
+                    <return>
+                        <lambda>
+                            <arguments><argument name="x" /></arguments>
+                            <body>
+                                <return><add><value variable="x" /><value variable="n" /></add></return>
+                            </body>
+                        </lambda>
+                    </return>
+                </body>
+            </function>
+            <main>
+                <assign variable="first"><call function="counter"><arguments /></call></assign>
+                <assign variable="second"><call function="counter"><arguments /></call></assign>
+                <call><value variable="first" /><arguments /></call>
+                <call function="print-line">
+                    <arguments>
+                        <add>
+                            <call><value variable="first" /><arguments /></call>
+                            <string value=" " />
+                            <call><value variable="second" /><arguments /></call>
+                            <string value=" " />
+                            <call>
+                                <call function="adder"><arguments><integer value="40" /></arguments></call>
+                                <arguments><integer value="2" /></arguments>
+                            </call>
+                        </add>
+                    </arguments>
+                </call>
+            </main>
+        </program>"#,
+    );
+    assert_eq!(outcome.output, "2 1 42\n");
+}
+
+#[test]
+fn catch_by_type() {
+    let outcome = program(
+        "catch by type",
+        r#"<program name="catch">
+            <function name="fail">
+                <arguments><argument name="kind" /></arguments>
+                <body>
+                    <if>
+                        <equal><value variable="kind" /><string value="thrown" /></equal>
+                        <then><throw type="custom"><string value="custom value" /></throw></then>
+                    </if>
+                    <if>
+                        <equal><value variable="kind" /><string value="type" /></equal>
+                        <then><integer><string value="nan" /></integer></then>
+                    </if>
+                    <return><value variable="undefined" /></return>
+                </body>
+            </function>
+            <main>
+                <each variable="kind">
+                    <array>
+                        <string value="thrown" />
+                        <string value="type" />
+                        <string value="unknown" />
+                    </array>
+                    <do>
+                        <handle>
+                            <try>
+                                <handle>
+                                    <try>
+                                        <call function="fail">
+                                            <arguments><value variable="kind" /></arguments>
+                                        </call>
+                                    </try>
+                                    <catch type="custom" variable="e">
+                                        <call function="print-line">
+                                            <arguments><add><string value="custom: " /><value variable="e" /></add></arguments>
+                                        </call>
+                                    </catch>
+                                    <catch type="E200">
+                                        <call function="print-line">
+                                            <arguments><string value="invalid number" /></arguments>
+                                        </call>
+                                    </catch>
+                                    <finally>
+                                        <call function="print-line">
+                                            <arguments><string value="inner finally" /></arguments>
+                                        </call>
+                                    </finally>
+                                </handle>
+                            </try>
+                            <catch type="unknown-variable" variable="e">
+                                <call function="print-line">
+                                    <arguments><value variable="e" /></arguments>
+                                </call>
+                            </catch>
+                        </handle>
+                    </do>
+                </each>
+                <call function="fail">
+                    <arguments><string value="thrown" /></arguments>
+                </call>
+            </main>
+        </program>"#,
+    );
+    assert_eq!(
+        outcome.output,
+        "custom: custom value\ninner finally\ninvalid number\ninner finally\n\
+         inner finally\nunknown variable 'undefined' at line 13, column 29\n"
+    );
+    let (error, trace) = outcome.result.expect_err("uncaught error");
+    assert!(error.starts_with("[E600]"), "{}", error);
+    assert_eq!(trace.len(), 1);
+}
@@ -0,0 +1,185 @@
+//! Imports programs into others, under a prefix, from paths relative
+//! to the importing program.
+
+use plxml::{Buffer, Interpreter, Options};
+use std::fs;
+use std::path::PathBuf;
+
+/// Prints when loaded, and shouts through a function of its own.
+const STRINGS: &str = r#"<program name="strings">
+    <global name="loaded">
+        <call function="print-line"><arguments><string value="loading strings" /></arguments></call>
+    </global>
+    <function name="exclaim">
+        <arguments><argument name="s" /></arguments>
+        <body><return><add><value variable="s" /><string value="!" /></add></return></body>
+    </function>
+    <function name="shout">
+        <arguments><argument name="s" /></arguments>
+        <body><return><call function="exclaim"><arguments><call function="exclaim"><arguments><value variable="s" /></arguments></call></arguments></call></return></body>
+    </function>
+    <main />
+</program>"#;
+
+/// Directory of programs, removed once the test is over.
+struct Dir(PathBuf);
+
+impl Dir {
+    fn new(name: &str, files: &[(&str, &str)]) -> Dir {
+        let dir =
+            std::env::temp_dir().join(format!("plxml-imports-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        for (file, program) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().expect("parent")).expect("directory");
+            fs::write(path, program).expect("program");
+        }
+        Dir(dir)
+    }
+
+    fn path(&self, file: &str) -> String {
+        self.0.join(file).to_str().expect("path").to_string()
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Interpreters for every engine, with the buffer they print to.
+fn interpreters() -> Vec<(Interpreter, Buffer)> {
+    [false, true]
+        .iter()
+        .map(|bytecode| {
+            let output = Buffer::new();
+            let mut interpreter = Options::new().bytecode(*bytecode).interpreter();
+            interpreter.output(output.clone());
+            (interpreter, output)
+        })
+        .collect()
+}
+
+/// Prints what a function gives for `hi`.
+fn printing(function: &str) -> String {
+    format!(
+        r#"<call function="print-line"><arguments><call function="{}"><arguments><string value="hi" /></arguments></call></arguments></call>"#,
+        function
+    )
+}
+
+#[test]
+fn imported_names() {
+    let main = format!(
+        r#"<program name="main">
+            <import src="lib/strings.pl.xml" as="str" />
+            <import src="lib/strings.pl.xml" />
+            <main>{}{}</main>
+        </program>"#,
+        printing("str.shout"),
+        printing("strings.exclaim"),
+    );
+    let unprefixed = format!(
+        r#"<program name="unprefixed">
+            <import src="lib/strings.pl.xml" />
+            <main>{}</main>
+        </program>"#,
+        printing("shout"),
+    );
+    let dir = Dir::new(
+        "names",
+        &[
+            ("main.pl.xml", &main),
+            ("unprefixed.pl.xml", &unprefixed),
+            ("lib/strings.pl.xml", STRINGS),
+        ],
+    );
+    for (mut interpreter, output) in interpreters() {
+        assert_eq!(
+            interpreter.run_file(&dir.path("main.pl.xml")).expect("run"),
+            0
+        );
+        assert_eq!(output.contents(), "loading strings\nhi!!\nhi!\n");
+        // imported functions are only known under their prefix
+        let error = interpreter
+            .run_file(&dir.path("unprefixed.pl.xml"))
+            .expect_err("unprefixed");
+        assert_eq!(error.code(), "E400");
+    }
+}
+
+#[test]
+fn imported_once() {
+    // both import the same program, relative to themselves
+    let upper = r#"<program name="upper">
+        <import src="../strings.pl.xml" />
+        <function name="up">
+            <arguments><argument name="s" /></arguments>
+            <body><return><call function="strings.shout"><arguments><value variable="s" /></arguments></call></return></body>
+        </function>
+        <main />
+    </program>"#;
+    let main = format!(
+        r#"<program name="main">
+            <import src="lib/strings.pl.xml" />
+            <import src="lib/upper/upper.pl.xml" />
+            <main>{}{}</main>
+        </program>"#,
+        printing("strings.shout"),
+        printing("upper.up"),
+    );
+    let dir = Dir::new(
+        "once",
+        &[
+            ("main.pl.xml", &main),
+            ("lib/strings.pl.xml", STRINGS),
+            ("lib/upper/upper.pl.xml", upper),
+        ],
+    );
+    for (mut interpreter, output) in interpreters() {
+        assert_eq!(
+            interpreter.run_file(&dir.path("main.pl.xml")).expect("run"),
+            0
+        );
+        // and are kept for the next runs
+        assert_eq!(
+            interpreter.run_file(&dir.path("main.pl.xml")).expect("run"),
+            0
+        );
+        assert_eq!(
+            output.contents(),
+            "loading strings\nhi!!\nhi!!\nhi!!\nhi!!\n"
+        );
+    }
+}
+
+#[test]
+fn cyclic_imports() {
+    let first = r#"<program name="first">
+        <import src="second.pl.xml" />
+        <main />
+    </program>"#;
+    let second = r#"<program name="second">
+        <import src="first.pl.xml" />
+        <main />
+    </program>"#;
+    let itself = r#"<program name="itself">
+        <import src="itself.pl.xml" />
+        <main />
+    </program>"#;
+    let dir = Dir::new(
+        "cycles",
+        &[
+            ("first.pl.xml", first),
+            ("second.pl.xml", second),
+            ("itself.pl.xml", itself),
+        ],
+    );
+    for (mut interpreter, _) in interpreters() {
+        for file in ["first.pl.xml", "itself.pl.xml"] {
+            let error = interpreter.run_file(&dir.path(file)).expect_err(file);
+            assert_eq!(error.code(), "E111", "{}", file);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Stops programs exceeding the limits of their sandbox with errors,
+//! which programs may handle like any other.
+
+use plxml::{Buffer, Interpreter, Options, Sandbox};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Recurses without end, printing `finally` on the way out.
+const RECURSIVE: &str = r#"<program name="recursive">
+    <function name="down">
+        <arguments><argument name="n" /></arguments>
+        <body>
+            <call function="down"><arguments><add><value variable="n" /><integer value="1" /></add></arguments></call>
+        </body>
+    </function>
+    <main>
+        <handle>
+            <try><call function="down"><arguments><integer value="0" /></arguments></call></try>
+            <finally><call function="print"><arguments><string value="finally" /></arguments></call></finally>
+        </handle>
+    </main>
+</program>"#;
+
+/// Runs a program on every engine, giving what it printed and the code
+/// of its error if any, which must be the same for all.
+fn run(sandbox: &Sandbox, program: &str) -> (String, Option<&'static str>) {
+    let mut results = [false, true].iter().map(|bytecode| {
+        let output = Buffer::new();
+        let mut options = Options::new();
+        options.bytecode(*bytecode).sandbox(sandbox.clone());
+        let mut interpreter = options.interpreter();
+        interpreter.output(output.clone());
+        let error = interpreter.run(program).err().map(|e| e.code());
+        (output.contents(), error)
+    });
+    let result = results.next().expect("tree walker");
+    assert_eq!(results.next(), Some(result.clone()), "{}", program);
+    result
+}
+
+#[test]
+fn exceeded_limits_are_caught() {
+    let growing = r#"<program name="growing">
+        <main>
+            <assign variable="s"><string value="" /></assign>
+            <handle>
+                <try>
+                    <while>
+                        <true />
+                        <do><assign variable="s"><add><value variable="s" /><string value="ab" /></add></assign></do>
+                    </while>
+                </try>
+                <catch type="E900"><call function="print"><arguments><string value="caught " /></arguments></call></catch>
+                <finally><call function="print"><arguments><string value="finally" /></arguments></call></finally>
+            </handle>
+        </main>
+    </program>"#;
+    let mut sandbox = Sandbox::new();
+    sandbox.size_limit(100);
+    assert_eq!(
+        run(&sandbox, growing),
+        (String::from("caught finally"), None)
+    );
+
+    // nothing more may be printed, but the handler still runs
+    let printing = r#"<program name="printing">
+        <main>
+            <handle>
+                <try>
+                    <while>
+                        <true />
+                        <do><call function="print"><arguments><string value="0123456789" /></arguments></call></do>
+                    </while>
+                </try>
+                <catch type="denied"><call function="exit"><arguments><integer value="3" /></arguments></call></catch>
+            </handle>
+        </main>
+    </program>"#;
+    for bytecode in [false, true] {
+        let mut sandbox = Sandbox::new();
+        sandbox.output_limit(100);
+        let mut options = Options::new();
+        options.bytecode(bytecode).sandbox(sandbox);
+        let mut interpreter = options.interpreter();
+        interpreter.output(Buffer::new());
+        assert_eq!(interpreter.run(printing).expect("run"), 3);
+    }
+
+    let mut sandbox = Sandbox::new();
+    sandbox.depth_limit(50);
+    let catching = RECURSIVE.replace(
+        "<finally>",
+        r#"<catch type="limit"><call function="print"><arguments><string value="caught " /></arguments></call></catch><finally>"#,
+    );
+    assert_eq!(
+        run(&sandbox, &catching),
+        (String::from("caught finally"), None)
+    );
+}
+
+#[test]
+fn finally_runs_on_exceeded_limits() {
+    let mut sandbox = Sandbox::new();
+    sandbox.depth_limit(50);
+    assert_eq!(
+        run(&sandbox, RECURSIVE),
+        (String::from("finally"), Some("E900"))
+    );
+}
+
+#[test]
+fn exit_skips_finally() {
+    let program = r#"<program name="exit">
+        <main>
+            <handle>
+                <try><call function="exit"><arguments><integer value="5" /></arguments></call></try>
+                <catch><call function="print"><arguments><string value="caught" /></arguments></call></catch>
+                <finally><call function="print"><arguments><string value="finally" /></arguments></call></finally>
+            </handle>
+        </main>
+    </program>"#;
+    for bytecode in [false, true] {
+        let output = Buffer::new();
+        let mut interpreter = Options::new().bytecode(bytecode).interpreter();
+        interpreter.output(output.clone());
+        assert_eq!(interpreter.run(program).expect("run"), 5);
+        assert_eq!(output.contents(), "");
+    }
+}
+
+#[test]
+fn recursion_is_limited_by_default() {
+    // the stack the `plxml` command runs programs on
+    let runner = thread::Builder::new().stack_size(64 << 20);
+    let outputs = runner
+        .spawn(|| {
+            let mut outputs = Vec::new();
+            for mut interpreter in [
+                Interpreter::new(),
+                Options::new().bytecode(true).interpreter(),
+            ] {
+                let output = Buffer::new();
+                interpreter.output(output.clone());
+                let error = interpreter.run(RECURSIVE).expect_err("recursion");
+                outputs.push((output.contents(), error.code()));
+            }
+            outputs
+        })
+        .expect("thread")
+        .join()
+        .expect("no overflow");
+    for output in outputs {
+        assert_eq!(output, (String::from("finally"), "E900"));
+    }
+
+    for engine in ["--optimize", "--bytecode"] {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_plxml"))
+            .args(["run", engine, "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("plxml");
+        child
+            .stdin
+            .take()
+            .expect("stdin")
+            .write_all(RECURSIVE.as_bytes())
+            .expect("input");
+        let output = child.wait_with_output().expect("output");
+        assert_eq!(output.status.code(), Some(1), "{}", engine);
+        assert_eq!(output.stdout, b"finally");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.starts_with("Error occurred: [E900]"), "{}", stderr);
+    }
+}
@@ -0,0 +1,124 @@
+//! Drives interactive sessions, both through the library and through
+//! the `plxml repl` command.
+
+use plxml::repl::{complete, Session};
+use plxml::Value;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[test]
+fn unfinished_fragments() {
+    for fragment in [
+        "<assign variable=\"x\">\n",
+        "<assign variable=\"x\">\n<integer value=\"4\" />\n",
+        "<integer",
+        "<integer value=\"4\"",
+        "<integer value=\"4\" ",
+        "<integer value=\"4",
+        "<add><integer value=\"1\" />",
+        "<!-- still writing",
+        "<string><![CDATA[ <not> ",
+    ] {
+        assert!(!complete(fragment), "{:?}", fragment);
+    }
+}
+
+#[test]
+fn finished_fragments() {
+    for fragment in [
+        "<integer value=\"4\" />\n",
+        "<assign variable=\"x\">\n<integer value=\"4\" />\n</assign>\n",
+        "<string><![CDATA[ <not> ]]></string>",
+        "<!-- <add> --><true />",
+        "<true /><false />",
+    ] {
+        assert!(complete(fragment), "{:?}", fragment);
+    }
+}
+
+#[test]
+fn malformed_fragments() {
+    for fragment in [
+        "<bad</add>",
+        "<add></subtract>",
+        "<true /></add>",
+        "<integer value=\"4\" value=\"5\" />",
+    ] {
+        assert!(complete(fragment), "{:?}", fragment);
+        let error = Session::new().eval(fragment).expect_err(fragment);
+        assert_eq!(error.code(), "E100", "{:?}", fragment);
+    }
+}
+
+#[test]
+fn session_keeps_variables() {
+    let mut session = Session::new();
+    assert!(session
+        .eval("<assign variable=\"x\"><integer value=\"4\" /></assign>")
+        .is_ok());
+    match session.eval("<add><value variable=\"x\" /><integer value=\"2\" /></add>") {
+        Ok(Some(Value::Integer(6))) => {}
+        other => panic!("add gave {:?}", other),
+    }
+    session.reset();
+    let error = session.eval("<value variable=\"x\" />").expect_err("reset");
+    assert_eq!(error.code(), "E400");
+}
+
+/// Output of a session reading the given lines.
+fn repl(input: &str) -> String {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_plxml"))
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("plxml repl");
+    child
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(input.as_bytes())
+        .expect("input");
+    let output = child.wait_with_output().expect("output");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout)
+        .expect("utf-8")
+        .replace("plxml> ", "")
+        .replace("  ...> ", "")
+}
+
+#[test]
+fn malformed_input_is_reported() {
+    let output = repl("<bad</add>\n<integer value=\"1\" />\n");
+    assert_eq!(output, "1\n");
+}
+
+#[test]
+fn blank_line_discards_pending_input() {
+    let output = repl("<add>\n<integer value=\"1\" />\n\n<integer value=\"2\" />\n");
+    assert_eq!(output, "2\n");
+}
+
+#[test]
+fn reset_discards_pending_input() {
+    let output = repl(concat!(
+        "<assign variable=\"x\"><integer value=\"1\" /></assign>\n",
+        "<add>\n",
+        ":reset\n",
+        "<integer value=\"2\" />\n",
+        ":vars\n",
+    ));
+    assert_eq!(output, "2\n");
+}
+
+#[test]
+fn commands_keep_pending_input() {
+    let output = repl(concat!(
+        "<assign variable=\"x\"><integer value=\"1\" /></assign>\n",
+        "<add>\n",
+        ":vars\n",
+        "<value variable=\"x\" /><integer value=\"2\" /></add>\n",
+    ));
+    assert_eq!(output, "x = 1\n3\n");
+}
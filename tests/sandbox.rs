@@ -0,0 +1,286 @@
+//! Runs programs in sandboxes, which must deny what they restrict
+//! with errors rather than let programs through.
+
+use plxml::{Buffer, Options, Sandbox};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Calls a function on arguments, printing what it gives.
+fn program(function: &str, args: &[&str]) -> String {
+    let args: String = args
+        .iter()
+        .map(|a| format!("<string value=\"{}\" />", a))
+        .collect();
+    format!(
+        r#"<program name="sandbox">
+            <main>
+                <call function="print">
+                    <arguments><call function="{}"><arguments>{}</arguments></call></arguments>
+                </call>
+            </main>
+        </program>"#,
+        function, args
+    )
+}
+
+/// Runs a program on every engine, giving what it printed or the code
+/// of its error, which must be the same for all.
+fn run(sandbox: &Sandbox, program: &str) -> Result<String, &'static str> {
+    let mut results = [false, true].iter().map(|bytecode| {
+        let output = Buffer::new();
+        let mut options = Options::new();
+        options
+            .bytecode(*bytecode)
+            .args(vec![String::from("arg")])
+            .sandbox(sandbox.clone());
+        let mut interpreter = options.interpreter();
+        interpreter.output(output.clone());
+        match interpreter.run(program) {
+            Ok(_) => Ok(output.contents()),
+            Err(e) => Err(e.code()),
+        }
+    });
+    let result = results.next().expect("tree walker");
+    assert_eq!(results.next(), Some(result.clone()), "{}", program);
+    result
+}
+
+/// Directory removed once the test is over, with a `root` directory
+/// to sandbox programs in and an `outside` one next to it.
+struct Dir(PathBuf);
+
+impl Dir {
+    fn new(name: &str) -> Dir {
+        let dir =
+            std::env::temp_dir().join(format!("plxml-sandbox-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("root").join("sub")).expect("root");
+        fs::create_dir_all(dir.join("outside")).expect("outside");
+        fs::write(dir.join("root").join("inside.txt"), "inside").expect("inside");
+        fs::write(dir.join("outside").join("secret.txt"), "secret").expect("secret");
+        Dir(dir)
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.0.join(path)
+    }
+
+    fn sandbox(&self) -> Sandbox {
+        let mut sandbox = Sandbox::new();
+        sandbox.root(self.path("root"));
+        sandbox
+    }
+}
+
+impl Drop for Dir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().expect("path")
+}
+
+#[test]
+fn denied_files_and_arguments() {
+    let dir = Dir::new("denied");
+    let inside = dir.path("root/inside.txt");
+    assert_eq!(
+        run(&Sandbox::new(), &program("read-file", &[path(&inside)])),
+        Ok(String::from("inside"))
+    );
+    let mut sandbox = Sandbox::new();
+    sandbox.files(false);
+    assert_eq!(
+        run(&sandbox, &program("read-file", &[path(&inside)])),
+        Err("E800")
+    );
+    assert_eq!(
+        run(&sandbox, &program("write-file", &[path(&inside), "x", ""])),
+        Err("E800")
+    );
+    assert_eq!(fs::read_to_string(&inside).expect("inside"), "inside");
+
+    let args = r#"<program name="args">
+        <main>
+            <call function="print">
+                <arguments><call function="array-get"><arguments><call function="get-args"><arguments /></call><integer value="0" /></arguments></call></arguments>
+            </call>
+        </main>
+    </program>"#;
+    assert_eq!(run(&Sandbox::new(), args), Ok(String::from("arg")));
+    let mut sandbox = Sandbox::new();
+    sandbox.args(false);
+    assert_eq!(run(&sandbox, args), Err("E800"));
+}
+
+#[test]
+fn files_below_the_root() {
+    let dir = Dir::new("root");
+    let sandbox = dir.sandbox();
+    assert_eq!(
+        run(&sandbox, &program("read-file", &["inside.txt"])),
+        Ok(String::from("inside"))
+    );
+    assert_eq!(
+        run(&sandbox, &program("read-file", &["sub/../inside.txt"])),
+        Ok(String::from("inside"))
+    );
+    assert_eq!(
+        run(
+            &sandbox,
+            &program("write-file", &["sub/new.txt", "new", ""])
+        ),
+        Ok(String::from("null"))
+    );
+    assert_eq!(
+        fs::read_to_string(dir.path("root/sub/new.txt")).expect("new"),
+        "new"
+    );
+    assert_eq!(
+        run(&sandbox, &program("read-file", &["missing.txt"])),
+        Err("E500")
+    );
+}
+
+#[test]
+fn files_outside_of_the_root() {
+    let dir = Dir::new("outside");
+    let sandbox = dir.sandbox();
+    let secret = dir.path("outside/secret.txt");
+    for file in [
+        "../outside/secret.txt",
+        "sub/../../outside/secret.txt",
+        path(&secret),
+    ] {
+        assert_eq!(
+            run(&sandbox, &program("read-file", &[file])),
+            Err("E800"),
+            "{}",
+            file
+        );
+        assert_eq!(
+            run(&sandbox, &program("write-file", &[file, "x", ""])),
+            Err("E800"),
+            "{}",
+            file
+        );
+    }
+    assert_eq!(
+        run(
+            &sandbox,
+            &program("write-file", &["../outside/new.txt", "x", ""])
+        ),
+        Err("E800")
+    );
+    assert_eq!(fs::read_to_string(&secret).expect("secret"), "secret");
+    assert!(!dir.path("outside/new.txt").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn links_out_of_the_root() {
+    use std::os::unix::fs::symlink;
+
+    let dir = Dir::new("links");
+    let sandbox = dir.sandbox();
+    symlink(dir.path("outside/secret.txt"), dir.path("root/secret.txt")).expect("file link");
+    symlink(dir.path("outside"), dir.path("root/outside")).expect("directory link");
+    // links to files which do not exist yet
+    symlink(
+        dir.path("outside/created.txt"),
+        dir.path("root/dangling.txt"),
+    )
+    .expect("dangling");
+    symlink(dir.path("root/inside.txt"), dir.path("root/sub/link.txt")).expect("inner link");
+
+    for file in ["secret.txt", "outside/secret.txt", "dangling.txt"] {
+        assert_eq!(
+            run(&sandbox, &program("read-file", &[file])),
+            Err("E800"),
+            "{}",
+            file
+        );
+        assert_eq!(
+            run(&sandbox, &program("write-file", &[file, "x", ""])),
+            Err("E800"),
+            "{}",
+            file
+        );
+    }
+    assert_eq!(
+        run(
+            &sandbox,
+            &program("write-file", &["outside/created.txt", "x", ""])
+        ),
+        Err("E800")
+    );
+    assert!(!dir.path("outside/created.txt").exists());
+    assert_eq!(
+        fs::read_to_string(dir.path("outside/secret.txt")).expect("secret"),
+        "secret"
+    );
+    // links staying below the root are followed
+    assert_eq!(
+        run(&sandbox, &program("read-file", &["sub/link.txt"])),
+        Ok(String::from("inside"))
+    );
+}
+
+#[test]
+fn limits() {
+    let looping = r#"<program name="loop">
+        <main>
+            <while><true /><do /></while>
+        </main>
+    </program>"#;
+    let mut sandbox = Sandbox::new();
+    sandbox.step_limit(1000);
+    assert_eq!(run(&sandbox, looping), Err("E900"));
+
+    let recursive = r#"<program name="recursive">
+        <function name="down">
+            <arguments><argument name="n" /></arguments>
+            <body>
+                <if>
+                    <greater><value variable="n" /><integer value="0" /></greater>
+                    <then><call function="down"><arguments><subtract><value variable="n" /><integer value="1" /></subtract></arguments></call></then>
+                </if>
+            </body>
+        </function>
+        <main>
+            <call function="down"><arguments><integer value="20" /></arguments></call>
+        </main>
+    </program>"#;
+    let mut sandbox = Sandbox::new();
+    sandbox.depth_limit(20);
+    assert_eq!(run(&sandbox, recursive), Err("E900"));
+    sandbox.depth_limit(21);
+    assert_eq!(run(&sandbox, recursive), Ok(String::new()));
+
+    let growing = r#"<program name="growing">
+        <main>
+            <assign variable="s"><string value="" /></assign>
+            <while>
+                <true />
+                <do><assign variable="s"><add><value variable="s" /><string value="ab" /></add></assign></do>
+            </while>
+        </main>
+    </program>"#;
+    let mut sandbox = Sandbox::new();
+    sandbox.size_limit(100);
+    assert_eq!(run(&sandbox, growing), Err("E900"));
+
+    let printing = r#"<program name="printing">
+        <main>
+            <while>
+                <true />
+                <do><call function="print"><arguments><string value="0123456789" /></arguments></call></do>
+            </while>
+        </main>
+    </program>"#;
+    let mut sandbox = Sandbox::new();
+    sandbox.output_limit(100);
+    assert_eq!(run(&sandbox, printing), Err("E800"));
+}
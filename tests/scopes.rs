@@ -0,0 +1,108 @@
+//! Checks that the scopes of function calls are freed once the calls
+//! are over, including when functions are defined in them.
+
+use plxml::{Options, Value};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Calls a function defining a nested function and a lambda, both
+/// kept in its scope along with the `probe` array of the host.
+const PROGRAM: &str = r#"<program name="scopes">
+    <function name="make">
+        <arguments><argument name="n" /></arguments>
+        <body>
+            <assign variable="held"><value variable="probe" /></assign>
+            <function name="inner">
+                <arguments />
+                <body><return><value variable="n" /></return></body>
+            </function>
+            <assign variable="twice">
+                <lambda>
+                    <arguments />
+                    <body>
+                        <return>
+                            <multiply>
+                                <call function="inner"><arguments /></call>
+                                <integer value="2" />
+                            </multiply>
+                        </return>
+                    </body>
+                </lambda>
+            </assign>
+            <return><call><value variable="twice" /><arguments /></call></return>
+        </body>
+    </function>
+    <main>
+        <assign variable="sum"><integer value="0" /></assign>
+        <for variable="i">
+            <from><integer value="0" /></from>
+            <to><integer value="100" /></to>
+            <step><integer value="1" /></step>
+            <do>
+                <assign variable="sum">
+                    <add>
+                        <value variable="sum" />
+                        <call function="make"><arguments><value variable="i" /></arguments></call>
+                    </add>
+                </assign>
+            </do>
+        </for>
+        <call function="exit"><arguments><integer><modulo><value variable="sum" /><integer value="256" /></modulo></integer></arguments></call>
+    </main>
+</program>"#;
+
+#[test]
+fn calls_defining_functions_are_freed() {
+    for bytecode in [false, true] {
+        let probe = Rc::new(RefCell::new(Vec::new()));
+        let mut options = Options::new();
+        options.bytecode(bytecode);
+        let mut interpreter = options.interpreter();
+        interpreter
+            .set("probe", Value::Array(Rc::clone(&probe)))
+            .expect("set");
+        // the sum of twice 0 to 99 is 9900
+        assert_eq!(interpreter.run(PROGRAM).expect("run"), 9900 % 256);
+        // only the host and the interpreter still hold the probe
+        assert_eq!(Rc::strong_count(&probe), 2, "bytecode: {}", bytecode);
+        drop(interpreter);
+        assert_eq!(Rc::strong_count(&probe), 1, "bytecode: {}", bytecode);
+    }
+}
+
+#[test]
+fn returned_functions_keep_their_scope() {
+    let program = r#"<program name="counter">
+        <function name="counter">
+            <arguments />
+            <body>
+                <assign variable="count"><integer value="0" /></assign>
+                <function name="next">
+                    <arguments />
+                    <body>
+                        <assign variable="count" scope="outer">
+                            <add><value variable="count" /><integer value="1" /></add>
+                        </assign>
+                        <return><value variable="count" /></return>
+                    </body>
+                </function>
+                <return><value variable="next" /></return>
+            </body>
+        </function>
+        <main />
+    </program>"#;
+    for bytecode in [false, true] {
+        let mut options = Options::new();
+        options.bytecode(bytecode);
+        let mut interpreter = options.interpreter();
+        interpreter.load(program).expect("load");
+        let next = interpreter.call("counter", Vec::new()).expect("counter");
+        interpreter.set("next", next).expect("set");
+        for expected in 1..=3 {
+            match interpreter.call("next", Vec::new()) {
+                Ok(Value::Integer(count)) => assert_eq!(count, expected),
+                other => panic!("next gave {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! Calls functions of the standard library from the host.
+
+use plxml::{Interpreter, Value};
+
+#[test]
+fn ascii_round_trip() {
+    let interpreter = Interpreter::new();
+    for code in 0..=127 {
+        let character = interpreter
+            .call("to-ascii", vec![Value::Integer(code)])
+            .expect("to-ascii");
+        match &character {
+            Value::String(s) => assert_eq!(s.chars().count(), 1, "{}", code),
+            other => panic!("to-ascii gave {:?}", other),
+        }
+        match interpreter.call("from-ascii", vec![character]) {
+            Ok(Value::Integer(back)) => assert_eq!(back, code),
+            other => panic!("from-ascii gave {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn ascii_rejects_other_codes() {
+    let interpreter = Interpreter::new();
+    for code in [-1, 128, 255, 256] {
+        let error = interpreter
+            .call("to-ascii", vec![Value::Integer(code)])
+            .expect_err("code outside of ASCII");
+        assert_eq!(error.code(), "E203", "{}", code);
+    }
+    let error = interpreter
+        .call("from-ascii", vec![Value::from("é")])
+        .expect_err("character outside of ASCII");
+    assert_eq!(error.code(), "E203");
+}
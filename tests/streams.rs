@@ -0,0 +1,70 @@
+//! Gives programs streams of their own, to feed their input and
+//! capture what they print.
+
+use plxml::{Buffer, Interpreter, Options};
+use std::thread;
+
+/// Reads lines until an empty one, echoing them to the output and
+/// counting them on the error stream.
+const ECHO: &str = r#"<program name="echo">
+    <main>
+        <assign variable="count"><integer value="0" /></assign>
+        <assign variable="line"><call function="input"><arguments /></call></assign>
+        <while>
+            <not><equal><value variable="line" /><string value="" /></equal></not>
+            <do>
+                <call function="print-line"><arguments><value variable="line" /></arguments></call>
+                <assign variable="count"><add><value variable="count" /><integer value="1" /></add></assign>
+                <assign variable="line"><call function="input"><arguments /></call></assign>
+            </do>
+        </while>
+        <call function="print-error"><arguments><value variable="count" /></arguments></call>
+    </main>
+</program>"#;
+
+/// An interpreter reading `input`, with the buffers it prints to.
+fn interpreter(bytecode: bool, input: &str) -> (Interpreter, Buffer, Buffer) {
+    let (output, error) = (Buffer::new(), Buffer::new());
+    let mut interpreter = Options::new().bytecode(bytecode).interpreter();
+    interpreter
+        .input(Buffer::from(input))
+        .output(output.clone())
+        .error(error.clone());
+    (interpreter, output, error)
+}
+
+#[test]
+fn streams_of_each_interpreter() {
+    for bytecode in [false, true] {
+        let (mut first, first_output, first_error) = interpreter(bytecode, "a\nb\n\nc\n\n");
+        let (mut second, second_output, second_error) = interpreter(bytecode, "x\n\n");
+        // runs are interleaved, each reading on from where it stopped
+        assert_eq!(first.run(ECHO).expect("first"), 0);
+        assert_eq!(second.run(ECHO).expect("second"), 0);
+        assert_eq!(first.run(ECHO).expect("first again"), 0);
+        assert_eq!(second.run(ECHO).expect("second again"), 0);
+        assert_eq!(first_output.contents(), "a\nb\nc\n");
+        assert_eq!(first_error.contents(), "2\n1\n");
+        assert_eq!(second_output.contents(), "x\n");
+        assert_eq!(second_error.contents(), "1\n0\n");
+    }
+}
+
+#[test]
+fn interpreters_on_several_threads() {
+    let threads: Vec<_> = (0..4)
+        .map(|n| {
+            thread::spawn(move || {
+                let input: String = (0..100).map(|i| format!("{}-{}\n", n, i)).collect();
+                let (mut interpreter, output, error) = interpreter(n % 2 == 1, &input);
+                interpreter.run(ECHO).expect("run");
+                (input, output.contents(), error.contents())
+            })
+        })
+        .collect();
+    for thread in threads {
+        let (input, output, error) = thread.join().expect("thread");
+        assert_eq!(output, input);
+        assert_eq!(error, "100\n");
+    }
+}
@@ -0,0 +1,96 @@
+//! Parses programs in strict mode, which rejects what does not follow
+//! the grammar instead of ignoring it.
+
+use plxml::{schema, Options};
+
+/// Wraps instructions in a program, its main starting on line 2.
+fn program(main: &str) -> String {
+    format!(
+        "<program name=\"strict\">\n<main>\n{}\n</main>\n</program>",
+        main
+    )
+}
+
+/// Code and line of the error of a program run in strict mode, which
+/// is run as well without it to make sure it is otherwise accepted.
+fn rejected(main: &str) -> (&'static str, u32) {
+    let program = program(main);
+    Options::new().run(program.clone()).expect("lenient run");
+    let error = Options::new()
+        .strict(true)
+        .run(program)
+        .expect_err("strict run");
+    (error.code(), error.location().expect("location").line)
+}
+
+#[test]
+fn conforming_programs() {
+    let program = program(
+        r#"<assign variable="x"><integer value="1" /></assign>
+<if>
+    <greater><value variable="x" /><integer value="0" /></greater>
+    <then><call function="exit"><arguments><value variable="x" /></arguments></call></then>
+    <else />
+</if>"#,
+    );
+    assert_eq!(Options::new().strict(true).run(program).expect("run"), 1);
+}
+
+#[test]
+fn unknown_attributes() {
+    assert_eq!(
+        rejected(r#"<assign variable="x" kind="int"><integer value="1" /></assign>"#),
+        ("E108", 3)
+    );
+    assert_eq!(
+        rejected("<assign variable=\"x\">\n<integer value=\"1\" base=\"16\" />\n</assign>"),
+        ("E108", 4)
+    );
+}
+
+#[test]
+fn stray_children() {
+    assert_eq!(
+        rejected(r#"<while><false /><do /><body /></while>"#),
+        ("E109", 3)
+    );
+    assert_eq!(
+        rejected(r#"<assign variable="x"><integer value="1" /><arguments /></assign>"#),
+        ("E109", 3)
+    );
+}
+
+#[test]
+fn extra_elements() {
+    assert_eq!(
+        rejected("<assign variable=\"x\">\n<not><true /><false /></not>\n</assign>"),
+        ("E109", 4)
+    );
+    assert_eq!(
+        rejected(r#"<assign variable="x"><integer value="1" /><integer value="2" /></assign>"#),
+        ("E109", 3)
+    );
+}
+
+#[test]
+fn stray_text() {
+    assert_eq!(
+        rejected(r#"<assign variable="x">one<integer value="1" /></assign>"#),
+        ("E110", 3)
+    );
+}
+
+#[test]
+fn schema_covers_the_grammar() {
+    let schema = schema();
+    assert!(schema.starts_with("<?xml"));
+    assert!(schema.contains(r#"xmlns="http://relaxng.org/ns/structure/1.0""#));
+    for tag in ["program", "main", "assign", "not", "while", "try", "lambda"] {
+        assert!(
+            schema.contains(&format!(r#"<element name="{}">"#, tag)),
+            "no element for {}",
+            tag
+        );
+    }
+    assert!(schema.contains(r#"<attribute name="variable" />"#));
+}